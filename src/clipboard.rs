@@ -0,0 +1,159 @@
+use std::{
+    env,
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+};
+
+// How text reaches the system clipboard for the "+ and "* registers
+#[derive(Clone, PartialEq, Eq)]
+pub enum ClipboardProvider {
+    // escape sequence understood by the terminal, works over ssh but cannot be read back
+    Osc52,
+    // external programs, the first copies from their stdin and the second pastes to their stdout
+    Command {
+        copy: Vec<String>,
+        paste: Vec<String>,
+    },
+}
+
+impl ClipboardProvider {
+    // Known providers by the name of the tool, or a custom `copy cmd | paste cmd` pair
+    pub fn from_name(name: &str) -> Option<Self> {
+        let command = |copy: &str, paste: &str| ClipboardProvider::Command {
+            copy: copy.split_whitespace().map(String::from).collect(),
+            paste: paste.split_whitespace().map(String::from).collect(),
+        };
+        let provider = match name.trim() {
+            "osc52" => ClipboardProvider::Osc52,
+            "xclip" => command(
+                "xclip -in -selection clipboard",
+                "xclip -out -selection clipboard",
+            ),
+            "xsel" => command("xsel --input --clipboard", "xsel --output --clipboard"),
+            "wl-copy" | "wl-clipboard" => command("wl-copy", "wl-paste --no-newline"),
+            "pbcopy" => command("pbcopy", "pbpaste"),
+            custom => {
+                let (copy, paste) = custom.split_once('|')?;
+                command(copy, paste)
+            }
+        };
+        Some(provider)
+    }
+}
+
+pub struct Clipboard {
+    provider: ClipboardProvider,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new(ClipboardProvider::Osc52)
+    }
+}
+
+impl Clipboard {
+    pub fn new(provider: ClipboardProvider) -> Self {
+        Self { provider }
+    }
+
+    // Copies to the clipboard, or the primary selection when primary is set
+    pub fn copy(&self, text: &str, primary: bool) -> io::Result<()> {
+        match &self.provider {
+            ClipboardProvider::Osc52 => {
+                let selection = if primary { 'p' } else { 'c' };
+                let mut sequence = format!("\x1b]52;{};{}\x07", selection, base64(text.as_bytes()));
+                if env::var_os("TMUX").is_some() {
+                    // tmux only passes the sequence on when wrapped
+                    sequence = format!("\x1bPtmux;\x1b{}\x1b\\", sequence);
+                }
+                let mut stdout = io::stdout();
+                stdout.write_all(sequence.as_bytes())?;
+                stdout.flush()
+            }
+            ClipboardProvider::Command { copy, .. } => {
+                // tools like xclip stay behind to serve the selection, away from the screen
+                let mut child = spawn(copy)?
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(text.as_bytes())?;
+                }
+                child.wait()?;
+                Ok(())
+            }
+        }
+    }
+
+    // Reads the clipboard, None when the provider has no way to read it back
+    pub fn paste(&self) -> io::Result<Option<String>> {
+        match &self.provider {
+            ClipboardProvider::Osc52 => Ok(None),
+            ClipboardProvider::Command { paste, .. } => {
+                let mut child = spawn(paste)?.stdout(Stdio::piped()).spawn()?;
+                let mut text = String::new();
+                if let Some(mut stdout) = child.stdout.take() {
+                    stdout.read_to_string(&mut text)?;
+                }
+                child.wait()?;
+                Ok(Some(text))
+            }
+        }
+    }
+}
+
+fn spawn(args: &[String]) -> io::Result<Command> {
+    let Some((program, args)) = args.split_first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "clipboard command is empty",
+        ));
+    };
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    Ok(command)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - i * 6) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64_bytes() {
+        assert_eq!(base64(&[0xff, 0xfe, 0xfd]), "//79");
+        assert_eq!(base64("é\n".as_bytes()), "w6kK");
+    }
+}
@@ -0,0 +1,395 @@
+// Settings read from config.toml at startup, changed with :set and read again with :source
+//
+// tabstop = 8
+// expandtab = false
+// theme = "gruvbox"
+// leader = "<Space>"
+// clipboard = "xclip"       # osc52, xclip, xsel, wl-copy, pbcopy or "copy command | paste command"
+// statusline = "{file} {modified}{=}{mode}{=}{filetype} {position} {percent}"
+//
+// [keys.normal]
+// "<leader>w" = ":w<CR>"                     # keys, not mapped again
+// "<C-s>" = { action = "save" }              # a named action
+// "Y" = { keys = "y$", remap = true }        # keys which mappings apply to
+// [keys.insert]
+// "jk" = "<Esc>"
+
+use std::{env, fmt::Display, fs, path::PathBuf};
+
+use toml::{Table, Value};
+
+use crate::{
+    clipboard::ClipboardProvider,
+    document::DocumentOptions,
+    keybinds::{parse_map_to, parse_mapped_keys, Keymap, MapMode, MapTo, Mapping},
+    modal::named_action,
+    status_line,
+};
+
+// every option by its name and its short name
+const OPTIONS: [(&str, &str); 16] = [
+    ("tabstop", "ts"),
+    ("shiftwidth", "sw"),
+    ("expandtab", "et"),
+    ("list", "list"),
+    ("scrolloff", "so"),
+    ("wrap", "wrap"),
+    ("linebreak", "lbr"),
+    ("showbreak", "sbr"),
+    ("number", "nu"),
+    ("relativenumber", "rnu"),
+    ("signcolumn", "scl"),
+    ("theme", "theme"),
+    ("leader", "leader"),
+    ("timeoutlen", "tm"),
+    ("statusline", "stl"),
+    ("clipboard", "cb"),
+];
+
+#[derive(Clone, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Number(usize),
+    Text(String),
+}
+
+impl Display for OptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Number(value) => write!(f, "{}", value),
+            OptionValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    // tabstop, shiftwidth and expandtab, given to each document opened
+    pub document: DocumentOptions,
+    // draws spaces and tabs as . and >
+    pub list: bool,
+    // rows kept in view above and below the cursor
+    pub scroll_off: usize,
+    // long rows go on over the screen lines below instead of scrolling sideways
+    pub wrap: bool,
+    // wrapped rows break after a space rather than at the edge of the screen
+    pub line_break: bool,
+    // drawn at the start of the screen lines a wrapped row goes on to
+    pub show_break: String,
+    // line numbers down the left side
+    pub number: bool,
+    // numbers counting away from the cursor's row, which shows its own number when number is on too
+    pub relative_number: bool,
+    // the sign column shown always with "yes", never with "no", or when there are signs with "auto"
+    pub sign_column: String,
+    pub theme: String,
+    // the keys <leader> stands for in mappings
+    pub leader: String,
+    // milliseconds to wait for the rest of a mapping before taking the keys as they are
+    pub timeout_len: usize,
+    pub keymap: Keymap,
+    // the status bar's format, see status_line
+    pub status_line: String,
+    // how "+ and "* reach the system clipboard, a provider's name or a copy | paste command pair
+    pub clipboard: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            document: DocumentOptions::default(),
+            list: true,
+            scroll_off: 0,
+            wrap: false,
+            line_break: false,
+            show_break: String::new(),
+            number: false,
+            relative_number: false,
+            sign_column: "auto".to_string(),
+            theme: "default".to_string(),
+            leader: "\\".to_string(),
+            timeout_len: 1000,
+            keymap: Keymap::default(),
+            status_line: "{file} {modified}{=}{mode}{=}{position}".to_string(),
+            clipboard: "osc52".to_string(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    // Sets every option in the file, keeping the ones that are valid when others are not
+    pub fn source(&mut self, path: &PathBuf) -> Result<(), String> {
+        let file_name = path
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err.kind()))?;
+        let table: Table = source.parse().map_err(|err: toml::de::Error| {
+            let line = err
+                .span()
+                .map_or(0, |span| source[..span.start].matches('\n').count());
+            let message = err.message().lines().next().unwrap_or_default();
+            format!("{} line {}: {}", file_name, line + 1, message)
+        })?;
+
+        // options first, the leader has to be known before the keys using it
+        let mut errors = vec![];
+        for (key, value) in &table {
+            let result = match key.as_str() {
+                "keys" => continue,
+                _ => self.set_from_toml(key, value),
+            };
+            errors.extend(result.err());
+        }
+        match table.get("keys") {
+            Some(Value::Table(modes)) => errors.extend(self.add_keys(modes, &file_name)),
+            Some(_) => errors.push("keys should be a table of modes".to_string()),
+            None => {}
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(format!("{}: {}", file_name, errors[0])),
+            n => Err(format!("{}: {} (and {} more)", file_name, errors[0], n - 1)),
+        }
+    }
+
+    fn set_from_toml(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        let current = self.get(key).ok_or(format!("unknown option {}", key))?;
+        let value = match (&current, value) {
+            (OptionValue::Bool(_), Value::Boolean(value)) => OptionValue::Bool(*value),
+            (OptionValue::Number(_), Value::Integer(value)) => OptionValue::Number(
+                usize::try_from(*value).map_err(|_| format!("{} cannot be negative", key))?,
+            ),
+            (OptionValue::Text(_), Value::String(value)) => OptionValue::Text(value.clone()),
+            (OptionValue::Bool(_), _) => return Err(format!("{} should be true or false", key)),
+            (OptionValue::Number(_), _) => return Err(format!("{} should be a number", key)),
+            (OptionValue::Text(_), _) => return Err(format!("{} should be a string", key)),
+        };
+        self.set(key, value)
+    }
+
+    // The [keys.mode] tables, returning what was wrong with any of them
+    fn add_keys(&mut self, modes: &Table, set_from: &str) -> Vec<String> {
+        let mut errors = vec![];
+        for (mode_name, keys) in modes {
+            let mode = match mode_name.as_str() {
+                "normal" => MapMode::Normal,
+                "visual" => MapMode::Visual,
+                "insert" => MapMode::Insert,
+                _ => {
+                    errors.push(format!("no mode called {} for keys", mode_name));
+                    continue;
+                }
+            };
+            let Value::Table(keys) = keys else {
+                errors.push(format!("keys.{} should be a table", mode_name));
+                continue;
+            };
+            for (from, to) in keys {
+                match self.mapping_from_toml(mode, from, to, set_from) {
+                    Ok(mapping) => self.keymap.add(mapping),
+                    Err(err) => errors.push(format!("keys.{} {}: {}", mode_name, from, err)),
+                }
+            }
+        }
+        errors
+    }
+
+    fn mapping_from_toml(
+        &self,
+        mode: MapMode,
+        from: &str,
+        to: &Value,
+        set_from: &str,
+    ) -> Result<Mapping, String> {
+        let keys = parse_mapped_keys(from, &self.leader);
+        if keys.is_empty() {
+            return Err("no keys to map".to_string());
+        }
+        let (to, recursive) = match to {
+            Value::String(to) => (parse_map_to(to, &self.leader), false),
+            Value::Table(table) => {
+                match (table.get("action"), table.get("keys"), table.get("remap")) {
+                    (Some(Value::String(name)), None, None) => (MapTo::Action(name.clone()), false),
+                    (None, Some(Value::String(to)), remap @ (None | Some(Value::Boolean(_)))) => (
+                        MapTo::Keys(parse_mapped_keys(to, &self.leader)),
+                        remap == Some(&Value::Boolean(true)),
+                    ),
+                    _ => return Err("should have an action, or keys and maybe remap".to_string()),
+                }
+            }
+            _ => return Err("should be keys or a table".to_string()),
+        };
+        if let MapTo::Action(name) = &to {
+            named_action(name).ok_or(format!("no action called {}", name))?;
+        }
+        Ok(Mapping {
+            mode,
+            keys,
+            to,
+            recursive,
+            set_from: set_from.to_string(),
+        })
+    }
+
+    // Takes `name`, `noname`, `name!`, `name=value` or `name?` as :set does
+    // Returns the values asked for with ?, or of non-boolean options given by name alone
+    pub fn set_from_args(&mut self, args: &str) -> Result<Vec<String>, String> {
+        let mut shown = vec![];
+        for arg in split_args(args) {
+            let arg = arg.as_str();
+            if let Some((name, value)) = arg.split_once(['=', ':']) {
+                let current = self.get(name).ok_or(format!("Unknown option: {}", name))?;
+                let value = match current {
+                    OptionValue::Bool(_) => {
+                        return Err(format!("{} is on or off, not set to a value", name))
+                    }
+                    OptionValue::Number(_) => OptionValue::Number(
+                        value
+                            .parse()
+                            .map_err(|_| format!("{} should be a number", name))?,
+                    ),
+                    OptionValue::Text(_) => OptionValue::Text(value.to_string()),
+                };
+                self.set(name, value)?;
+                continue;
+            }
+            if let Some(name) = arg.strip_suffix('?') {
+                let value = self.get(name).ok_or(format!("Unknown option: {}", name))?;
+                shown.push(format!("{}={}", full_name(name).unwrap_or(name), value));
+                continue;
+            }
+            if let Some(name) = arg.strip_suffix('!') {
+                match self.get(name) {
+                    Some(OptionValue::Bool(value)) => self.set(name, OptionValue::Bool(!value))?,
+                    Some(_) => return Err(format!("{} is not on or off", name)),
+                    None => return Err(format!("Unknown option: {}", name)),
+                }
+                continue;
+            }
+            match (self.get(arg), arg.strip_prefix("no")) {
+                (Some(OptionValue::Bool(_)), _) => self.set(arg, OptionValue::Bool(true))?,
+                (Some(value), _) => {
+                    shown.push(format!("{}={}", full_name(arg).unwrap_or(arg), value))
+                }
+                (None, Some(name)) if matches!(self.get(name), Some(OptionValue::Bool(_))) => {
+                    self.set(name, OptionValue::Bool(false))?
+                }
+                (None, _) => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+        Ok(shown)
+    }
+
+    // Every option and its value, for :set all
+    pub fn listing(&self) -> Vec<String> {
+        OPTIONS
+            .iter()
+            .filter_map(|(name, _)| Some(format!("{:12} {}", name, self.get(name)?)))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<OptionValue> {
+        let value = match full_name(name)? {
+            "tabstop" => OptionValue::Number(self.document.tab_stop),
+            "shiftwidth" => OptionValue::Number(self.document.shift_width),
+            "expandtab" => OptionValue::Bool(self.document.expand_tab),
+            "list" => OptionValue::Bool(self.list),
+            "scrolloff" => OptionValue::Number(self.scroll_off),
+            "wrap" => OptionValue::Bool(self.wrap),
+            "linebreak" => OptionValue::Bool(self.line_break),
+            "showbreak" => OptionValue::Text(self.show_break.clone()),
+            "number" => OptionValue::Bool(self.number),
+            "relativenumber" => OptionValue::Bool(self.relative_number),
+            "signcolumn" => OptionValue::Text(self.sign_column.clone()),
+            "theme" => OptionValue::Text(self.theme.clone()),
+            "leader" => OptionValue::Text(self.leader.clone()),
+            "timeoutlen" => OptionValue::Number(self.timeout_len),
+            "statusline" => OptionValue::Text(self.status_line.clone()),
+            "clipboard" => OptionValue::Text(self.clipboard.clone()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // The value is already the option's type, only its range is checked here
+    fn set(&mut self, name: &str, value: OptionValue) -> Result<(), String> {
+        let name = full_name(name).ok_or(format!("Unknown option: {}", name))?;
+        match (name, value) {
+            ("tabstop" | "shiftwidth", OptionValue::Number(0)) => {
+                return Err(format!("{} must be at least 1", name))
+            }
+            ("tabstop", OptionValue::Number(value)) => self.document.tab_stop = value,
+            ("shiftwidth", OptionValue::Number(value)) => self.document.shift_width = value,
+            ("expandtab", OptionValue::Bool(value)) => self.document.expand_tab = value,
+            ("list", OptionValue::Bool(value)) => self.list = value,
+            ("scrolloff", OptionValue::Number(value)) => self.scroll_off = value,
+            ("wrap", OptionValue::Bool(value)) => self.wrap = value,
+            ("linebreak", OptionValue::Bool(value)) => self.line_break = value,
+            ("showbreak", OptionValue::Text(value)) => self.show_break = value,
+            ("number", OptionValue::Bool(value)) => self.number = value,
+            ("relativenumber", OptionValue::Bool(value)) => self.relative_number = value,
+            ("signcolumn", OptionValue::Text(value)) => match value.as_str() {
+                "auto" | "yes" | "no" => self.sign_column = value,
+                _ => return Err("signcolumn should be auto, yes or no".to_string()),
+            },
+            ("theme", OptionValue::Text(value)) => self.theme = value,
+            ("leader", OptionValue::Text(value)) => self.leader = value,
+            ("timeoutlen", OptionValue::Number(value)) => self.timeout_len = value,
+            ("statusline", OptionValue::Text(value)) => {
+                status_line::validate(&value)?;
+                self.status_line = value;
+            }
+            ("clipboard", OptionValue::Text(value)) => {
+                ClipboardProvider::from_name(&value)
+                    .ok_or(format!("No clipboard called {}", value))?;
+                self.clipboard = value;
+            }
+            _ => return Err(format!("Wrong kind of value for {}", name)),
+        }
+        Ok(())
+    }
+}
+
+// Splits at whitespace, except where it follows a \ as in a value like `stl={file}\ {mode}`
+fn split_args(args: &str) -> Vec<String> {
+    let mut split = vec![];
+    let mut arg = String::new();
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    split.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        split.push(arg);
+    }
+    split
+}
+
+fn full_name(name: &str) -> Option<&'static str> {
+    OPTIONS
+        .iter()
+        .find(|(full, short)| *full == name || *short == name)
+        .map(|(full, _)| *full)
+}
+
+// $XDG_CONFIG_HOME/textist, or ~/.config/textist
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("textist"))
+}
@@ -1,67 +1,367 @@
 use std::{
-    cmp, fs,
+    cmp,
+    collections::{BTreeMap, BTreeSet},
+    fs,
     io::{Error, Write},
+    ops::Range,
 };
 
-use crossterm::style::Stylize;
+use crossterm::style::{Color, Stylize};
+use regex::Regex;
 
-use crate::{editor::Position, text_target::TextTarget};
+use crate::{
+    editor::Position,
+    filetype::{CommentStyle, Filetype},
+    highlight::Token,
+    modal::VerticalDirection,
+    signs::Sign,
+    text_target::{
+        bracket_range, quote_range, sentence_range, tag_range, word_range, FindKind, ObjectScope,
+        TextObject, TextTarget,
+    },
+    theme::{Theme, Ui},
+};
+
+// How :sort orders rows
+#[derive(Default, Clone)]
+pub struct SortOptions {
+    pub numeric: bool,     // by the first number in the row
+    pub unique: bool,      // keeps only the first of rows which compare equal
+    pub ignore_case: bool, // compares without case
+    pub reverse: bool,
+    // sorts by what this matches, or its first group when it has one
+    // rows it does not match keep their order ahead of the rest
+    pub pattern: Option<String>,
+}
+
+// What the case operators do to letters, ~ or g~, gu and gU respectively
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaseChange {
+    Toggle,
+    Lower,
+    Upper,
+}
+
+impl CaseChange {
+    // some characters change into more than one, like ß into SS
+    fn apply(self, c: char) -> String {
+        match self {
+            CaseChange::Upper => c.to_uppercase().collect(),
+            CaseChange::Lower => c.to_lowercase().collect(),
+            CaseChange::Toggle if c.is_uppercase() => c.to_lowercase().collect(),
+            CaseChange::Toggle => c.to_uppercase().collect(),
+        }
+    }
+}
+
+// columns per tab stop unless set otherwise
+pub const TAB_WIDTH: usize = 4;
+
+// Settings kept per document
+#[derive(Clone)]
+pub struct DocumentOptions {
+    // columns per tab stop
+    pub tab_stop: usize,
+    // columns per indent level
+    pub shift_width: usize,
+    // indent with spaces, otherwise with as many tabs as fit
+    pub expand_tab: bool,
+}
+
+impl Default for DocumentOptions {
+    fn default() -> Self {
+        Self {
+            tab_stop: TAB_WIDTH,
+            shift_width: TAB_WIDTH,
+            expand_tab: true,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Document {
     pub rows: Vec<Row>,
     pub file_name: String,
+    pub filetype: Filetype,
+    pub options: DocumentOptions,
+    // changed since it was opened or last saved
+    pub modified: bool,
+    // the file's lines end in \r\n, which saving keeps
+    pub crlf: bool,
+    // a-z marks plus any A-Z file marks in this document, kept on their text as rows come and go
+    pub marks: BTreeMap<char, Position>,
+    // what git diff said of each row when the file was last read or saved, kept on their text like marks
+    pub git_signs: BTreeMap<usize, Sign>,
+    // rows holding only the indent they were given when opened, untouched since
+    pub auto_indented: BTreeSet<usize>,
+    // the rows changed since the highlighter last took them
+    pub edit: Option<RowEdit>,
+    pub history: History,
+}
+
+// Rows start..old_end were replaced by the rows now at start..new_end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RowEdit {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+// The rows and cursor as they were on one side of a change
+struct Snapshot {
+    rows: Vec<Row>,
+    cursor: Position,
+}
+
+// Changes to undo and redo, each a whole command or everything typed in one insert
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    // how the document was when the change being made began
+    open: Option<Snapshot>,
 }
 
 impl Document {
     // Opens the file, file_name, or if that is not possible will open an empty document
-    pub fn open(file_name: &str) -> Self {
+    pub fn open(file_name: &str, options: DocumentOptions) -> Self {
         let contents = match fs::read_to_string(file_name) {
             Ok(c) => c,
             Err(_) => {
                 return Document {
                     file_name: file_name.to_string(),
-                    rows: vec![],
+                    filetype: Filetype::detect(file_name, None),
+                    options,
+                    ..Default::default()
                 };
             }
         };
 
+        // tabs are kept as they are unless the document indents with spaces
+        let tab = " ".repeat(options.tab_stop);
         let mut rows = vec![];
         for line in contents.lines() {
-            let line = line.replace("\t", "    ").into();
+            let line = match options.expand_tab {
+                true => line.replace("\t", &tab).into(),
+                false => line.into(),
+            };
             rows.push(line);
         }
 
-        Self {
+        let mut document = Self {
             rows,
             file_name: file_name.to_string(),
-        }
+            options,
+            crlf: contents.contains("\r\n"),
+            ..Default::default()
+        };
+        document.detect_filetype();
+        document
+    }
+
+    // Works out the filetype again, for when the file is given a name
+    pub(crate) fn detect_filetype(&mut self) {
+        let first_line = self.rows.first().map(|row| row.string.as_str());
+        self.filetype = Filetype::detect(&self.file_name, first_line);
     }
 
-    pub fn save(&self) -> Result<(), Error> {
+    pub fn save(&mut self) -> Result<(), Error> {
         match fs::File::create(&self.file_name) {
             Ok(mut file) => {
                 for row in &self.rows {
                     file.write_all(row.string.as_bytes())?;
-                    file.write_all(b"\n")?;
+                    file.write_all(if self.crlf { b"\r\n" } else { b"\n" })?;
                 }
+                self.modified = false;
                 Ok(())
             }
             Err(e) => Err(e),
         }
     }
 
-    pub fn insert(&mut self, at: &Position, c: char) {
-        let row = match self.rows.get_mut(at.y) {
-            Some(row) => row,
-            None => {
-                self.rows.insert(at.y, Row::default());
-                // unwrap - insertion prior so it should be there
-                self.rows.get_mut(at.y).unwrap()
-            }
+    // Starts a change unless one is open, whatever is edited until end_change is undone at once
+    pub(crate) fn begin_change(&mut self, cursor: Position) {
+        if self.history.open.is_none() {
+            self.history.open = Some(Snapshot {
+                rows: self.rows.clone(),
+                cursor,
+            });
+        }
+    }
+
+    // Keeps the open change for undo when it left the rows any different
+    pub(crate) fn end_change(&mut self) {
+        let Some(before) = self.history.open.take() else {
+            return;
         };
+        if before.rows != self.rows {
+            self.history.undo.push(before);
+            self.history.redo.clear();
+        }
+    }
+
+    // Puts the rows back as they were before the last change, None when there is nothing to undo
+    // Gives where the cursor was when the change began
+    pub(crate) fn undo(&mut self, cursor: Position) -> Option<Position> {
+        self.end_change();
+        let before = self.history.undo.pop()?;
+        let rows = self.restore(before.rows);
+        self.history.redo.push(Snapshot { rows, cursor });
+        Some(before.cursor)
+    }
+
+    // Makes the last undone change again, giving where the cursor was when it was undone
+    pub(crate) fn redo(&mut self, cursor: Position) -> Option<Position> {
+        self.end_change();
+        let after = self.history.redo.pop()?;
+        let rows = self.restore(after.rows);
+        self.history.undo.push(Snapshot { rows, cursor });
+        Some(after.cursor)
+    }
+
+    // Swaps in rows, telling the hooks only about those which differ, and gives back the old ones
+    fn restore(&mut self, rows: Vec<Row>) -> Vec<Row> {
+        let old = std::mem::replace(&mut self.rows, rows);
+        let start = old
+            .iter()
+            .zip(&self.rows)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let same_end = old[start..]
+            .iter()
+            .rev()
+            .zip(self.rows[start..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        let (old_end, new_end) = (old.len() - same_end, self.rows.len() - same_end);
+        // rows in both are edited in place, keeping their marks, the rest come or go
+        let both = cmp::min(old_end, new_end);
+        if both > start {
+            self.rows_changed(start, both, both);
+        }
+        if old_end > both {
+            self.rows_removed(both..old_end);
+        }
+        if new_end > both {
+            self.rows_inserted(both, new_end - both);
+        }
+        old
+    }
+
+    // Moves each mark, git sign and auto indented row to follow its text, dropping those whose text is gone
+    // Rows go with their start, a git sign giving way to one already on the row it lands on
+    fn adjust_marks(&mut self, adjust: impl Fn(Position) -> Option<Position>) {
+        self.marks = std::mem::take(&mut self.marks)
+            .into_iter()
+            .filter_map(|(name, pos)| Some((name, adjust(pos)?)))
+            .collect();
+        let mut signs = BTreeMap::new();
+        for (y, sign) in std::mem::take(&mut self.git_signs) {
+            if let Some(pos) = adjust(Position { x: 0, y }) {
+                signs.entry(pos.y).or_insert(sign);
+            }
+        }
+        self.git_signs = signs;
+        self.auto_indented = std::mem::take(&mut self.auto_indented)
+            .into_iter()
+            .filter_map(|y| Some(adjust(Position { x: 0, y })?.y))
+            .collect();
+    }
+
+    // Rows start..old_end were replaced by start..new_end, folded into the edit not yet taken
+    fn rows_changed(&mut self, start: usize, old_end: usize, new_end: usize) {
+        self.modified = true;
+        // an indent typed over or moved is the user's to keep
+        self.auto_indented.retain(|y| !(start..old_end).contains(y));
+        self.edit = Some(match self.edit {
+            None => RowEdit {
+                start,
+                old_end,
+                new_end,
+            },
+            Some(edit) => {
+                // rows past both edits only moved, so the two cover everything up to the later end
+                let end = cmp::max(edit.new_end, old_end);
+                RowEdit {
+                    start: cmp::min(edit.start, start),
+                    old_end: edit.old_end + end - edit.new_end,
+                    new_end: end - old_end + new_end,
+                }
+            }
+        });
+    }
+
+    // the text of row y changed
+    fn row_edited(&mut self, y: usize) {
+        self.rows_changed(y, y + 1, y + 1);
+    }
+
+    // n new rows were put in at row y
+    fn rows_inserted(&mut self, y: usize, n: usize) {
+        self.rows_changed(y, y, y + n);
+        self.adjust_marks(|pos| match pos.y >= y {
+            true => Some(Position {
+                x: pos.x,
+                y: pos.y + n,
+            }),
+            false => Some(pos),
+        });
+    }
+
+    // the rows in removed were taken out
+    fn rows_removed(&mut self, removed: Range<usize>) {
+        self.rows_changed(removed.start, removed.end, removed.start);
+        self.adjust_marks(|pos| {
+            if removed.contains(&pos.y) {
+                None
+            } else if pos.y >= removed.end {
+                Some(Position {
+                    x: pos.x,
+                    y: pos.y - removed.len(),
+                })
+            } else {
+                Some(pos)
+            }
+        });
+    }
 
-        row.string.insert(at.x, c);
+    // row at.y was split with everything from at.x moving to a new row below
+    fn row_split(&mut self, at: Position) {
+        self.rows_changed(at.y, at.y + 1, at.y + 2);
+        self.adjust_marks(|pos| {
+            if pos.y > at.y || (pos.y == at.y && pos.x >= at.x) {
+                let x = if pos.y == at.y { pos.x - at.x } else { pos.x };
+                Some(Position { x, y: pos.y + 1 })
+            } else {
+                Some(pos)
+            }
+        });
+    }
+
+    // the row below y was joined onto the end of row y, which was len long
+    fn row_joined(&mut self, y: usize, len: usize) {
+        self.rows_changed(y, y + 2, y + 1);
+        self.adjust_marks(|pos| {
+            if pos.y == y + 1 {
+                Some(Position { x: pos.x + len, y })
+            } else if pos.y > y + 1 {
+                Some(Position {
+                    x: pos.x,
+                    y: pos.y - 1,
+                })
+            } else {
+                Some(pos)
+            }
+        });
+    }
+
+    pub fn set_mark(&mut self, name: char, at: Position) {
+        self.marks.insert(name, at);
+    }
+
+    pub fn insert(&mut self, at: &Position, c: char) {
+        let mut buf = [0; 4];
+        self.insert_str(at, c.encode_utf8(&mut buf));
     }
 
     pub(crate) fn remove_behind(&mut self, at: &mut Position) {
@@ -76,18 +376,18 @@ impl Document {
                 row.string += &old_row.string;
                 at.y = at.y.saturating_sub(1);
             }
+            self.row_joined(at.y, at.x);
             return;
         }
 
         let row = self.rows.get_mut(at.y).unwrap();
         row.string.remove(at.x.saturating_sub(1));
         at.x = at.x.saturating_sub(1);
+        self.row_edited(at.y);
     }
 
     pub(crate) fn remove_ahead(&mut self, at: &mut Position) -> Option<char> {
-        let Some(curr_row) = self.rows.get(at.y) else {
-            return None;
-        };
+        let curr_row = self.rows.get(at.y)?;
         let mut old_row = None;
         if at.x == curr_row.len() {
             if at.y >= self.rows.len() - 1 {
@@ -98,26 +398,484 @@ impl Document {
 
         let row = self.rows.get_mut(at.y).unwrap();
         if let Some(text) = old_row {
+            let len = row.len();
             row.string += &text.string;
+            self.row_joined(at.y, len);
             return None;
         }
 
-        Some(row.string.remove(at.x))
+        let removed = row.string.remove(at.x);
+        self.row_edited(at.y);
+        Some(removed)
     }
 
     pub(crate) fn _add_blank_line(&mut self, at: &Position) {
         let row = cmp::min(at.y, self.rows.len());
         self.rows.insert(row, Row::default());
+        self.rows_inserted(row, 1);
     }
 
-    pub(crate) fn add_line_with_spaces_to_cursor(&mut self, at: &Position) {
-        let row = cmp::min(at.y, self.rows.len());
-        self.rows.insert(
-            row,
-            Row {
-                string: " ".repeat(at.x),
+    // Puts a new row holding only the indent at row y, returning where the cursor goes on it
+    pub(crate) fn add_indented_line(&mut self, y: usize, indent: &str) -> Position {
+        let y = cmp::min(y, self.rows.len());
+        self.rows.insert(y, Row::from(indent));
+        self.rows_inserted(y, 1);
+        if !indent.is_empty() {
+            self.auto_indented.insert(y);
+        }
+        Position { x: indent.len(), y }
+    }
+
+    // Splits the row at `at` like add_line, indenting the new row to suit the text before it
+    // A closing bracket right after the cursor goes on a row of its own below the new one
+    pub(crate) fn add_line_indented(&mut self, at: &Position) -> Position {
+        if self.rows.get(at.y).is_none() {
+            // nothing to split yet, make the row first
+            self.insert_str(at, "");
+        }
+        let mut at = *at;
+        let base = self.indent_of(at.y);
+        let indent = self.indent_for_line_after(at.y, at.x);
+        if self.auto_indented.contains(&at.y) {
+            // an indent left with nothing after it
+            at.x = 0;
+            self.clear_auto_indent(at.y);
+        }
+
+        self.add_line(&at);
+        let rest = &self.rows[at.y + 1].string;
+        let blank = rest.len() - rest.trim_start().len();
+        let closes = rest.trim_start().starts_with([')', ']', '}']);
+        self.delete_span(&Span {
+            start: Position { x: 0, y: at.y + 1 },
+            end: Position {
+                x: blank,
+                y: at.y + 1,
             },
-        );
+        });
+        if closes && indent.len() > base.len() {
+            self.insert_str(&Position { x: 0, y: at.y + 1 }, &base);
+            return self.add_indented_line(at.y + 1, &indent);
+        }
+        self.insert_str(&Position { x: 0, y: at.y + 1 }, &indent);
+        if !indent.is_empty() && self.rows[at.y + 1].len() == indent.len() {
+            self.auto_indented.insert(at.y + 1);
+        }
+        Position {
+            x: indent.len(),
+            y: at.y + 1,
+        }
+    }
+
+    // Empties row y when it holds only the indent it was opened with, as vim does on Esc or Enter
+    pub(crate) fn clear_auto_indent(&mut self, y: usize) {
+        if self.auto_indented.contains(&y) {
+            self.clear_blank_row(y);
+        }
+    }
+
+    // Empties row y when it holds nothing but whitespace
+    fn clear_blank_row(&mut self, y: usize) {
+        let Some(row) = self.rows.get(y) else {
+            return;
+        };
+        if !row.string.is_empty() && row.string.trim().is_empty() {
+            let len = row.len();
+            self.delete_span(&Span {
+                start: Position { x: 0, y },
+                end: Position { x: len, y },
+            });
+        }
+    }
+
+    // The leading whitespace of row y
+    pub(crate) fn indent_of(&self, y: usize) -> String {
+        let x = self.first_non_blank(y);
+        match self.rows.get(y) {
+            Some(row) => row.string[..x].to_string(),
+            None => String::new(),
+        }
+    }
+
+    // The indent for a row following the text of row y up to column x
+    // One level deeper when that text ends by opening a block
+    pub(crate) fn indent_for_line_after(&self, y: usize, x: usize) -> String {
+        let before = self
+            .rows
+            .get(y)
+            .and_then(|row| row.string.get(..x))
+            .unwrap_or_default();
+        if before.trim_end().ends_with(self.indent_openers()) {
+            self.indent_string(self.indent_width(y) + self.options.shift_width)
+        } else {
+            self.indent_of(y)
+        }
+    }
+
+    // Characters which open an indented block at the end of a line, going by the file's extension
+    fn indent_openers(&self) -> &'static [char] {
+        match self.filetype.indent_by_braces() {
+            true => &['{', '(', '['],
+            false => &[':', '(', '[', '{'],
+        }
+    }
+
+    // The columns taken by the leading whitespace of row y
+    pub(crate) fn indent_width(&self, y: usize) -> usize {
+        self.indent_of(y).chars().fold(0, |width, c| match c {
+            '\t' => width + self.options.tab_stop - width % self.options.tab_stop,
+            _ => width + 1,
+        })
+    }
+
+    // Whitespace filling width columns, as tabs and then spaces unless expand_tab is set
+    pub(crate) fn indent_string(&self, width: usize) -> String {
+        match self.options.expand_tab {
+            true => " ".repeat(width),
+            false => {
+                let tab_stop = self.options.tab_stop;
+                "\t".repeat(width / tab_stop) + &" ".repeat(width % tab_stop)
+            }
+        }
+    }
+
+    // Swaps the leading whitespace of row y for width columns, returning how far its text moved
+    pub(crate) fn set_indent(&mut self, y: usize, width: usize) -> isize {
+        let old = self.first_non_blank(y);
+        let indent = self.indent_string(width);
+        let Some(row) = self.rows.get_mut(y) else {
+            return 0;
+        };
+        if row.string[..old] == indent {
+            return 0;
+        }
+        row.string.replace_range(..old, &indent);
+        self.row_edited(y);
+        indent.len() as isize - old as isize
+    }
+
+    // Shifts the rows by levels of shift_width, leaving empty rows alone
+    pub(crate) fn shift_rows(&mut self, rows: Range<usize>, right: bool, levels: usize) {
+        let by = self.options.shift_width * levels;
+        for y in rows {
+            if self.rows.get(y).is_none_or(|row| row.string.is_empty()) {
+                continue;
+            }
+            let width = self.indent_width(y);
+            let width = match right {
+                true => width + by,
+                false => width.saturating_sub(by),
+            };
+            self.set_indent(y, width);
+        }
+    }
+
+    // Indents the rows again from the nearest non blank row above them
+    // Bracketed files go by the brackets opened and closed on each row, others by a trailing :
+    pub(crate) fn reindent(&mut self, rows: Range<usize>) {
+        let shift = self.options.shift_width;
+        let braces = self.filetype.indent_by_braces();
+        let above = (0..rows.start)
+            .rev()
+            .find(|&y| !self.rows[y].string.trim().is_empty());
+        // whether the row before starts a block the rows have to be inside
+        let mut opened = above.is_some_and(|y| {
+            self.rows[y]
+                .string
+                .trim_end()
+                .ends_with(self.indent_openers())
+        });
+        let mut width = match above {
+            Some(y) if opened => self.indent_width(y) + shift,
+            Some(y) => self.indent_width(y),
+            None => 0,
+        };
+
+        for y in rows {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            let text = row.string.trim().to_string();
+            if text.is_empty() {
+                self.clear_blank_row(y);
+                continue;
+            }
+
+            if braces {
+                let (opens, closes) = bracket_counts(&text);
+                let leading = text
+                    .chars()
+                    .take_while(|c| matches!(c, ')' | ']' | '}'))
+                    .count();
+                self.set_indent(y, width.saturating_sub(leading * shift));
+                width = (width + opens * shift).saturating_sub(closes * shift);
+            } else {
+                // rows can end blocks but only go deeper right after a :
+                let own = match opened {
+                    true => width,
+                    false => cmp::min(self.indent_width(y), width),
+                };
+                width = own - own % shift;
+                self.set_indent(y, width);
+                opened = text.ends_with(self.indent_openers());
+                if opened {
+                    width += shift;
+                }
+            }
+        }
+    }
+
+    // Joins count rows starting at y into one, returning the column of the last join
+    // With spaces the joined rows lose their indent and are separated by a space where needed
+    pub(crate) fn join_rows(&mut self, y: usize, count: usize, spaces: bool) -> Option<usize> {
+        let mut join_at = None;
+        for _ in 1..count {
+            if y + 1 >= self.rows.len() {
+                break;
+            }
+            let next = self.rows.remove(y + 1).string;
+            let row = &mut self.rows[y];
+            let mut text = next.as_str();
+            join_at = Some(row.len());
+            if spaces {
+                text = text.trim_start();
+                let trimmed = row.string.trim_end().len();
+                row.string.truncate(trimmed);
+                join_at = Some(trimmed);
+                if !row.string.is_empty() && !text.is_empty() && !text.starts_with(')') {
+                    row.string.push(' ');
+                }
+            }
+            // marks on the joined row follow its text
+            let offset = row.string.len() as isize - (next.len() - text.len()) as isize;
+            row.string += text;
+            self.adjust_marks(|pos| match pos.y.cmp(&(y + 1)) {
+                cmp::Ordering::Less => Some(pos),
+                cmp::Ordering::Equal => Some(Position {
+                    x: pos.x.saturating_add_signed(offset),
+                    y,
+                }),
+                cmp::Ordering::Greater => Some(Position {
+                    x: pos.x,
+                    y: pos.y - 1,
+                }),
+            });
+            self.rows_changed(y, y + 2, y + 1);
+        }
+        join_at
+    }
+
+    // Puts the rows in the given order in place of region, each entry being the row to take
+    // Marks go with the first copy of their row and are dropped with rows left out
+    fn reorder_rows(&mut self, region: Range<usize>, order: Vec<usize>) {
+        let rows: Vec<Row> = order.iter().map(|&y| self.rows[y].clone()).collect();
+        let (start, old_len, new_len) = (region.start, region.len(), order.len());
+        self.rows.splice(region.clone(), rows);
+        self.rows_changed(start, start + old_len, start + new_len);
+        self.adjust_marks(|pos| {
+            if region.contains(&pos.y) {
+                let idx = order.iter().position(|&y| y == pos.y)?;
+                Some(Position {
+                    x: pos.x,
+                    y: start + idx,
+                })
+            } else if pos.y >= region.end {
+                Some(Position {
+                    x: pos.x,
+                    y: pos.y + new_len - old_len,
+                })
+            } else {
+                Some(pos)
+            }
+        });
+    }
+
+    // Moves the rows to go before row `to`, which cannot be inside them
+    pub(crate) fn move_rows(&mut self, rows: Range<usize>, to: usize) -> bool {
+        if to > rows.start && to < rows.end {
+            return false;
+        }
+        let order: Vec<usize> = if to >= rows.end {
+            (rows.end..to).chain(rows.clone()).collect()
+        } else {
+            rows.clone().chain(to..rows.start).collect()
+        };
+        let region = cmp::min(rows.start, to)..cmp::max(rows.end, to);
+        self.reorder_rows(region, order);
+        true
+    }
+
+    // Puts a copy of the rows before row `to`
+    pub(crate) fn copy_rows(&mut self, rows: Range<usize>, to: usize) {
+        self.reorder_rows(to..to, rows.collect());
+    }
+
+    pub(crate) fn sort_rows(
+        &mut self,
+        rows: Range<usize>,
+        options: &SortOptions,
+    ) -> Result<(), regex::Error> {
+        let pattern = match &options.pattern {
+            Some(pattern) => Some(Regex::new(pattern)?),
+            None => None,
+        };
+        let key = |y: usize| -> Option<String> {
+            let text = self.rows[y].string.as_str();
+            let text = match &pattern {
+                Some(pattern) => {
+                    let captures = pattern.captures(text)?;
+                    captures.get(1).or(captures.get(0))?.as_str()
+                }
+                None => text,
+            };
+            match options.ignore_case {
+                true => Some(text.to_lowercase()),
+                false => Some(text.to_string()),
+            }
+        };
+
+        let (mut unmatched, mut keyed): (Vec<_>, Vec<_>) = rows
+            .clone()
+            .map(|y| (y, key(y)))
+            .partition(|(_, key)| key.is_none());
+        let compare = |a: &Option<String>, b: &Option<String>| match options.numeric {
+            true => first_number(a).cmp(&first_number(b)),
+            false => a.cmp(b),
+        };
+        keyed.sort_by(|a, b| compare(&a.1, &b.1));
+        if options.reverse {
+            keyed.reverse();
+        }
+        if options.unique {
+            keyed.dedup_by(|a, b| compare(&a.1, &b.1).is_eq());
+            unmatched.dedup_by(|a, b| self.rows[a.0].string == self.rows[b.0].string);
+        }
+
+        let order = unmatched.into_iter().chain(keyed).map(|(y, _)| y).collect();
+        self.reorder_rows(rows, order);
+        Ok(())
+    }
+
+    pub(crate) fn change_case(&mut self, span: &Span, case: CaseChange) {
+        for y in span.start.y..=cmp::min(span.end.y, self.rows.len().saturating_sub(1)) {
+            let (Some(columns), Some(row)) = (span.columns_on(y), self.rows.get_mut(y)) else {
+                continue;
+            };
+            let end = cmp::min(columns.end, row.len());
+            let start = cmp::min(columns.start, end);
+            let changed: String = row.string[start..end]
+                .chars()
+                .map(|c| case.apply(c))
+                .collect();
+            row.string.replace_range(start..end, &changed);
+            self.row_edited(y);
+        }
+    }
+
+    // Adds amount to the first number on row at.y ending after at.x and starting before limit
+    // Returns the column of the number's last character
+    // Decimal numbers can be negative and keep any leading zeros, 0x and 0b numbers wrap around
+    pub(crate) fn increment_number(
+        &mut self,
+        at: &Position,
+        limit: usize,
+        amount: i64,
+    ) -> Option<usize> {
+        let line = self.rows.get(at.y)?.string.clone();
+        // unwrap - the pattern is fixed
+        let numbers = Regex::new(r"(?i)0x[0-9a-f]+|0b[01]+|[0-9]+").unwrap();
+        let found = numbers
+            .find_iter(&line)
+            .find(|found| found.end() > at.x && found.start() < limit)?;
+        let text = found.as_str();
+
+        let (start, replacement) = if let Some(digits) =
+            text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+        {
+            let value = u64::from_str_radix(digits, 16).ok()?;
+            let value = value.wrapping_add_signed(amount);
+            let mut hex = format!("{:0width$x}", value, width = digits.len());
+            if digits.chars().any(|c| c.is_ascii_uppercase()) {
+                hex = hex.to_uppercase();
+            }
+            (found.start(), format!("{}{}", &text[..2], hex))
+        } else if let Some(digits) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+            let value = u64::from_str_radix(digits, 2).ok()?;
+            let value = value.wrapping_add_signed(amount);
+            let binary = format!("{:0width$b}", value, width = digits.len());
+            (found.start(), format!("{}{}", &text[..2], binary))
+        } else {
+            let negative = line[..found.start()].ends_with('-');
+            let start = found.start() - negative as usize;
+            let value: i128 = text.parse().ok()?;
+            let value = if negative { -value } else { value } + amount as i128;
+            // leading zeros keep the number as wide as it was
+            let width = match text.len() > 1 && text.starts_with('0') {
+                true => text.len(),
+                false => 0,
+            };
+            let sign = if value < 0 { "-" } else { "" };
+            (
+                start,
+                format!("{}{:0width$}", sign, value.unsigned_abs(), width = width),
+            )
+        };
+
+        let row = &mut self.rows[at.y];
+        row.string.replace_range(start..found.end(), &replacement);
+        self.row_edited(at.y);
+        Some(start + replacement.len() - 1)
+    }
+
+    // Removes rows repeating the row right before them, returning how many went
+    pub(crate) fn uniq_rows(&mut self, rows: Range<usize>) -> usize {
+        let mut order: Vec<usize> = rows.clone().collect();
+        order.dedup_by(|a, b| self.rows[*a].string == self.rows[*b].string);
+        let removed = rows.len() - order.len();
+        if removed > 0 {
+            self.reorder_rows(rows, order);
+        }
+        removed
+    }
+
+    // The rows a linewise target takes, None when j or k would have nowhere to go
+    fn whole_rows(&self, at: &Position, target: &TextTarget, count: usize) -> Option<Range<usize>> {
+        let last = self.rows.len().saturating_sub(1);
+        let (start, end) = match target {
+            TextTarget::All => (0, self.rows.len()),
+            TextTarget::WholeRow => (at.y, at.y + count),
+            TextTarget::Rows(VerticalDirection::Down) if at.y < last => (at.y, at.y + count + 1),
+            TextTarget::Rows(VerticalDirection::Up) if at.y > 0 => {
+                (at.y.saturating_sub(count), at.y + 1)
+            }
+            TextTarget::ToLine(line) => {
+                let y = line.map_or(last, |line| cmp::min(line.saturating_sub(1), last));
+                (cmp::min(at.y, y), cmp::max(at.y, y) + 1)
+            }
+            _ => return None,
+        };
+        let end = cmp::min(end, self.rows.len());
+        Some(cmp::min(start, end)..end)
+    }
+
+    // The rows a target covers, a span ending at the start of a row leaves that row out
+    pub(crate) fn target_rows(
+        &self,
+        at: &Position,
+        target: &TextTarget,
+        count: usize,
+    ) -> Option<Range<usize>> {
+        match target.is_linewise() {
+            true => self.whole_rows(at, target, count),
+            false => {
+                let span = self.target_span(at, target, count)?;
+                let end = match span.end.x == 0 && span.end.y > span.start.y {
+                    true => span.end.y,
+                    false => span.end.y + 1,
+                };
+                Some(span.start.y..cmp::min(end, self.rows.len()))
+            }
+        }
     }
 
     // takes whatever is after the position horizontally and moves that to the next line
@@ -143,33 +901,396 @@ impl Document {
             return;
         };
         curr_row.string.truncate(at.x);
+        self.row_split(*at);
     }
 
-    pub(crate) fn delete(&mut self, at: &mut Position, target: &TextTarget) -> String {
+    pub(crate) fn delete(
+        &mut self,
+        at: &mut Position,
+        target: &TextTarget,
+        count: usize,
+    ) -> String {
         match target {
-            TextTarget::Char(_) => todo!(),
-            TextTarget::Nothing => todo!(),
-            TextTarget::All => todo!(),
-            TextTarget::UnderCursor => match self.remove_ahead(at) {
-                Some(char) => char.to_string(),
-                None => "".to_string(),
-            },
-            TextTarget::WholeRow => {
-                let old_row = self.rows.remove(at.y);
-                old_row.string
-            }
-            TextTarget::RowAfterCursor => match self.rows.get_mut(at.y) {
-                Some(row) => {
-                    let (new_str, deleted_str) = row.string.split_at(at.x);
-                    let deleted_str: String = deleted_str.to_string();
-                    row.string = new_str.to_string();
-                    deleted_str
+            TextTarget::Nothing => String::new(),
+            TextTarget::Selection => unreachable!("selection must be resolved by the editor"),
+            _ if target.is_linewise() => {
+                let Some(rows) = self.whole_rows(at, target, count) else {
+                    return String::new();
+                };
+                at.y = rows.start;
+                let old_rows: Vec<String> = self
+                    .rows
+                    .drain(rows.clone())
+                    .map(|row| row.string)
+                    .collect();
+                self.rows_removed(rows);
+                old_rows.join("\n")
+            }
+            _ => match self.target_span(at, target, count) {
+                Some(span) => {
+                    *at = span.start;
+                    self.delete_span(&span)
                 }
                 None => String::new(),
             },
         }
     }
 
+    // The text a target covers without removing it
+    pub(crate) fn target_text(&self, at: &Position, target: &TextTarget, count: usize) -> String {
+        match self.whole_rows(at, target, count) {
+            Some(rows) => {
+                let rows: Vec<&str> = self.rows[rows]
+                    .iter()
+                    .map(|row| row.string.as_str())
+                    .collect();
+                rows.join("\n")
+            }
+            None if target.is_linewise() => String::new(),
+            None => match self.target_span(at, target, count) {
+                Some(span) => self.span_text(&span),
+                None => String::new(),
+            },
+        }
+    }
+
+    // Finds the column the count'th occurence of c on the current row, adjusted for till searches
+    // A repeated till search skips the match beside the cursor, which it would only land on again
+    pub(crate) fn find_char(
+        &self,
+        at: &Position,
+        kind: FindKind,
+        c: char,
+        count: usize,
+        repeat: bool,
+    ) -> Option<usize> {
+        let row = self.rows.get(at.y)?;
+        let matches = row.string.char_indices().filter(|(_, ch)| *ch == c);
+        let from = match kind {
+            FindKind::TillForward if repeat => row.next_boundary(at.x),
+            FindKind::TillBackward if repeat => row.prev_boundary(at.x),
+            _ => at.x,
+        };
+        let found = if kind.is_forward() {
+            matches
+                .filter(|(idx, _)| *idx > from)
+                .nth(count.saturating_sub(1))?
+                .0
+        } else {
+            matches
+                .rev()
+                .filter(|(idx, _)| *idx < from)
+                .nth(count.saturating_sub(1))?
+                .0
+        };
+
+        Some(match kind {
+            FindKind::Forward | FindKind::Backward => found,
+            FindKind::TillForward => row.prev_boundary(found),
+            FindKind::TillBackward => row.next_boundary(found),
+        })
+    }
+
+    // Resolves targets which cover a region within the document into that region
+    pub(crate) fn target_span(
+        &self,
+        at: &Position,
+        target: &TextTarget,
+        count: usize,
+    ) -> Option<Span> {
+        let row = self.rows.get(at.y);
+        match target {
+            TextTarget::Span(span) => Some(*span),
+            TextTarget::Object(object, scope) => self.object_span(at, *object, *scope, count),
+            TextTarget::Char(kind, c, repeat) => {
+                let found = self.find_char(at, *kind, *c, count, *repeat)?;
+                // forward searches include the character landed on, backward ones stop before the cursor
+                let (start, end) = if kind.is_forward() {
+                    (at.x, row?.next_boundary(found))
+                } else {
+                    (found, at.x)
+                };
+                Some(Span {
+                    start: Position { x: start, y: at.y },
+                    end: Position { x: end, y: at.y },
+                })
+            }
+            TextTarget::UnderCursor => {
+                // stays within the row unlike remove_ahead
+                let row = row?;
+                let mut end = cmp::min(at.x, row.len());
+                for _ in 0..count {
+                    end = row.next_boundary(end);
+                }
+                Some(Span {
+                    start: *at,
+                    end: Position { x: end, y: at.y },
+                })
+            }
+            TextTarget::BeforeCursor => {
+                let row = row?;
+                let mut start = cmp::min(at.x, row.len());
+                for _ in 0..count {
+                    start = row.prev_boundary(start);
+                }
+                Some(Span {
+                    start: Position { x: start, y: at.y },
+                    end: *at,
+                })
+            }
+            TextTarget::RowAfterCursor => Some(Span {
+                start: *at,
+                end: Position {
+                    x: row?.len(),
+                    y: at.y,
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    fn object_span(
+        &self,
+        at: &Position,
+        object: TextObject,
+        scope: ObjectScope,
+        count: usize,
+    ) -> Option<Span> {
+        match object {
+            TextObject::Paragraph => return self.paragraph_span(at, scope, count),
+            TextObject::Quote(quote) => {
+                // quotes never span more than the current row
+                let row = self.rows.get(at.y)?;
+                let line: Vec<char> = row.string.chars().collect();
+                let col = row.string.get(..at.x).unwrap_or_default().chars().count();
+                let (start, end) = quote_range(&line, col, quote, scope)?;
+                let byte = |idx: usize| line[..idx].iter().map(|c| c.len_utf8()).sum();
+                return Some(Span {
+                    start: Position {
+                        x: byte(start),
+                        y: at.y,
+                    },
+                    end: Position {
+                        x: byte(end),
+                        y: at.y,
+                    },
+                });
+            }
+            _ => {}
+        }
+
+        let (text, positions) = self.flatten();
+        let idx = positions
+            .iter()
+            .position(|pos| pos >= at)
+            .unwrap_or(text.len());
+        let (start, end) = match object {
+            TextObject::Word => word_range(&text, idx, false, scope, count),
+            TextObject::BigWord => word_range(&text, idx, true, scope, count),
+            TextObject::Sentence => sentence_range(&text, idx, scope, count),
+            TextObject::Bracket(open, close) => {
+                bracket_range(&text, idx, open, close, scope, count)
+            }
+            TextObject::Tag => tag_range(&text, idx, scope, count),
+            TextObject::Paragraph
+            | TextObject::Quote(_)
+            | TextObject::Function
+            | TextObject::Class
+            | TextObject::Argument => None,
+        }?;
+        Some(Span {
+            start: positions[start],
+            end: positions[end],
+        })
+    }
+
+    // Paragraphs are whole rows, either a run of text rows or a run of blank ones
+    fn paragraph_span(&self, at: &Position, scope: ObjectScope, count: usize) -> Option<Span> {
+        if at.y >= self.rows.len() {
+            return None;
+        }
+        let blank = |y: usize| self.rows[y].string.trim().is_empty();
+        let run_end = |y: usize| {
+            let mut end = y;
+            while end + 1 < self.rows.len() && blank(end + 1) == blank(y) {
+                end += 1;
+            }
+            end
+        };
+
+        let mut start = at.y;
+        while start > 0 && blank(start - 1) == blank(at.y) {
+            start -= 1;
+        }
+        let mut end = run_end(start);
+        for _ in 1..count {
+            if end + 1 >= self.rows.len() {
+                break;
+            }
+            end = run_end(end + 1);
+        }
+        if scope == ObjectScope::Around {
+            if end + 1 < self.rows.len() && blank(end + 1) != blank(end) {
+                end = run_end(end + 1);
+            } else if !blank(start) {
+                while start > 0 && blank(start - 1) {
+                    start -= 1;
+                }
+            }
+        }
+
+        let mut span = Span {
+            start: Position { x: 0, y: start },
+            end: Position { x: 0, y: end + 1 },
+        };
+        if end + 1 >= self.rows.len() {
+            // no row after to end on, so take the line break before instead
+            span.end = Position {
+                x: self.rows[end].len(),
+                y: end,
+            };
+            if start > 0 {
+                span.start = Position {
+                    x: self.rows[start - 1].len(),
+                    y: start - 1,
+                };
+            }
+        }
+        Some(span)
+    }
+
+    // The document as chars with '\n' between rows, alongside where each char is
+    // The positions has one extra entry for the end of the document
+    fn flatten(&self) -> (Vec<char>, Vec<Position>) {
+        let mut text = vec![];
+        let mut positions = vec![];
+        for (y, row) in self.rows.iter().enumerate() {
+            if y > 0 {
+                text.push('\n');
+                positions.push(Position {
+                    x: self.rows[y - 1].len(),
+                    y: y - 1,
+                });
+            }
+            for (x, c) in row.string.char_indices() {
+                text.push(c);
+                positions.push(Position { x, y });
+            }
+        }
+        positions.push(match self.rows.last() {
+            Some(row) => Position {
+                x: row.len(),
+                y: self.rows.len() - 1,
+            },
+            None => Position::default(),
+        });
+        (text, positions)
+    }
+
+    // The text covered by the span, rows joined with '\n'
+    pub(crate) fn span_text(&self, span: &Span) -> String {
+        let mut lines = vec![];
+        for y in span.start.y..=cmp::min(span.end.y, self.rows.len().saturating_sub(1)) {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            let start = if y == span.start.y { span.start.x } else { 0 };
+            let end = if y == span.end.y {
+                span.end.x
+            } else {
+                row.len()
+            };
+            let end = cmp::min(end, row.len());
+            lines.push(
+                row.string
+                    .get(cmp::min(start, end)..end)
+                    .unwrap_or_default(),
+            );
+        }
+        if span.end.y >= self.rows.len() && span.end.y > span.start.y {
+            // the span runs off the last row, so it ended with a line break
+            lines.push("");
+        }
+        lines.join("\n")
+    }
+
+    pub(crate) fn delete_span(&mut self, span: &Span) -> String {
+        if span.start.y >= self.rows.len() {
+            return String::new();
+        }
+        let deleted = self.span_text(span);
+
+        let tail = match self.rows.get(span.end.y) {
+            Some(row) => row.string.get(span.end.x..).unwrap_or_default().to_string(),
+            None => String::new(),
+        };
+        let last = cmp::min(span.end.y, self.rows.len() - 1);
+        self.rows.drain(span.start.y + 1..=last);
+        let row = &mut self.rows[span.start.y];
+        row.string.truncate(span.start.x);
+        row.string += &tail;
+
+        self.rows_changed(span.start.y, last + 1, span.start.y + 1);
+        let (start, end) = (span.start, span.end);
+        self.adjust_marks(|pos| {
+            if pos.y <= start.y {
+                Some(pos)
+            } else if pos.y > last {
+                Some(Position {
+                    x: pos.x,
+                    y: pos.y - (last - start.y),
+                })
+            } else if pos.y == end.y && pos.x >= end.x {
+                // on the tail which moved up onto the start row
+                Some(Position {
+                    x: start.x + pos.x - end.x,
+                    y: start.y,
+                })
+            } else {
+                None
+            }
+        });
+        deleted
+    }
+
+    // The position just past the character at `at`, moving onto the next row at the end of one
+    pub(crate) fn after(&self, at: &Position) -> Position {
+        let Some(row) = self.rows.get(at.y) else {
+            return *at;
+        };
+        if at.x < row.len() {
+            Position {
+                x: row.next_boundary(at.x),
+                y: at.y,
+            }
+        } else if at.y + 1 < self.rows.len() {
+            Position { x: 0, y: at.y + 1 }
+        } else {
+            Position {
+                x: row.len(),
+                y: at.y,
+            }
+        }
+    }
+
+    // The position of the character before `at`, moving onto the previous row at the start of one
+    pub(crate) fn before(&self, at: &Position) -> Position {
+        if at.x > 0 {
+            let x = match self.rows.get(at.y) {
+                Some(row) => row.prev_boundary(cmp::min(at.x, row.len())),
+                None => at.x - 1,
+            };
+            Position { x, y: at.y }
+        } else if at.y > 0 {
+            Position {
+                x: self.rows.get(at.y - 1).map_or(0, |row| row.len()),
+                y: at.y - 1,
+            }
+        } else {
+            *at
+        }
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.rows.is_empty()
     }
@@ -178,17 +1299,228 @@ impl Document {
         self.rows.len()
     }
 
+    // Inserts text which may hold line breaks, returning the position just after it
+    pub(crate) fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        let mut lines = text.split('\n');
+        // unwrap - split always gives atleast one item
+        let first = lines.next().unwrap();
+        self.insert_str(at, first);
+        let mut end = Position {
+            x: at.x + first.len(),
+            y: at.y,
+        };
+        for line in lines {
+            self.add_line(&end);
+            end = Position { x: 0, y: end.y + 1 };
+            self.insert_str(&end, line);
+            end.x = line.len();
+        }
+        end
+    }
+
+    // Inserts each line of text as its own row starting at row y
+    pub(crate) fn insert_rows(&mut self, y: usize, text: &str) {
+        let y = cmp::min(y, self.rows.len());
+        let rows: Vec<Row> = text.split('\n').map(Row::from).collect();
+        let n = rows.len();
+        self.rows.splice(y..y, rows);
+        self.rows_inserted(y, n);
+    }
+
     pub(crate) fn insert_str(&mut self, at: &Position, str: &str) {
-        let row = match self.rows.get_mut(at.y) {
-            Some(row) => row,
-            None => {
-                self.rows.insert(at.y, Row::default());
-                // unwrap - insertion prior so it should be there
-                self.rows.get_mut(at.y).unwrap()
+        if at.y >= self.rows.len() {
+            self.rows.insert(at.y, Row::default());
+            self.rows_inserted(at.y, 1);
+        }
+        // unwrap - insertion prior so it should be there
+        let row = self.rows.get_mut(at.y).unwrap();
+        row.string.insert_str(at.x, str);
+        self.row_edited(at.y);
+    }
+
+    // Replaces count characters from at with c, returning the position of the last one
+    // Nothing changes when the row does not have count characters left
+    pub(crate) fn replace_chars(
+        &mut self,
+        at: &Position,
+        c: char,
+        count: usize,
+    ) -> Option<Position> {
+        let row = self.rows.get_mut(at.y)?;
+        let rest = row.string.get(at.x..)?;
+        if rest.chars().count() < count {
+            return None;
+        }
+        let len: usize = rest.chars().take(count).map(char::len_utf8).sum();
+        row.string
+            .replace_range(at.x..at.x + len, &c.to_string().repeat(count));
+        self.row_edited(at.y);
+        Some(Position {
+            x: at.x + c.len_utf8() * (count - 1),
+            y: at.y,
+        })
+    }
+
+    // Types c over the character at `at`, returning the character it replaced
+    // At the end of a row c is added instead and there is nothing to return
+    pub(crate) fn overwrite(&mut self, at: &Position, c: char) -> Option<char> {
+        let Some(row) = self.rows.get_mut(at.y) else {
+            self.insert(at, c);
+            return None;
+        };
+        let old = row.string.get(at.x..).and_then(|rest| rest.chars().next());
+        match old {
+            Some(old) => row
+                .string
+                .replace_range(at.x..at.x + old.len_utf8(), &c.to_string()),
+            None => row.string.insert(at.x, c),
+        }
+        self.row_edited(at.y);
+        old
+    }
+
+    // Indents row y by one level, or outdents by up to one, returning how far its text moved
+    pub(crate) fn shift_row(&mut self, y: usize, right: bool) -> isize {
+        let width = self.indent_width(y);
+        let width = match right {
+            true => width + self.options.shift_width,
+            false => width.saturating_sub(self.options.shift_width),
+        };
+        self.set_indent(y, width)
+    }
+
+    // Comments out the rows, or uncomments them if every one with text is already commented
+    // Markers line up at the smallest indent and blank rows are left alone
+    // None when the filetype has no comments, otherwise whether the rows are now commented
+    pub(crate) fn toggle_comment(&mut self, rows: Range<usize>) -> Option<bool> {
+        let (open, close) = match self.filetype.comment_style()? {
+            CommentStyle::Line(leader) => (leader, ""),
+            CommentStyle::Block(open, close) => (open, close),
+        };
+        let rows = rows.start..cmp::min(rows.end, self.rows.len());
+        let text_rows: Vec<usize> = rows
+            .clone()
+            .filter(|&y| !self.rows[y].string.trim().is_empty())
+            .collect();
+        let commented = !text_rows.is_empty()
+            && text_rows.iter().all(|&y| {
+                let text = self.rows[y].string.trim();
+                text.len() >= open.len() + close.len()
+                    && text.starts_with(open)
+                    && text.ends_with(close)
+            });
+
+        if commented {
+            for &y in &text_rows {
+                let indent = self.first_non_blank(y);
+                let row = &mut self.rows[y].string;
+                let text = row.trim_end()[indent + open.len()..].to_string();
+                let text = text.strip_prefix(' ').unwrap_or(&text);
+                let text = text.strip_suffix(close).unwrap_or(text);
+                let text = match close.is_empty() {
+                    true => text,
+                    false => text.strip_suffix(' ').unwrap_or(text),
+                };
+                row.replace_range(indent.., text);
+            }
+        } else {
+            let Some(column) = text_rows.iter().map(|&y| self.first_non_blank(y)).min() else {
+                return Some(false);
+            };
+            for &y in &text_rows {
+                let row = &mut self.rows[y].string;
+                row.insert_str(column, &format!("{} ", open));
+                if !close.is_empty() {
+                    row.push_str(&format!(" {}", close));
+                }
+            }
+        }
+        self.rows_changed(rows.start, rows.end, rows.end);
+        Some(!commented)
+    }
+
+    // The word the position is on and the column it starts at
+    pub(crate) fn word_at(&self, at: &Position) -> Option<(usize, &str)> {
+        let row = &self.rows.get(at.y)?.string;
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if !row.get(at.x..)?.starts_with(is_word) {
+            return None;
+        }
+        let start = row[..at.x]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word(*c))
+            .last()
+            .map_or(at.x, |(i, _)| i);
+        let end = row[at.x..]
+            .find(|c| !is_word(c))
+            .map_or(row.len(), |i| at.x + i);
+        Some((start, &row[start..end]))
+    }
+
+    // Where word next shows up by itself after the position, going round to the top at the end
+    pub(crate) fn find_word(&self, after: &Position, word: &str) -> Option<Position> {
+        self.rows.get(after.y)?;
+        let pattern = Regex::new(&format!(r"\b{}\b", regex::escape(word))).ok()?;
+        let rows = self.rows.len();
+        (0..=rows).find_map(|i| {
+            let y = (after.y + i) % rows;
+            let row = &self.rows[y].string;
+            let mut found = pattern.find_iter(row).map(|m| m.start());
+            match i {
+                0 => found.find(|&x| x > after.x),
+                // back round on the starting row, only what is before the position
+                _ if i == rows => found.find(|&x| x <= after.x),
+                _ => found.next(),
             }
+            .map(|x| Position { x, y })
+        })
+    }
+
+    // The start of every match of the pattern inside the span
+    pub(crate) fn find_in_span(&self, span: &Span, pattern: &Regex) -> Vec<Position> {
+        let mut found = vec![];
+        for y in span.start.y..cmp::min(span.end.y + 1, self.rows.len()) {
+            let Some(columns) = span.columns_on(y) else {
+                continue;
+            };
+            let row = &self.rows[y].string;
+            let end = cmp::min(columns.end, row.len());
+            let Some(text) = row.get(..end) else {
+                continue;
+            };
+            found.extend(
+                pattern
+                    .find_iter(text)
+                    .filter(|m| m.start() >= columns.start && !m.is_empty())
+                    .map(|m| Position { x: m.start(), y }),
+            );
+        }
+        found
+    }
+
+    // The column a word before `at` starts on, skipping any whitespace right before it
+    pub(crate) fn word_start_before(&self, at: &Position) -> usize {
+        let Some(before) = self.rows.get(at.y).and_then(|row| row.string.get(..at.x)) else {
+            return 0;
+        };
+        let trimmed = before.trim_end();
+        let Some(last) = trimmed.chars().next_back() else {
+            return 0;
         };
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let word = is_word(last);
+        trimmed
+            .trim_end_matches(|c: char| !c.is_whitespace() && is_word(c) == word)
+            .len()
+    }
 
-        row.string.insert_str(at.x, str);
+    // The column of the first character on row y which is not whitespace
+    pub(crate) fn first_non_blank(&self, y: usize) -> usize {
+        match self.rows.get(y) {
+            Some(row) => row.string.len() - row.string.trim_start().len(),
+            None => 0,
+        }
     }
 
     pub(crate) fn current_row_length(&self, at: &Position) -> usize {
@@ -197,26 +1529,235 @@ impl Document {
             None => 0,
         }
     }
+
+    pub(crate) fn row_len(&self, y: usize) -> usize {
+        self.current_row_length(&Position { x: 0, y })
+    }
 }
 
-#[derive(Default)]
+// The first whole number in the text, rows without one sort first
+fn first_number(text: &Option<String>) -> Option<i64> {
+    let text = text.as_deref()?;
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let negative = text[..start].ends_with('-');
+    let digits: String = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    let number = digits.parse().unwrap_or(i64::MAX);
+    Some(if negative { -number } else { number })
+}
+
+// How many brackets a row opens and closes, leaving out any inside quotes
+fn bracket_counts(text: &str) -> (usize, usize) {
+    let (mut opens, mut closes) = (0, 0);
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '{' | '(' | '[') => opens += 1,
+            (None, '}' | ')' | ']') => closes += 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+    (opens, closes)
+}
+
+// A region of the document from start up to but not including end
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    // The byte columns of row y inside the span, past the row length when its line break is included
+    pub fn columns_on(&self, y: usize) -> Option<Range<usize>> {
+        if y < self.start.y || y > self.end.y {
+            return None;
+        }
+        let start = if y == self.start.y { self.start.x } else { 0 };
+        let end = if y == self.end.y {
+            self.end.x
+        } else {
+            usize::MAX
+        };
+        Some(start..end)
+    }
+}
+
+#[derive(Default, Clone, PartialEq)]
 pub struct Row {
     string: String,
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let output = self.string.get(start..end).unwrap_or_default().to_string();
+    // Renders the columns in the colours of its tokens
+    // Highlighted columns are drawn reversed, showing the line break when they go past the end
+    pub fn render(
+        &self,
+        columns: Range<usize>,
+        highlights: &[Range<usize>],
+        tokens: &[Token],
+        theme: &Theme,
+        list: bool,
+        tab_stop: usize,
+    ) -> String {
+        let end = cmp::min(columns.end, self.string.len());
+        let start = cmp::min(columns.start, end);
 
-        // config: visible spaces
-        output.replace(" ", &".".dim().to_string())
+        // every column where the colour or highlight can change
+        let mut cuts = vec![start, end];
+        for columns in highlights
+            .iter()
+            .chain(tokens.iter().map(|token| &token.columns))
+        {
+            cuts.push(columns.start.clamp(start, end));
+            cuts.push(columns.end.clamp(start, end));
+        }
+        cuts.sort();
+        cuts.dedup();
+
+        let mut output = String::new();
+        // tabs reach the tab stop after the screen column they start at
+        let mut column = self.column_of(start, tab_stop);
+        for pair in cuts.windows(2) {
+            let Some(text) = self.string.get(pair[0]..pair[1]) else {
+                continue;
+            };
+            let color = tokens
+                .iter()
+                .find(|token| token.columns.contains(&pair[0]))
+                .and_then(|token| theme.color(token.scope));
+            let selected = highlights.iter().any(|columns| columns.contains(&pair[0]));
+            let text = Text {
+                text,
+                color,
+                selected,
+            };
+            output += &render_text(text, theme, list, &mut column, tab_stop);
+        }
+        let len = self.string.len();
+        if end == len
+            && highlights
+                .iter()
+                .any(|columns| columns.end > len && columns.start <= len)
+        {
+            output += &" ".reverse().to_string();
+        }
+        output
+    }
+
+    // Where each screen line starts when the row is wrapped to width columns
+    // Lines after the first leave room for showbreak, with linebreak they end after a space if one fits
+    pub fn wrap_starts(
+        &self,
+        width: usize,
+        showbreak: usize,
+        linebreak: bool,
+        tab_stop: usize,
+    ) -> Vec<usize> {
+        let mut starts = vec![0];
+        // the screen columns of the row where the current line starts and where it is up to
+        let (mut start_column, mut column) = (0, 0);
+        for (idx, c) in self.string.char_indices() {
+            let room = match starts.len() {
+                1 => width,
+                _ => width.saturating_sub(showbreak),
+            }
+            .max(1);
+            let next = column + char_width(c, column, tab_stop);
+            // always at least a character a line
+            if column > start_column && next - start_column > room {
+                let start = starts[starts.len() - 1];
+                let mut end = idx;
+                if linebreak {
+                    if let Some(space) = self.string[start..end].rfind(' ') {
+                        end = start + space + 1;
+                    }
+                }
+                starts.push(end);
+                start_column = self.column_of(end, tab_stop);
+            }
+            column = next;
+        }
+        starts
+    }
+
+    // The screen column the character at x starts at, counting from the row's start
+    pub fn column_of(&self, x: usize, tab_stop: usize) -> usize {
+        let mut column = 0;
+        for c in self.string.get(..x).unwrap_or(&self.string).chars() {
+            column += char_width(c, column, tab_stop);
+        }
+        column
+    }
+
+    // The character covering a screen column, the row's length when it is past the end
+    pub fn byte_at(&self, target: usize, tab_stop: usize) -> usize {
+        let mut column = 0;
+        for (idx, c) in self.string.char_indices() {
+            column += char_width(c, column, tab_stop);
+            if column > target {
+                return idx;
+            }
+        }
+        self.string.len()
+    }
+
+    // The characters lying wholly within the screen columns
+    pub fn visible(&self, columns: Range<usize>, tab_stop: usize) -> Range<usize> {
+        let (mut start, mut end) = (self.string.len(), self.string.len());
+        let mut column = 0;
+        for (idx, c) in self.string.char_indices() {
+            let next = column + char_width(c, column, tab_stop);
+            if column >= columns.start && start == self.string.len() {
+                start = idx;
+            }
+            if next > columns.end {
+                end = idx;
+                break;
+            }
+            column = next;
+        }
+        start..cmp::max(start, end)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
     }
 
     pub fn len(&self) -> usize {
         self.string.len()
     }
+
+    // byte index of the character following the one starting at idx
+    fn next_boundary(&self, idx: usize) -> usize {
+        match self.string.get(idx..).and_then(|rest| rest.chars().next()) {
+            Some(c) => idx + c.len_utf8(),
+            None => self.string.len(),
+        }
+    }
+
+    // byte index of the character preceding the one starting at idx
+    fn prev_boundary(&self, idx: usize) -> usize {
+        match self
+            .string
+            .get(..idx)
+            .and_then(|before| before.chars().next_back())
+        {
+            Some(c) => idx - c.len_utf8(),
+            None => 0,
+        }
+    }
 }
 
 impl From<&str> for Row {
@@ -229,8 +1770,491 @@ impl From<&str> for Row {
 
 impl From<String> for Row {
     fn from(string: String) -> Self {
-        Self {
-            string: string.into(),
+        Self { string }
+    }
+}
+
+// Columns a character takes when it starts at the given column, a tab going to the next tab stop
+fn char_width(c: char, column: usize, tab_stop: usize) -> usize {
+    match c {
+        '\t' => {
+            let tab_stop = cmp::max(tab_stop, 1);
+            tab_stop - column % tab_stop
+        }
+        _ => 1,
+    }
+}
+
+// A run of a row drawn in one colour
+struct Text<'a> {
+    text: &'a str,
+    color: Option<Color>,
+    selected: bool,
+}
+
+// Draws text in one colour, selected text reversed and otherwise with visible spaces and tabs when list is set
+// Tabs are drawn as spaces to their tab stop, column being the screen column the text starts at
+fn render_text(
+    run: Text,
+    theme: &Theme,
+    list: bool,
+    column: &mut usize,
+    tab_stop: usize,
+) -> String {
+    let styled = |text: &str| {
+        let mut styled = text.to_string().stylize();
+        if let Some(color) = run.color {
+            styled = styled.with(color);
+        }
+        match run.selected {
+            true => styled.reverse().to_string(),
+            false => styled.to_string(),
+        }
+    };
+
+    let mut output = String::new();
+    let mut rest = run.text;
+    while !rest.is_empty() {
+        let blank = rest.find(|c| c != ' ' && c != '\t').unwrap_or(rest.len());
+        for c in rest[..blank].chars() {
+            let width = char_width(c, *column, tab_stop);
+            *column += width;
+            let shown = match (list && !run.selected, c) {
+                (false, _) => " ".repeat(width),
+                (true, '\t') => ">".to_string() + &" ".repeat(width - 1),
+                (true, _) => ".".to_string(),
+            };
+            output += &match list && !run.selected {
+                true => theme.paint(Ui::Whitespace, &shown),
+                false => styled(&shown),
+            };
         }
+        rest = &rest[blank..];
+        let word = rest.find([' ', '\t']).unwrap_or(rest.len());
+        if word > 0 {
+            *column += rest[..word].chars().count();
+            output += &styled(&rest[..word]);
+        }
+        rest = &rest[word..];
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signs::SignKind;
+
+    fn document(lines: &[&str]) -> Document {
+        Document {
+            rows: lines.iter().map(|&line| Row::from(line)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        document.rows.iter().map(Row::as_str).collect()
+    }
+
+    #[test]
+    fn sort_rows() {
+        let mut doc = document(&["b10", "a2", "B1", "a2"]);
+        doc.sort_rows(0..4, &SortOptions::default()).unwrap();
+        assert_eq!(lines(&doc), ["B1", "a2", "a2", "b10"]);
+
+        let options = SortOptions {
+            numeric: true,
+            unique: true,
+            reverse: true,
+            ..Default::default()
+        };
+        doc.sort_rows(0..4, &options).unwrap();
+        assert_eq!(lines(&doc), ["b10", "a2", "B1"]);
+
+        // rows the pattern misses stay ahead in their order
+        let mut doc = document(&["x", "k=2", "y", "k=1"]);
+        let options = SortOptions {
+            pattern: Some("k=(\\d)".to_string()),
+            ..Default::default()
+        };
+        doc.sort_rows(0..4, &options).unwrap();
+        assert_eq!(lines(&doc), ["x", "y", "k=1", "k=2"]);
+    }
+
+    fn increment(line: &str, x: usize, amount: i64) -> (String, Option<usize>) {
+        let mut doc = document(&[line]);
+        let last = doc.increment_number(&Position { x, y: 0 }, usize::MAX, amount);
+        (doc.rows[0].as_str().to_string(), last)
+    }
+
+    #[test]
+    fn increment_decimal() {
+        assert_eq!(increment("x = 9;", 0, 1), ("x = 10;".to_string(), Some(5)));
+        // the number under or after the cursor, not one before it
+        assert_eq!(increment("1 and 2", 2, 5).0, "1 and 7");
+        assert_eq!(
+            increment("no digits", 0, 1),
+            ("no digits".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn increment_negative() {
+        assert_eq!(increment("-3", 0, 5), ("2".to_string(), Some(0)));
+        assert_eq!(increment("x-1", 0, -1).0, "x-2");
+        assert_eq!(increment("0", 0, -1).0, "-1");
+    }
+
+    #[test]
+    fn increment_zero_padding() {
+        assert_eq!(increment("007", 0, 1).0, "008");
+        assert_eq!(increment("099", 0, 1).0, "100");
+        assert_eq!(increment("010", 0, -11).0, "-001");
+    }
+
+    #[test]
+    fn increment_hex_and_binary() {
+        assert_eq!(increment("0xff", 0, 1), ("0x100".to_string(), Some(4)));
+        // the case of the digits is kept
+        assert_eq!(increment("0x0F", 0, 1).0, "0x10");
+        assert_eq!(increment("0x0a", 0, 1).0, "0x0b");
+        assert_eq!(increment("0b0111", 0, 1).0, "0b1000");
+        // hex and binary wrap around rather than going negative
+        assert_eq!(increment("0x0", 0, -1).0, "0xffffffffffffffff");
+        assert_eq!(increment("0b0", 0, -1).0, format!("0b{}", "1".repeat(64)));
+    }
+
+    #[test]
+    fn increment_within_limit() {
+        let mut doc = document(&["a 1 b 2"]);
+        assert!(doc
+            .increment_number(&Position { x: 2, y: 0 }, 2, 1)
+            .is_none());
+        assert_eq!(
+            doc.increment_number(&Position { x: 2, y: 0 }, 3, 1),
+            Some(2)
+        );
+        assert_eq!(lines(&doc), ["a 2 b 2"]);
+    }
+
+    #[test]
+    fn wrap_starts() {
+        let row = Row::from("abcdefghij");
+        assert_eq!(row.wrap_starts(4, 0, false, 4), [0, 4, 8]);
+        // lines after the first lose the showbreak's width
+        assert_eq!(row.wrap_starts(4, 2, false, 4), [0, 4, 6, 8]);
+        // always at least a character a line
+        assert_eq!(row.wrap_starts(2, 4, false, 4), [0, 2, 3, 4, 5, 6, 7, 8, 9]);
+        // a row that just fits is one line
+        assert_eq!(row.wrap_starts(10, 0, false, 4), [0]);
+        assert_eq!(Row::from("").wrap_starts(4, 0, false, 4), [0]);
+    }
+
+    #[test]
+    fn wrap_starts_by_chars() {
+        // byte starts, split by characters rather than bytes
+        let row = Row::from("éééé");
+        assert_eq!(row.wrap_starts(2, 0, false, 4), [0, 4]);
+        assert_eq!(Row::from("aé日b").wrap_starts(1, 0, false, 4), [0, 1, 3, 6]);
+    }
+
+    #[test]
+    fn wrap_starts_at_spaces() {
+        let row = Row::from("foo bar baz");
+        assert_eq!(row.wrap_starts(6, 0, false, 4), [0, 6]);
+        assert_eq!(row.wrap_starts(6, 0, true, 4), [0, 4, 8]);
+        // without a space the line is cut where it runs out
+        assert_eq!(Row::from("abcdefgh").wrap_starts(3, 0, true, 4), [0, 3, 6]);
+    }
+
+    #[test]
+    fn tabs_by_column() {
+        let row = Row::from("\ta\tb");
+        // a tab runs to the next tab stop
+        assert_eq!(row.column_of(1, 4), 4);
+        assert_eq!(row.column_of(2, 4), 5);
+        assert_eq!(row.column_of(3, 4), 8);
+        assert_eq!(row.byte_at(3, 4), 0);
+        assert_eq!(row.byte_at(4, 4), 1);
+        assert_eq!(row.byte_at(6, 4), 2);
+        assert_eq!(row.byte_at(20, 4), 4);
+        // only whole characters are shown
+        assert_eq!(row.visible(0..5, 4), 0..2);
+        assert_eq!(row.visible(2..9, 4), 1..4);
+        assert_eq!(row.wrap_starts(6, 0, false, 4), [0, 2]);
+    }
+
+    #[test]
+    fn open_keeps_tabs() {
+        let path = std::env::temp_dir().join("textright-open-keeps-tabs.txt");
+        fs::write(&path, "\tab\n").unwrap();
+        let name = path.to_str().unwrap();
+        let options = DocumentOptions {
+            expand_tab: false,
+            ..Default::default()
+        };
+        assert_eq!(lines(&Document::open(name, options)), ["\tab"]);
+        assert_eq!(
+            lines(&Document::open(name, DocumentOptions::default())),
+            ["    ab"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn shift_rows() {
+        let mut doc = document(&["a", "", "  b"]);
+        doc.shift_rows(0..3, true, 1);
+        assert_eq!(lines(&doc), ["    a", "", "      b"]);
+        doc.shift_rows(0..3, false, 2);
+        assert_eq!(lines(&doc), ["a", "", "b"]);
+
+        // without expandtab whole tab stops become tabs
+        let mut doc = document(&["a", "  b", "\tc"]);
+        doc.options = DocumentOptions {
+            shift_width: 2,
+            expand_tab: false,
+            ..Default::default()
+        };
+        doc.shift_rows(0..3, true, 1);
+        assert_eq!(lines(&doc), ["  a", "\tb", "\t  c"]);
+        doc.shift_rows(0..3, false, 1);
+        assert_eq!(lines(&doc), ["a", "  b", "\tc"]);
+    }
+
+    #[test]
+    fn reindent_by_braces() {
+        let mut doc = document(&["fn a() {", "if b {", "c();", "  }", "   }"]);
+        doc.filetype = Filetype::Rust;
+        doc.reindent(1..5);
+        assert_eq!(
+            lines(&doc),
+            ["fn a() {", "    if b {", "        c();", "    }", "}"]
+        );
+
+        let mut doc = document(&["fn a() {", "b();", "}"]);
+        doc.filetype = Filetype::Rust;
+        doc.options.expand_tab = false;
+        doc.reindent(1..3);
+        assert_eq!(lines(&doc), ["fn a() {", "\tb();", "}"]);
+    }
+
+    #[test]
+    fn repeat_till_search() {
+        let doc = document(&["axbxc"]);
+        let find = |x, kind, repeat| doc.find_char(&Position { x, y: 0 }, kind, 'x', 1, repeat);
+        // tx beside an x stays put, ; goes on to the next one
+        assert_eq!(find(0, FindKind::TillForward, false), Some(0));
+        assert_eq!(find(0, FindKind::TillForward, true), Some(2));
+        assert_eq!(find(2, FindKind::TillForward, true), None);
+        assert_eq!(find(4, FindKind::TillBackward, false), Some(4));
+        assert_eq!(find(4, FindKind::TillBackward, true), Some(2));
+        // f has no gap to skip
+        assert_eq!(find(1, FindKind::Forward, true), Some(3));
+    }
+
+    #[test]
+    fn delete_nothing_and_all() {
+        let mut doc = document(&["a", "b"]);
+        let mut at = Position { x: 1, y: 1 };
+        assert_eq!(doc.delete(&mut at, &TextTarget::Nothing, 1), "");
+        assert_eq!(lines(&doc), ["a", "b"]);
+        assert_eq!(doc.delete(&mut at, &TextTarget::All, 1), "a\nb");
+        assert!(doc.rows.is_empty());
+        assert_eq!(at.y, 0);
+    }
+
+    #[test]
+    fn insert_text_over_rows() {
+        let mut doc = document(&["ab"]);
+        let end = doc.insert_text(&Position { x: 1, y: 0 }, "1\n22\n333");
+        assert_eq!(lines(&doc), ["a1", "22", "333b"]);
+        assert!(end == Position { x: 3, y: 2 });
+
+        let end = doc.insert_text(&Position { x: 0, y: 1 }, "");
+        assert!(end == Position { x: 0, y: 1 });
+        let end = doc.insert_text(&Position { x: 2, y: 1 }, "\n");
+        assert_eq!(lines(&doc), ["a1", "22", "", "333b"]);
+        assert!(end == Position { x: 0, y: 2 });
+    }
+
+    #[test]
+    fn marks_follow_their_text() {
+        let mut doc = document(&["one", "two", "three", "four"]);
+        doc.set_mark('a', Position { x: 1, y: 1 });
+        doc.set_mark('b', Position { x: 4, y: 2 });
+        doc.set_mark('c', Position { x: 0, y: 3 });
+
+        doc.add_line(&Position { x: 0, y: 0 });
+        assert_eq!(lines(&doc), ["", "one", "two", "three", "four"]);
+        // splitting a row takes the marks after the split along with their text
+        doc.add_line(&Position { x: 2, y: 3 });
+        assert_eq!(lines(&doc), ["", "one", "two", "th", "ree", "four"]);
+        assert!(doc.marks[&'a'] == Position { x: 1, y: 2 });
+        assert!(doc.marks[&'b'] == Position { x: 2, y: 4 });
+
+        // joining moves them back along the row
+        doc.join_rows(3, 2, false);
+        assert!(doc.marks[&'b'] == Position { x: 4, y: 3 });
+
+        // a mark goes with its deleted row, those below move up
+        let mut at = Position { x: 0, y: 2 };
+        doc.delete(&mut at, &TextTarget::WholeRow, 1);
+        assert_eq!(lines(&doc), ["", "one", "three", "four"]);
+        assert!(!doc.marks.contains_key(&'a'));
+        assert!(doc.marks[&'c'] == Position { x: 0, y: 3 });
+    }
+
+    #[test]
+    fn word_start_before() {
+        let doc = document(&["let ab_c = x.y  "]);
+        let start = |x| doc.word_start_before(&Position { x, y: 0 });
+        assert_eq!(start(8), 4);
+        // whitespace before the cursor is skipped, punctuation is a word of its own
+        assert_eq!(start(9), 4);
+        assert_eq!(start(13), 12);
+        assert_eq!(start(16), 13);
+        assert_eq!(start(3), 0);
+        assert_eq!(start(0), 0);
+    }
+
+    #[test]
+    fn shift_row() {
+        let mut doc = document(&["  a", "b"]);
+        assert_eq!(doc.shift_row(0, true), 4);
+        assert_eq!(doc.shift_row(0, false), -4);
+        assert_eq!(doc.shift_row(0, false), -2);
+        assert_eq!(doc.shift_row(1, false), 0);
+        assert_eq!(lines(&doc), ["a", "b"]);
+    }
+
+    #[test]
+    fn new_lines_keep_the_indent() {
+        let mut doc = document(&["  a {}", "    b"]);
+        doc.filetype = Filetype::Rust;
+        // a bracket closed right after the cursor goes on a row of its own
+        let at = doc.add_line_indented(&Position { x: 5, y: 0 });
+        assert_eq!(lines(&doc), ["  a {", "      ", "  }", "    b"]);
+        assert!(at == Position { x: 6, y: 1 });
+
+        // enter again on the untouched indent leaves the row above empty
+        let at = doc.add_line_indented(&at);
+        assert_eq!(lines(&doc), ["  a {", "", "      ", "  }", "    b"]);
+        doc.clear_auto_indent(at.y);
+        assert_eq!(lines(&doc), ["  a {", "", "", "  }", "    b"]);
+
+        // rows after other text take its indent
+        let at = doc.add_line_indented(&Position { x: 5, y: 4 });
+        assert_eq!(lines(&doc)[5], "    ");
+        assert!(at == Position { x: 4, y: 5 });
+        // once typed on, the indent is kept
+        doc.insert(&at, 'c');
+        doc.clear_auto_indent(5);
+        assert_eq!(lines(&doc)[5], "    c");
+    }
+
+    #[test]
+    fn blocks_open_by_filetype() {
+        let mut doc = document(&["if a:", "  f(", "b {"]);
+        doc.filetype = Filetype::Python;
+        assert_eq!(doc.indent_for_line_after(0, 5), "    ");
+        assert_eq!(doc.indent_for_line_after(1, 4), "      ");
+        // only the text before the cursor counts
+        assert_eq!(doc.indent_for_line_after(1, 3), "  ");
+
+        // bracketed files do not open blocks with :
+        doc.filetype = Filetype::Rust;
+        assert_eq!(doc.indent_for_line_after(0, 5), "");
+        assert_eq!(doc.indent_for_line_after(2, 3), "    ");
+        doc.options.expand_tab = false;
+        assert_eq!(doc.indent_for_line_after(2, 3), "\t");
+    }
+
+    #[test]
+    fn toggle_comments() {
+        let mut doc = document(&["    a", "", "  b"]);
+        doc.filetype = Filetype::Rust;
+        // markers line up at the smallest indent, blank rows stay blank
+        assert_eq!(doc.toggle_comment(0..3), Some(true));
+        assert_eq!(lines(&doc), ["  //   a", "", "  // b"]);
+        assert_eq!(doc.toggle_comment(0..3), Some(false));
+        assert_eq!(lines(&doc), ["    a", "", "  b"]);
+
+        // a row without the marker comments them all
+        let mut doc = document(&["# a", "b"]);
+        doc.filetype = Filetype::Python;
+        assert_eq!(doc.toggle_comment(0..2), Some(true));
+        assert_eq!(lines(&doc), ["# # a", "# b"]);
+
+        let mut doc = document(&["<p>"]);
+        doc.filetype = Filetype::Html;
+        doc.toggle_comment(0..1);
+        assert_eq!(lines(&doc), ["<!-- <p> -->"]);
+        doc.toggle_comment(0..1);
+        assert_eq!(lines(&doc), ["<p>"]);
+
+        doc.filetype = Filetype::Json;
+        assert_eq!(doc.toggle_comment(0..1), None);
+    }
+
+    #[test]
+    fn git_signs_follow_their_rows() {
+        let mut doc = document(&["a", "b", "c", "d"]);
+        let sign = |text: &str| Sign {
+            kind: SignKind::Changed,
+            text: text.to_string(),
+        };
+        doc.git_signs = BTreeMap::from([(1, sign("1")), (2, sign("2")), (3, sign("3"))]);
+        let rows = |doc: &Document| doc.git_signs.keys().copied().collect::<Vec<_>>();
+
+        doc.insert_rows(0, "new");
+        assert_eq!(rows(&doc), [2, 3, 4]);
+        let mut at = Position { x: 0, y: 2 };
+        doc.delete(&mut at, &TextTarget::WholeRow, 1);
+        assert_eq!(rows(&doc), [2, 3]);
+        // a joined row keeps the sign already on the row it joins
+        doc.join_rows(2, 2, true);
+        assert_eq!(rows(&doc), [2]);
+        assert_eq!(doc.git_signs[&2].text, "2");
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut doc = document(&["one", "two", "three"]);
+        doc.marks.insert('a', Position { x: 1, y: 2 });
+        let start = Position { x: 0, y: 1 };
+
+        // edits at several places between begin and end come undone together
+        doc.begin_change(start);
+        doc.insert_text(&Position { x: 0, y: 0 }, "1 ");
+        doc.insert_text(&Position { x: 3, y: 1 }, "!");
+        doc.insert_rows(1, "new");
+        doc.end_change();
+        assert_eq!(lines(&doc), ["1 one", "new", "two!", "three"]);
+        assert_eq!(doc.marks[&'a'].y, 3);
+
+        // a change which edits nothing is not kept
+        doc.begin_change(Position::default());
+        doc.end_change();
+
+        let end = Position { x: 2, y: 2 };
+        assert!(doc.undo(end) == Some(start));
+        assert_eq!(lines(&doc), ["one", "two", "three"]);
+        assert_eq!(doc.marks[&'a'].y, 2);
+        assert!(doc.undo(start).is_none());
+
+        assert!(doc.redo(start) == Some(end));
+        assert_eq!(lines(&doc), ["1 one", "new", "two!", "three"]);
+        assert!(doc.redo(end).is_none());
+
+        // a new change after an undo drops what could be redone
+        doc.undo(end);
+        doc.begin_change(start);
+        doc.remove_behind(&mut Position { x: 1, y: 0 });
+        doc.end_change();
+        assert_eq!(lines(&doc), ["ne", "two", "three"]);
+        assert!(doc.redo(start).is_none());
+        doc.undo(start);
+        assert_eq!(lines(&doc), ["one", "two", "three"]);
     }
 }
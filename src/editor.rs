@@ -1,19 +1,56 @@
-use std::{cmp, env, fmt::Display, process::exit, time::Duration};
+use std::{
+    cmp,
+    collections::VecDeque,
+    env,
+    fmt::Display,
+    io,
+    ops::Range,
+    path::PathBuf,
+    process::exit,
+    time::{Duration, Instant},
+};
 
 use chrono::Local;
 use crossterm::{
-    event::{poll, read, Event, KeyCode, KeyEventKind},
+    event::{poll, read, DisableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind},
+    execute,
     style::Stylize,
     terminal::disable_raw_mode,
 };
+use regex::Regex;
 
 use crate::{
-    document::Document,
-    modal::{Direction, InputAction, InputMode, ModalInputter, NormalInput, VerticalDirection},
+    clipboard::ClipboardProvider,
+    config::Config,
+    document::{Document, DocumentOptions, Span},
+    highlight::Highlighter,
+    keybinds::{
+        key_to_notation, parse_keys, parse_map_to, parse_mapped_keys, same_key, Lookup, MapTo,
+        Mapping,
+    },
+    line_range::{Address, LineAddress, LineRange},
+    marks::{absolute_path, FileMarks},
+    modal::{
+        named_action, Direction, InputAction, InputEvent, InputMode, InsertAt, ModalInputter,
+        NormalInput, VerticalDirection,
+    },
+};
+use crate::{
+    registers::{Register, Registers, StoreKind},
+    signs::{git_signs, mark_signs, SignSource, Signs},
+    status_line::{self, GitBranch, StatusInfo},
+    status_message::StatusMessage,
+    syntax::Syntax,
+    terminal::Terminal,
+    text_target::{TextObject, TextTarget},
+    theme::{Theme, Ui},
 };
-use crate::{status_message::StatusMessage, terminal::Terminal};
 
 const EDITOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+// how deep macros can call other macros, stops a macro calling itself forever
+const MAX_MACRO_DEPTH: usize = 100;
+// how deep mappings can expand into other mappings
+const MAX_MAP_DEPTH: usize = 100;
 
 pub struct Editor {
     should_quit: bool,
@@ -21,24 +58,71 @@ pub struct Editor {
 
     terminal: Terminal,
     cursor: Position,
+    // more cursors besides the main one, typing, edits and motions happen at all of them
+    cursors: Vec<Position>,
+    // while editing at every cursor, which one is being edited counting from the top and how many there are
+    cursor_turn: Option<(usize, usize)>,
+    // what each cursor put in a register meanwhile, stored as one once all are done
+    taken: Vec<(Option<char>, Register, StoreKind)>,
 
     document: Document,
+    // the top row in view, and without wrap the first screen column
     offset: Position,
+    // screen lines of the top row scrolled out of view, when it is wrapped
+    offset_line: usize,
+    highlighter: Highlighter,
+    theme: Theme,
+    config: Config,
+    git_branch: GitBranch,
+    signs: Signs,
 
     input: ModalInputter,
-    yanked: String,
+    registers: Registers,
+    // the fixed end of the visual selection, the cursor being the other
+    visual_anchor: Position,
+    // selections from before each syntax node expansion, None where it started in normal mode
+    expansions: Vec<Option<(Position, Position)>>,
+    // characters written over in replace mode, None where the row was extended instead
+    replaced: Vec<Option<char>>,
+
+    // input being replayed by . or a macro, read before the terminal
+    // each with whether the keymap applies to it, keys from a noremap mapping skip it
+    replay_queue: VecDeque<(InputEvent, bool)>,
+    replay_depth: usize,
+    last_macro: Option<char>,
+    map_depth: usize,
 
+    // typed keys which could still become a mapping, and when the last of them came
+    pending_keys: Vec<KeyEvent>,
+    pending_since: Instant,
+
+    // A-Z marks in every file, the current document holds its own copies
+    file_marks: FileMarks,
+    // where large motions left from, Ctrl-o and Ctrl-i move through it
+    jumps: Vec<Position>,
+    jump_idx: usize,
+
+    // a titled listing drawn over the bottom of the rows until the next key
+    listing: Option<(String, Vec<String>)>,
     status_message: StatusMessage,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
-impl Position {
-    // displays the position in the file, 1-based
-    fn file_position(&self) -> String {
-        format!("{:2}, {:2}", self.x + 1, self.y + 1)
+
+// positions are ordered as they appear in the document, row first
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -61,12 +145,31 @@ impl From<(usize, usize)> for Position {
 impl Default for Editor {
     fn default() -> Self {
         let args: Vec<String> = env::args().collect();
+        let file_marks = FileMarks::load();
+        let mut config = Config::default();
+        let mut errors = vec![];
+        if let Some(path) = Config::path().filter(|path| path.exists()) {
+            errors.extend(config.source(&path).err());
+        }
+        let theme = Theme::load(&config.theme).unwrap_or_else(|err| {
+            errors.push(err);
+            Theme::default()
+        });
+        config.theme = theme.name().to_string();
         let document = if args.len() > 1 {
-            let file_name = &args[1];
-            Document::open(file_name)
+            open_with_marks(&args[1], &file_marks, config.document.clone())
         } else {
-            Document::default()
+            Document {
+                options: config.document.clone(),
+                ..Default::default()
+            }
         };
+        let mut registers = Registers::default();
+        registers.set_clipboard(clipboard_provider(&config));
+        let message = errors
+            .into_iter()
+            .next()
+            .unwrap_or("Welcome to Textist".to_string());
 
         Self {
             should_quit: false,
@@ -74,10 +177,32 @@ impl Default for Editor {
             terminal: Terminal::setup().expect("Problem initializing terminal for editor."),
             document,
             cursor: Position { x: 0, y: 0 },
+            cursors: vec![],
+            cursor_turn: None,
+            taken: vec![],
             offset: Position { x: 0, y: 0 },
-            status_message: StatusMessage::new("Welcome to Textist".to_string()),
+            offset_line: 0,
+            highlighter: Highlighter::default(),
+            theme,
+            config,
+            git_branch: GitBranch::default(),
+            signs: Signs::default(),
+            listing: None,
+            status_message: StatusMessage::new(message),
             input: ModalInputter::default(),
-            yanked: "".to_string(),
+            registers,
+            visual_anchor: Position::default(),
+            expansions: vec![],
+            replaced: vec![],
+            replay_queue: VecDeque::new(),
+            replay_depth: 0,
+            last_macro: None,
+            map_depth: 0,
+            pending_keys: vec![],
+            pending_since: Instant::now(),
+            file_marks,
+            jumps: vec![],
+            jump_idx: 0,
         }
     }
 }
@@ -90,25 +215,45 @@ impl Editor {
                 self.dirty = false;
             }
             // where to draw the cursor on screen
-            Terminal::move_cursor(&Position {
-                x: self.cursor.x.saturating_sub(self.offset.x),
-                y: self.cursor.y.saturating_sub(self.offset.y),
-            });
+            Terminal::move_cursor(&self.cursor_on_screen());
 
-            if poll(Duration::from_millis(200)).unwrap() {
-                let read = match read() {
-                    Ok(read) => read,
-                    Err(e) => panic!("{}", e),
+            let timeout = Duration::from_millis(self.config.timeout_len as u64);
+            let wait = match self.pending_keys.is_empty() {
+                true => Duration::from_millis(200),
+                false => timeout.saturating_sub(self.pending_since.elapsed()),
+            };
+            if poll(wait).unwrap() {
+                let Some(read) = self.next_event() else {
+                    continue;
                 };
+                self.document.begin_change(self.cursor);
                 match read {
-                    Event::Key(ev_key) => {
-                        let action = self.input.process_key_press(ev_key);
+                    // the key that puts a listing away does nothing else
+                    Event::Key(ev_key) if self.listing.is_some() => {
+                        if ev_key.kind == KeyEventKind::Press {
+                            self.listing = None;
+                            self.status_message.reset(Some(String::new()));
+                        }
+                    }
+                    Event::Key(ev_key) => self.press_key(ev_key),
+                    Event::Resize(width, height) => self.resize(width, height),
+                    Event::Paste(text) => {
+                        self.flush_keys();
+                        let action = self.input.process_paste(text);
                         self.handle_action(action);
-                        self.dirty = true;
-                        self.pull_view_to_cursor();
                     }
-                    _ => {}
+                    _ => continue,
                 }
+                self.end_change();
+                self.dirty = true;
+                self.pull_view_to_cursor();
+            } else if !self.pending_keys.is_empty() && self.pending_since.elapsed() >= timeout {
+                // nothing more came to finish the mapping, so the keys are taken as they are
+                self.document.begin_change(self.cursor);
+                self.flush_keys();
+                self.end_change();
+                self.dirty = true;
+                self.pull_view_to_cursor();
             } else {
                 // no events found
             }
@@ -116,106 +261,1067 @@ impl Editor {
     }
 
     fn handle_action(&mut self, action: InputAction) {
+        if !self.cursors.is_empty() {
+            // a selection belongs to the main cursor alone
+            if action.is_per_cursor() && !self.input.is_visual() {
+                self.at_each_cursor(|editor| editor.handle_action(action.clone()));
+                return;
+            }
+            if action.drops_cursors() {
+                self.cursors.clear();
+                self.status_message
+                    .reset(Some("Extra cursors cleared".to_string()));
+            }
+        }
         match action {
             InputAction::NoAction => {}
-            InputAction::InvalidCommand => {}
+            InputAction::InvalidCommand => self
+                .status_message
+                .reset(Some("Not an editor command".to_string())),
             InputAction::Save => {
                 self.save_document();
             }
             InputAction::Quit => {
                 self.should_quit = true;
             }
-            InputAction::MoveCursor { direction, count } => self.move_cursor(direction, count),
+            InputAction::MoveCursor { direction, count } => {
+                self.replaced.clear();
+                self.move_cursor(direction, count)
+            }
+            InputAction::MoveScreenLine { direction, count } => {
+                self.replaced.clear();
+                self.move_screen_line(direction, count)
+            }
             InputAction::InsertChar(c) => {
+                if matches!(c, ')' | ']' | '}')
+                    && self.cursor.x > 0
+                    && self.cursor.x <= self.document.first_non_blank(self.cursor.y)
+                {
+                    // a closing bracket typed on its own goes back out to the block's level
+                    let moved = self.document.shift_row(self.cursor.y, false);
+                    self.cursor.x = self.cursor.x.saturating_add_signed(moved);
+                }
                 self.document.insert(&self.cursor, c);
                 self.move_cursor(Direction::Right, 1);
             }
+            InputAction::InsertText(text) => {
+                // pasted tabs are kept unless the document indents with spaces
+                let text = match self.document.options.expand_tab {
+                    true => text.replace('\t', &" ".repeat(self.document.options.tab_stop)),
+                    false => text,
+                };
+                self.cursor = self.document.insert_text(&self.cursor, &text);
+            }
+            InputAction::InsertTab => {
+                let tab = match self.document.options.expand_tab {
+                    true => {
+                        let tab_stop = cmp::max(self.document.options.tab_stop, 1);
+                        let column = self.cursor_column();
+                        " ".repeat(tab_stop - column % tab_stop)
+                    }
+                    false => "\t".to_string(),
+                };
+                self.document.insert_str(&self.cursor, &tab);
+                self.cursor.x += tab.len();
+            }
+            InputAction::InsertRegister(name) => match self.registers.get(Some(name)) {
+                Ok(Some(register)) => {
+                    let linewise = register.linewise;
+                    let mut text = self.text_at_cursor(register);
+                    if linewise {
+                        text.push('\n');
+                    }
+                    self.cursor = self.document.insert_text(&self.cursor, &text);
+                }
+                Ok(None) => {}
+                Err(err) => self
+                    .status_message
+                    .reset(Some(format!("Unable to read clipboard: {}", err))),
+            },
+            InputAction::Insert(at) => {
+                match at {
+                    InsertAt::Cursor => {}
+                    InsertAt::AfterCursor => {
+                        let after = self.document.after(&self.cursor);
+                        self.cursor.x = match after.y == self.cursor.y {
+                            true => after.x,
+                            false => self.document.current_row_length(&self.cursor),
+                        };
+                    }
+                    InsertAt::FirstNonBlank => {
+                        self.cursor.x = self.document.first_non_blank(self.cursor.y)
+                    }
+                    InsertAt::RowEnd => {
+                        self.cursor.x = self.document.current_row_length(&self.cursor)
+                    }
+                    InsertAt::LastInsert => {
+                        if let Some(pos) = self.document.marks.get(&'^') {
+                            self.cursor = *pos;
+                            self.bound_cursor_to_line();
+                        }
+                    }
+                }
+                self.input.switch(InputMode::Insert);
+            }
+            InputAction::FinishInsert(events) => {
+                let replaying = self.input.is_replaying();
+                self.input.set_replaying(true);
+                self.replay(events);
+                self.input.set_replaying(replaying);
+                self.at_each_cursor(|editor| {
+                    editor.document.clear_auto_indent(editor.cursor.y);
+                    editor.bound_cursor_to_line();
+                });
+                // indents left behind on other rows are kept, like anything else typed
+                self.document.auto_indented.clear();
+                self.document.set_mark('^', self.cursor);
+                self.input.switch(InputMode::Normal(NormalInput::default()));
+            }
+            InputAction::ThenInsert(action) => {
+                self.handle_action(*action);
+                self.input.switch(InputMode::Insert);
+            }
+            InputAction::DeleteWordBehind | InputAction::DeleteToRowStart => {
+                if self.cursor.x == 0 {
+                    self.document.remove_behind(&mut self.cursor);
+                    return;
+                }
+                let first_non_blank = self.document.first_non_blank(self.cursor.y);
+                let x = match action {
+                    InputAction::DeleteWordBehind => self.document.word_start_before(&self.cursor),
+                    _ if self.cursor.x > first_non_blank => first_non_blank,
+                    _ => 0,
+                };
+                self.document.delete_span(&Span {
+                    start: Position {
+                        x,
+                        y: self.cursor.y,
+                    },
+                    end: self.cursor,
+                });
+                self.cursor.x = x;
+            }
+            InputAction::ShiftRows { right, count } => {
+                for y in self.cursor.y..self.cursor.y + count {
+                    let moved = self.document.shift_row(y, right);
+                    if y == self.cursor.y {
+                        self.cursor.x = self.cursor.x.saturating_add_signed(moved);
+                    }
+                }
+            }
+            InputAction::MoveToRowStart => self.cursor.x = 0,
+            InputAction::MoveToRowEnd => {
+                self.cursor.x = self.document.current_row_length(&self.cursor)
+            }
+            InputAction::Page(direction) => {
+                let page = (self.terminal.size.height as usize)
+                    .saturating_sub(3)
+                    .max(1);
+                match direction {
+                    VerticalDirection::Up => self.move_cursor(Direction::Up, page),
+                    VerticalDirection::Down => self.move_cursor(Direction::Down, page),
+                }
+            }
             InputAction::NewLine { count } => {
-                self.document.add_line(&self.cursor);
-                self.move_cursor(Direction::Down, 1);
-                self.cursor.x = 0;
+                self.replaced.clear();
+                for _ in 0..count {
+                    self.cursor = self.document.add_line_indented(&self.cursor);
+                }
             }
             InputAction::DeleteBehind { count } => {
-                self.document.remove_behind(&mut self.cursor);
+                for _ in 0..count {
+                    self.document.remove_behind(&mut self.cursor);
+                }
             }
             InputAction::DeleteAhead { count } => {
-                self.document.remove_ahead(&mut self.cursor);
+                for _ in 0..count {
+                    self.document.remove_ahead(&mut self.cursor);
+                }
             }
             InputAction::SwitchMode(new_mode) => {
+                match new_mode {
+                    InputMode::Visual(_) => self.visual_anchor = self.cursor,
+                    InputMode::Replace => self.replaced.clear(),
+                    _ => {}
+                }
                 self.input.switch(new_mode);
             }
             InputAction::SaveAndQuit => {
                 self.save_document();
                 self.should_quit = true;
             }
-            InputAction::NewLineAndInsert(vertical_direction) => match vertical_direction {
-                VerticalDirection::Up => {
-                    self.document.add_line_with_spaces_to_cursor(&self.cursor);
-                    self.input.switch(InputMode::Insert);
-                }
-                VerticalDirection::Down => {
-                    self.move_cursor(Direction::Down, 1);
-                    self.document.add_line_with_spaces_to_cursor(&self.cursor);
-                    self.input.switch(InputMode::Insert);
-                }
-            },
+            InputAction::NewLineAndInsert(vertical_direction) => {
+                let y = self.cursor.y;
+                self.cursor = match vertical_direction {
+                    VerticalDirection::Up => {
+                        let indent = self.document.indent_of(y);
+                        self.document.add_indented_line(y, &indent)
+                    }
+                    VerticalDirection::Down => {
+                        let len = self.document.current_row_length(&self.cursor);
+                        let indent = self.document.indent_for_line_after(y, len);
+                        self.document.add_indented_line(y + 1, &indent)
+                    }
+                };
+                self.input.switch(InputMode::Insert);
+            }
             InputAction::CommandPrompt => {
+                // a selection is given to the command as the lines between '< and '>
+                let mut start = None;
+                if self.input.is_visual() {
+                    let span = self.selection_span();
+                    self.document.set_mark('<', span.start);
+                    self.document.set_mark('>', self.document.before(&span.end));
+                    start = Some("'<,'>");
+                }
                 self.input.switch(InputMode::Command);
-                self.draw_status_bar();
-                let action = match self.prompt(":", None) {
+                if self.replay_depth == 0 {
+                    self.draw_status_bar();
+                }
+                let action = match self.prompt(":", start) {
                     Some(cmd_input) => self.input.evaluate_cmd_input(&cmd_input),
                     None => InputAction::NoAction,
                 };
                 self.handle_action(action);
                 self.input.switch(InputMode::Normal(NormalInput::default()));
             }
-            InputAction::PasteYanked(direction) => match direction {
-                Direction::Left => {
-                    self.document.insert_str(&self.cursor, &self.yanked);
-                    self.move_cursor(Direction::Right, self.yanked.len());
+            InputAction::Paste {
+                direction,
+                count,
+                register,
+            } => self.paste(direction, count, register),
+            InputAction::Delete {
+                target,
+                count,
+                register,
+            } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                let text = self.document.delete(&mut self.cursor, &target, count);
+                self.store_register(register, text, target.is_linewise(), StoreKind::Delete);
+                self.bound_cursor_to_line();
+            }
+            InputAction::Change {
+                target,
+                count,
+                register,
+            } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                let text = self.document.delete(&mut self.cursor, &target, count);
+                self.store_register(register, text, target.is_linewise(), StoreKind::Delete);
+                if target.is_linewise() {
+                    // the rows are emptied rather than removed
+                    let y = cmp::min(self.cursor.y, self.document.len());
+                    self.document.insert_rows(y, "");
+                    self.cursor = Position { x: 0, y };
+                }
+                self.bound_cursor_to_line();
+                self.input.switch(InputMode::Insert);
+            }
+            InputAction::Shift {
+                right,
+                target,
+                count,
+            } => {
+                // a count on a selection shifts it that many times instead
+                let (levels, count) = match target {
+                    TextTarget::Selection => (count, 1),
+                    _ => (1, count),
+                };
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                if let Some(rows) = self.document.target_rows(&self.cursor, &target, count) {
+                    let y = rows.start;
+                    self.document.shift_rows(rows, right, levels);
+                    self.cursor = Position {
+                        x: self.document.first_non_blank(y),
+                        y,
+                    };
                 }
-                Direction::Right => {
-                    self.move_cursor(Direction::Right, 1);
-                    self.document.insert_str(&self.cursor, &self.yanked);
-                    self.move_cursor(Direction::Right, self.yanked.len() - 1);
+            }
+            InputAction::Reindent { target, count } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                if let Some(rows) = self.document.target_rows(&self.cursor, &target, count) {
+                    let y = rows.start;
+                    self.document.reindent(rows);
+                    self.cursor = Position {
+                        x: self.document.first_non_blank(y),
+                        y,
+                    };
                 }
-                _ => {}
+            }
+            InputAction::Join {
+                target,
+                count,
+                spaces,
+            } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                if let Some(rows) = self.document.target_rows(&self.cursor, &target, count) {
+                    let count = cmp::max(rows.len(), 2);
+                    if let Some(x) = self.document.join_rows(rows.start, count, spaces) {
+                        self.cursor = Position { x, y: rows.start };
+                    }
+                }
+            }
+            InputAction::MoveRows { range, to } => {
+                let (Some(rows), Some(to)) =
+                    (self.resolve_range(range, false), self.resolve_address(to))
+                else {
+                    self.status_message.reset(Some("Invalid range".to_string()));
+                    return;
+                };
+                if !self.document.move_rows(rows.clone(), to) {
+                    self.status_message
+                        .reset(Some("Cannot move a range of lines into itself".to_string()));
+                    return;
+                }
+                let last = match to >= rows.end {
+                    true => to - 1,
+                    false => to + rows.len() - 1,
+                };
+                self.cursor = Position {
+                    x: self.document.first_non_blank(last),
+                    y: last,
+                };
+            }
+            InputAction::CopyRows { range, to } => {
+                let (Some(rows), Some(to)) =
+                    (self.resolve_range(range, false), self.resolve_address(to))
+                else {
+                    self.status_message.reset(Some("Invalid range".to_string()));
+                    return;
+                };
+                let last = to + rows.len() - 1;
+                self.document.copy_rows(rows, to);
+                self.cursor = Position {
+                    x: self.document.first_non_blank(last),
+                    y: last,
+                };
+            }
+            InputAction::SortRows { range, options } => {
+                let Some(rows) = self.resolve_range(range, true) else {
+                    self.status_message.reset(Some("Invalid range".to_string()));
+                    return;
+                };
+                let y = rows.start;
+                if let Err(err) = self.document.sort_rows(rows, &options) {
+                    self.status_message
+                        .reset(Some(format!("Invalid pattern: {}", err)));
+                    return;
+                }
+                self.cursor = Position {
+                    x: self.document.first_non_blank(y),
+                    y,
+                };
+            }
+            InputAction::UniqRows { range } => {
+                let Some(rows) = self.resolve_range(range, true) else {
+                    self.status_message.reset(Some("Invalid range".to_string()));
+                    return;
+                };
+                let removed = self.document.uniq_rows(rows);
+                self.status_message
+                    .reset(Some(format!("{} fewer lines", removed)));
+                self.bound_cursor_to_line();
+            }
+            InputAction::GotoRange(range) => match self.resolve_address(range.end) {
+                Some(line) => {
+                    self.record_jump();
+                    let y = line.saturating_sub(1);
+                    self.cursor = Position {
+                        x: self.document.first_non_blank(y),
+                        y,
+                    };
+                }
+                None => self.status_message.reset(Some("Invalid range".to_string())),
             },
-            InputAction::Delete(target) => {
-                self.yanked = self.document.delete(&mut self.cursor, &target);
+            InputAction::AddCursorAtNext => {
+                let Some((x, word)) = self.document.word_at(&self.cursor) else {
+                    self.status_message
+                        .reset(Some("No word under cursor".to_string()));
+                    return;
+                };
+                let (start, text) = (
+                    Position {
+                        x,
+                        y: self.cursor.y,
+                    },
+                    word.to_string(),
+                );
+                let found = self.document.find_word(&start, &text).map(|at| Position {
+                    // the same place in the word as the cursor
+                    x: at.x + self.cursor.x - start.x,
+                    y: at.y,
+                });
+                match found {
+                    Some(at) if at != self.cursor && !self.cursors.contains(&at) => {
+                        self.cursors.push(self.cursor);
+                        self.cursor = at;
+                    }
+                    _ => self
+                        .status_message
+                        .reset(Some(format!("No more matches for {}", text))),
+                }
+            }
+            InputAction::CursorsOnRows => {
+                let span = self.selection_span();
+                self.input.switch(InputMode::Normal(NormalInput::default()));
+                let end = match span.end.x == 0 && span.end.y > span.start.y {
+                    true => span.end.y,
+                    false => span.end.y + 1,
+                };
+                let mut rows = (span.start.y..end).map(|y| Position {
+                    x: cmp::min(span.start.x, self.document.row_len(y)),
+                    y,
+                });
+                if let Some(first) = rows.next() {
+                    self.cursor = first;
+                    self.cursors = rows.collect();
+                }
+            }
+            InputAction::SplitSelection => {
+                let span = self.selection_span();
+                self.input.switch(InputMode::Normal(NormalInput::default()));
+                let Some(pattern) = self.prompt("split: ", None).filter(|p| !p.is_empty()) else {
+                    self.status_message.reset(None);
+                    return;
+                };
+                let pattern = match Regex::new(&pattern) {
+                    Ok(pattern) => pattern,
+                    Err(err) => {
+                        self.status_message
+                            .reset(Some(format!("Invalid pattern: {}", err)));
+                        return;
+                    }
+                };
+                let mut found = self.document.find_in_span(&span, &pattern).into_iter();
+                match found.next() {
+                    Some(first) => {
+                        self.cursor = first;
+                        self.cursors = found.collect();
+                        self.status_message.reset(None);
+                    }
+                    None => {
+                        self.cursor = span.start;
+                        self.status_message
+                            .reset(Some("Pattern not found".to_string()));
+                    }
+                }
+            }
+            InputAction::ClearCursors => self.cursors.clear(),
+            InputAction::Comment { target, count } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                let Some(rows) = self.document.target_rows(&self.cursor, &target, count) else {
+                    return;
+                };
+                let y = rows.start;
+                if self.document.toggle_comment(rows).is_none() {
+                    self.status_message.reset(Some(format!(
+                        "No comment syntax for {} files",
+                        self.document.filetype.name()
+                    )));
+                    return;
+                }
+                self.cursor = Position {
+                    x: self.document.first_non_blank(y),
+                    y,
+                };
+            }
+            InputAction::ChangeCase {
+                case,
+                target,
+                count,
+            } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                let span = match target.is_linewise() {
+                    true => self
+                        .document
+                        .target_rows(&self.cursor, &target, count)
+                        .map(|rows| Span {
+                            start: Position {
+                                x: 0,
+                                y: rows.start,
+                            },
+                            end: Position {
+                                x: usize::MAX,
+                                y: rows.end.saturating_sub(1),
+                            },
+                        }),
+                    false => self.document.target_span(&self.cursor, &target, count),
+                };
+                if let Some(span) = span {
+                    self.document.change_case(&span, case);
+                    self.cursor = match target {
+                        // ~ steps past what it changed
+                        TextTarget::UnderCursor => span.end,
+                        _ => span.start,
+                    };
+                    self.bound_cursor_to_line();
+                }
+            }
+            InputAction::Increment {
+                amount,
+                progressive,
+            } => {
+                if !self.input.is_visual() {
+                    if let Some(x) =
+                        self.document
+                            .increment_number(&self.cursor, usize::MAX, amount)
+                    {
+                        self.cursor.x = x;
+                    }
+                    return;
+                }
+                let span = self.selection_span();
+                self.input.switch(InputMode::Normal(NormalInput::default()));
+                let mut step = amount;
+                // the selection is charwise, rows between its first and last are taken whole
+                for y in span.start.y..=span.end.y {
+                    let Some(columns) = span.columns_on(y) else {
+                        continue;
+                    };
+                    let at = Position {
+                        x: columns.start,
+                        y,
+                    };
+                    if self
+                        .document
+                        .increment_number(&at, columns.end, step)
+                        .is_some()
+                        && progressive
+                    {
+                        step += amount;
+                    }
+                }
+                self.cursor = span.start;
+            }
+            InputAction::ReplaceChar { c, count } => {
+                if let Some(last) = self.document.replace_chars(&self.cursor, c, count) {
+                    self.cursor = last;
+                }
+            }
+            InputAction::OverwriteChar(c) => {
+                let old = self.document.overwrite(&self.cursor, c);
+                self.replaced.push(old);
+                self.cursor.x += c.len_utf8();
+            }
+            InputAction::RestoreBehind => match self.replaced.pop() {
+                Some(Some(old)) => {
+                    self.cursor = self.document.before(&self.cursor);
+                    self.document.overwrite(&self.cursor, old);
+                }
+                Some(None) => self.document.remove_behind(&mut self.cursor),
+                None => self.move_cursor(Direction::Left, 1),
+            },
+            InputAction::Yank {
+                target,
+                count,
+                register,
+            } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                let text = self.document.target_text(&self.cursor, &target, count);
+                if let Some(span) = self.document.target_span(&self.cursor, &target, count) {
+                    self.cursor = cmp::min(self.cursor, span.start);
+                }
+                self.store_register(register, text, target.is_linewise(), StoreKind::Yank);
+            }
+            InputAction::ShowRegisters => {
+                let lines: Vec<String> = self
+                    .registers
+                    .list()
+                    .iter()
+                    .map(|(name, register)| {
+                        let kind = if register.linewise { 'l' } else { 'c' };
+                        format!(
+                            "{}  \"{}   {}",
+                            kind,
+                            name,
+                            register.text.replace('\n', "^J")
+                        )
+                    })
+                    .collect();
+                self.show_listing("Type Name Content", &lines);
+            }
+            InputAction::Jump { target, count } => self.jump_to(target, count),
+            InputAction::RepeatChange { count } => {
+                let events = self.input.change_to_repeat(count);
+                self.input.set_replaying(true);
+                self.replay(events);
+                self.input.set_replaying(false);
+            }
+            InputAction::RecordMacro(register) => self.input.start_recording(register),
+            InputAction::StopRecording => {
+                if let Some((register, events)) = self.input.stop_recording() {
+                    let text = events
+                        .iter()
+                        .map(|event| match event {
+                            InputEvent::Key(ev_key) => key_to_notation(ev_key),
+                            InputEvent::Paste(text) => {
+                                text.replace('<', "<lt>").replace('\n', "<CR>")
+                            }
+                        })
+                        .collect();
+                    self.registers.set(register, Register::new(text, false));
+                }
+            }
+            InputAction::PlayMacro { register, count } => self.play_macro(register, count),
+            InputAction::Select { target, count } => {
+                let Some(target) = self.resolve_selection(target, count) else {
+                    return;
+                };
+                if let Some(span) = self.document.target_span(&self.cursor, &target, count) {
+                    if span.start < span.end {
+                        self.visual_anchor = span.start;
+                        self.cursor = self.document.before(&span.end);
+                    }
+                }
+            }
+            InputAction::SetMark(mark) => {
+                self.document.set_mark(mark, self.cursor);
+                if mark.is_ascii_uppercase() {
+                    self.sync_file_marks();
+                }
+            }
+            InputAction::JumpToMark { mark, exact } => self.jump_to_mark(mark, exact),
+            InputAction::ColorScheme(name) if name.is_empty() => {
+                let lines: Vec<String> = Theme::names()
+                    .into_iter()
+                    .map(|name| {
+                        let current = if name == self.theme.name() { "*" } else { " " };
+                        format!("{} {}", current, name)
+                    })
+                    .collect();
+                self.show_listing("color schemes", &lines);
+            }
+            InputAction::ColorScheme(name) => match Theme::load(&name) {
+                Ok(theme) => {
+                    self.theme = theme;
+                    self.config.theme = name;
+                }
+                Err(err) => self.status_message.reset(Some(err)),
+            },
+            InputAction::SetOptions(args) if args.is_empty() || args == "all" => {
+                self.show_listing("options", &self.config.listing());
+            }
+            InputAction::SetOptions(args) => {
+                let mut config = self.config.clone();
+                let result = config
+                    .set_from_args(&args)
+                    .and_then(|shown| self.apply_config(config).map(|_| shown));
+                match result {
+                    Ok(shown) if shown.is_empty() => {}
+                    Ok(shown) => self.status_message.reset(Some(shown.join("  "))),
+                    Err(err) => self.status_message.reset(Some(err)),
+                }
+            }
+            InputAction::Source(file) => {
+                let path = match file {
+                    Some(file) => Some(PathBuf::from(file)),
+                    None => Config::path(),
+                };
+                let Some(path) = path else {
+                    self.status_message
+                        .reset(Some("No config directory to read from".to_string()));
+                    return;
+                };
+                let mut config = self.config.clone();
+                let sourced = config.source(&path);
+                if let Err(err) = self.apply_config(config).and(sourced) {
+                    self.status_message.reset(Some(err));
+                }
+            }
+            InputAction::Map {
+                modes,
+                recursive,
+                verbose,
+                args,
+            } => {
+                let (from, to) = args
+                    .split_once(char::is_whitespace)
+                    .map_or((args.as_str(), ""), |(from, to)| (from, to.trim_start()));
+                let keys = parse_mapped_keys(from, &self.config.leader);
+                if to.is_empty() {
+                    let lines = self.config.keymap.listing(&modes, &keys, verbose);
+                    match lines.is_empty() {
+                        true => self
+                            .status_message
+                            .reset(Some("No mapping found".to_string())),
+                        false => self.show_listing("mode keys         maps to", &lines),
+                    }
+                    return;
+                }
+                let to = parse_map_to(to, &self.config.leader);
+                if let MapTo::Action(name) = &to {
+                    if named_action(name).is_none() {
+                        self.status_message
+                            .reset(Some(format!("No action called {}", name)));
+                        return;
+                    }
+                }
+                for mode in modes {
+                    self.config.keymap.add(Mapping {
+                        mode,
+                        keys: keys.clone(),
+                        to: to.clone(),
+                        recursive,
+                        set_from: "the command line".to_string(),
+                    });
+                }
+            }
+            InputAction::Unmap { modes, keys } => {
+                let keys = parse_mapped_keys(&keys, &self.config.leader);
+                let mut removed = false;
+                for mode in modes {
+                    removed |= self.config.keymap.remove(mode, &keys);
+                }
+                if !removed {
+                    self.status_message
+                        .reset(Some("No such mapping".to_string()));
+                }
+            }
+            InputAction::ShowMarks => {
+                let mut marks: Vec<(char, Position, String)> = self
+                    .document
+                    .marks
+                    .iter()
+                    .map(|(name, pos)| (*name, *pos, self.document.file_name.clone()))
+                    .collect();
+                let path = absolute_path(&self.document.file_name);
+                for c in 'A'..='Z' {
+                    match self.file_marks.get(c) {
+                        Some((file, pos))
+                            if *file != path && !self.document.marks.contains_key(&c) =>
+                        {
+                            marks.push((c, *pos, file.display().to_string()))
+                        }
+                        _ => {}
+                    }
+                }
+                let lines: Vec<String> = marks
+                    .iter()
+                    .map(|(name, pos, file)| {
+                        format!("{} {:6} {:4}  {}", name, pos.y + 1, pos.x, file)
+                    })
+                    .collect();
+                self.show_listing("mark line  col  file", &lines);
+            }
+            InputAction::GotoLine(line) => {
+                self.record_jump();
+                let last = self.document.len().saturating_sub(1);
+                let y = match line {
+                    Some(line) => cmp::min(line.saturating_sub(1), last),
+                    None => last,
+                };
+                self.cursor = Position {
+                    x: self.document.first_non_blank(y),
+                    y,
+                };
+            }
+            InputAction::JumpFunction { forward, count } => {
+                let cursor = self.cursor;
+                let found = self
+                    .syntax()
+                    .and_then(|syntax| syntax.function_start(&cursor, forward, count));
+                match found {
+                    Some(at) => {
+                        self.record_jump();
+                        self.cursor = at;
+                    }
+                    None => self.no_syntax_node(),
+                }
+            }
+            InputAction::ExpandSelection => {
+                let visual = self.input.is_visual();
+                let current = match visual {
+                    true => self.selection_span(),
+                    false => Span {
+                        start: self.cursor,
+                        end: self.cursor,
+                    },
+                };
+                let Some(span) = self
+                    .syntax()
+                    .and_then(|syntax| syntax.enclosing_node(&current))
+                else {
+                    return self.no_syntax_node();
+                };
+                if !visual {
+                    self.expansions.clear();
+                    self.input.switch(InputMode::Visual(NormalInput::default()));
+                }
+                self.expansions
+                    .push(visual.then_some((self.visual_anchor, self.cursor)));
+                self.visual_anchor = span.start;
+                self.cursor = self.document.before(&span.end);
+            }
+            InputAction::ShrinkSelection => match self.expansions.pop() {
+                Some(Some((anchor, cursor))) => {
+                    self.visual_anchor = anchor;
+                    self.cursor = cursor;
+                }
+                Some(None) => {
+                    self.cursor = self.visual_anchor;
+                    self.input.switch(InputMode::Normal(NormalInput::default()));
+                }
+                None => {}
+            },
+            InputAction::JumpBack { count } => {
+                if self.jump_idx == self.jumps.len() {
+                    // remember where we were so Ctrl-i can come back
+                    self.push_jump(self.cursor);
+                    self.jump_idx = self.jumps.len() - 1;
+                }
+                if let Some(idx) = self.jump_idx.checked_sub(count) {
+                    self.jump_idx = idx;
+                    self.cursor = self.jumps[idx];
+                    self.bound_cursor_to_line();
+                }
+            }
+            InputAction::JumpForward { count } => {
+                let idx = self.jump_idx + count;
+                if idx < self.jumps.len() {
+                    self.jump_idx = idx;
+                    self.cursor = self.jumps[idx];
+                    self.bound_cursor_to_line();
+                }
+            }
+            InputAction::Undo { count } => {
+                for _ in 0..count {
+                    let Some(cursor) = self.document.undo(self.cursor) else {
+                        self.status_message
+                            .reset(Some("Already at oldest change".to_string()));
+                        break;
+                    };
+                    self.cursor = cursor;
+                }
                 self.bound_cursor_to_line();
             }
+            InputAction::Redo { count } => {
+                for _ in 0..count {
+                    let Some(cursor) = self.document.redo(self.cursor) else {
+                        self.status_message
+                            .reset(Some("Already at newest change".to_string()));
+                        break;
+                    };
+                    self.cursor = cursor;
+                }
+                self.bound_cursor_to_line();
+            }
+        }
+    }
+
+    // The 1-based line an address points at, 0 being before the first line
+    fn resolve_address(&self, address: Address) -> Option<usize> {
+        let line = match address.line {
+            LineAddress::Current => self.cursor.y + 1,
+            LineAddress::Last => self.document.len(),
+            LineAddress::Number(line) => line,
+            LineAddress::Mark(mark) => self.document.marks.get(&mark)?.y + 1,
+        };
+        let line = line.checked_add_signed(address.offset)?;
+        (line <= self.document.len()).then_some(line)
+    }
+
+    // The rows a range covers, the cursor's row or the whole document when there is no range
+    fn resolve_range(&self, range: Option<LineRange>, whole: bool) -> Option<Range<usize>> {
+        let current = Address {
+            line: LineAddress::Current,
+            offset: 0,
+        };
+        let range = match range {
+            Some(range) => range,
+            None if whole => LineRange::whole(),
+            None => LineRange {
+                start: current,
+                end: current,
+            },
+        };
+        let start = self.resolve_address(range.start)?;
+        let end = self.resolve_address(range.end)?;
+        let (start, end) = (cmp::min(start, end), cmp::max(start, end));
+        (end > 0).then(|| start.saturating_sub(1)..end)
+    }
+
+    // Remembers the cursor before a large motion, for Ctrl-o and the ' mark
+    fn record_jump(&mut self) {
+        self.push_jump(self.cursor);
+        self.jump_idx = self.jumps.len();
+        self.document.set_mark('\'', self.cursor);
+    }
+
+    // a row is only kept once in the jump list, at its newest place
+    fn push_jump(&mut self, at: Position) {
+        self.jumps.retain(|pos| pos.y != at.y);
+        self.jumps.push(at);
+    }
+
+    fn jump_to_mark(&mut self, mark: char, exact: bool) {
+        let mark = if mark == '`' { '\'' } else { mark };
+        let pos = match self.document.marks.get(&mark) {
+            Some(pos) => *pos,
+            None => {
+                let Some((file, pos)) = self.file_marks.get(mark).cloned() else {
+                    self.status_message
+                        .reset(Some(format!("Mark {} is not set", mark)));
+                    return;
+                };
+                if self.document.modified {
+                    self.status_message.reset(Some(format!(
+                        "Mark {} is in {}, save before leaving this file",
+                        mark,
+                        file.display()
+                    )));
+                    return;
+                }
+                self.document = open_with_marks(
+                    &file.to_string_lossy(),
+                    &self.file_marks,
+                    self.config.document.clone(),
+                );
+                self.highlighter = Highlighter::default();
+                self.jumps.clear();
+                self.jump_idx = 0;
+                self.offset = Position::default();
+                self.offset_line = 0;
+                self.cursor = Position::default();
+                pos
+            }
+        };
+
+        self.record_jump();
+        self.cursor = match exact {
+            true => pos,
+            false => Position {
+                x: self.document.first_non_blank(pos.y),
+                y: pos.y,
+            },
+        };
+        self.bound_cursor_to_line();
+    }
+
+    // Copies the document's A-Z marks, wherever edits have moved them, into the saved marks
+    fn sync_file_marks(&mut self) {
+        if self.document.file_name.is_empty() {
+            return;
+        }
+        let path = absolute_path(&self.document.file_name);
+        let marks = self
+            .document
+            .marks
+            .iter()
+            .filter(|(name, _)| name.is_ascii_uppercase())
+            .map(|(name, pos)| (*name, *pos));
+        self.file_marks.set_file(&path, marks);
+        if let Err(err) = self.file_marks.save() {
+            self.status_message
+                .reset(Some(format!("Unable to save marks: {}", err)));
         }
     }
 
     fn refresh_screen(&mut self) {
         Terminal::hide_cursor();
         if self.should_quit {
+            self.sync_file_marks();
             Terminal::clear_screen();
             println!("Goodbye :)");
+            let _ = execute!(io::stdout(), DisableBracketedPaste);
             let _ = disable_raw_mode();
             exit(0);
         }
 
         // NOTE: draw rows assume we are drawing from 0 -> terminal width/height
         self.draw_rows();
+        self.draw_listing();
         self.draw_status_bar();
         self.draw_status_message();
         Terminal::show_cursor();
         Terminal::flush();
     }
 
-    fn draw_rows(&self) {
+    fn draw_rows(&mut self) {
         Terminal::move_cursor(&Position { x: 0, y: 0 });
         let height = self.terminal.size.height as usize + self.offset.y;
-        let width = self.terminal.size.width as usize + self.offset.x;
+        let gutter = self.gutter_width();
+        let width = self.text_width();
+        let tab_stop = self.document.options.tab_stop;
+        let selection = self.input.is_visual().then(|| self.selection_span());
+        // wrapped rows take more than one screen line, so fewer of them fit
+        let edit = self.document.edit.take();
+        self.highlighter.update(
+            self.document.filetype,
+            &self.document.rows,
+            edit,
+            self.offset.y..height - 2,
+        );
         // 2 spaces for the status bar height
-        for i in self.offset.y..height - 2 {
+        let mut at = (self.offset.y, self.offset_line);
+        for _ in self.offset.y..height - 2 {
             Terminal::clear_line();
-            match self.document.rows.get(i as usize) {
-                Some(s) => println!("{}\r", s.render(self.offset.x, width)),
-                None => println!("~\r"),
+            let (i, line) = at;
+            let Some(s) = self.document.rows.get(i) else {
+                println!("{}\r", self.theme.paint(Ui::NonText, "~"));
+                continue;
+            };
+            let mut highlights: Vec<Range<usize>> = self
+                .cursors
+                .iter()
+                .filter(|at| at.y == i)
+                .map(|at| {
+                    let after = self.document.after(at);
+                    at.x..if after.y == i { after.x } else { usize::MAX }
+                })
+                .chain(selection.and_then(|span| span.columns_on(i)))
+                .collect();
+            highlights.sort_by_key(|columns| columns.start);
+            let starts = self.screen_lines(i);
+            let (start, end) = match self.config.wrap {
+                true => (
+                    starts.get(line).copied().unwrap_or_default(),
+                    starts.get(line + 1).copied().unwrap_or(usize::MAX),
+                ),
+                false => {
+                    let visible = s.visible(self.offset.x..self.offset.x + width, tab_stop);
+                    (visible.start, visible.end)
+                }
+            };
+            let mut gutter = match line {
+                0 => self.gutter(i),
+                _ => " ".repeat(gutter) + &self.theme.paint(Ui::NonText, &self.config.show_break),
+            };
+            if !self.config.wrap {
+                // the part of a tab scrolled past the left edge
+                gutter += &" ".repeat(s.column_of(start, tab_stop).saturating_sub(self.offset.x));
             }
+            println!(
+                "{}{}\r",
+                gutter,
+                s.render(
+                    start..end,
+                    &highlights,
+                    self.highlighter.tokens(i),
+                    &self.theme,
+                    self.config.list,
+                    tab_stop,
+                )
+            );
+            at = match line + 1 < starts.len() {
+                true => (i, line + 1),
+                false => (i + 1, 0),
+            };
         }
 
         if self.document.is_empty() {
@@ -228,10 +1334,10 @@ impl Editor {
     fn draw_welcome_message(&self, height: usize) {
         let welcome_msg = format!("Texist -- {}", EDITOR_VERSION);
         let width = self.terminal.size.width;
-        let start_left = (width / 2) - (welcome_msg.len() as u16 / 2);
+        let start_left = (width / 2).saturating_sub(welcome_msg.len() as u16 / 2);
         Terminal::move_cursor(&Position {
             x: start_left as usize,
-            y: height as usize / 3,
+            y: height / 3,
         });
         println!("{}", welcome_msg);
         Terminal::move_cursor(&Position { x: 0, y: 0 });
@@ -249,44 +1355,667 @@ impl Editor {
         self.bound_cursor_to_line();
     }
 
+    // Up or down the screen lines of wrapped rows, keeping to the same screen column
+    fn move_screen_line(&mut self, direction: VerticalDirection, count: usize) {
+        if !self.config.wrap {
+            match direction {
+                VerticalDirection::Up => self.move_cursor(Direction::Up, count),
+                VerticalDirection::Down => self.move_cursor(Direction::Down, count),
+            }
+            return;
+        }
+        let show_break = self.config.show_break.chars().count();
+        let indent = |line: usize| if line == 0 { 0 } else { show_break };
+        let (line, start) = self.cursor_line();
+        let column = indent(line) + self.columns_between(self.cursor.y, start, self.cursor.x);
+        let mut at = (self.cursor.y, line);
+        for _ in 0..count {
+            let next = match direction {
+                VerticalDirection::Up => self.previous_line(at),
+                VerticalDirection::Down => self.next_line(at),
+            };
+            let Some(next) = next else {
+                break;
+            };
+            at = next;
+        }
+
+        let (y, line) = at;
+        let starts = self.screen_lines(y);
+        let tab_stop = self.document.options.tab_stop;
+        let row = &self.document.rows[y];
+        // the last column of the screen line, which on the row's last line is the row's end
+        let last = match starts.get(line + 1) {
+            Some(end) => end - 1,
+            None => row.len(),
+        };
+        let start = row.column_of(starts[line], tab_stop);
+        let x = row.byte_at(start + column.saturating_sub(indent(line)), tab_stop);
+        let mut x = cmp::min(x, last);
+        while !row.as_str().is_char_boundary(x) {
+            x -= 1;
+        }
+        self.cursor = Position { x, y };
+        self.bound_cursor_to_line();
+    }
+
+    // Feeds recorded input back through the inputter as if it was typed
+    // Nothing is drawn until the whole replay is done
+    fn replay(&mut self, events: Vec<InputEvent>) {
+        self.replay_depth += 1;
+        self.run_queued(events.into_iter().map(|event| (event, true)).collect());
+        self.replay_depth -= 1;
+    }
+
+    // Runs the events through the replay queue, where prompts they open can read them too
+    fn run_queued(&mut self, events: Vec<(InputEvent, bool)>) {
+        // nested replays go ahead of what is left of the outer one
+        let base = self.replay_queue.len();
+        for event in events.into_iter().rev() {
+            self.replay_queue.push_front(event);
+        }
+
+        while self.replay_queue.len() > base {
+            match self.replay_queue.pop_front() {
+                Some((InputEvent::Key(ev_key), true)) => self.press_key(ev_key),
+                Some((InputEvent::Key(ev_key), false)) => {
+                    let action = self.input.process_key_press(ev_key);
+                    self.handle_action(action);
+                }
+                Some((InputEvent::Paste(text), _)) => {
+                    self.flush_keys();
+                    let action = self.input.process_paste(text);
+                    self.handle_action(action);
+                }
+                None => break,
+            }
+        }
+        // queued keys do not wait on the rest of a mapping
+        self.flush_keys();
+    }
+
+    // Passes a key through the keymap, which holds on to it while it could begin a mapping
+    // Keys replayed by . and counted inserts were already mapped when they were first typed
+    fn press_key(&mut self, ev_key: KeyEvent) {
+        if ev_key.kind != KeyEventKind::Press || self.input.is_replaying() {
+            let action = self.input.process_key_press(ev_key);
+            self.handle_action(action);
+            return;
+        }
+        let mut pending = std::mem::take(&mut self.pending_keys);
+        pending.push(ev_key);
+        self.run_keys(&mut pending, false);
+        self.pending_keys.extend(pending);
+        self.pending_since = Instant::now();
+    }
+
+    // Runs all the pending keys, mapped where they can be
+    fn flush_keys(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending_keys);
+        self.run_keys(&mut pending, true);
+    }
+
+    // Runs keys from the front of pending until what is left could still become a mapping
+    // The mode is checked before each key, as the keys before it can change it
+    fn run_keys(&mut self, pending: &mut Vec<KeyEvent>, flush: bool) {
+        while !pending.is_empty() {
+            let found = match self.input.map_mode() {
+                Some(mode) => self.config.keymap.lookup(mode, pending, flush),
+                None => Lookup::Unmapped,
+            };
+            match found {
+                Lookup::Wait => return,
+                Lookup::Unmapped => {
+                    let action = self.input.process_key_press(pending.remove(0));
+                    self.handle_action(action);
+                }
+                Lookup::Mapped(mapping) => {
+                    let mapping = mapping.clone();
+                    pending.drain(..mapping.keys.len());
+                    self.run_mapping(mapping);
+                }
+            }
+        }
+    }
+
+    fn run_mapping(&mut self, mapping: Mapping) {
+        let keys = match mapping.to {
+            MapTo::Action(name) => {
+                match named_action(&name) {
+                    Some(action) => self.handle_action(action),
+                    None => self
+                        .status_message
+                        .reset(Some(format!("No action called {}", name))),
+                }
+                return;
+            }
+            MapTo::Keys(keys) => keys,
+        };
+        if self.map_depth >= MAX_MAP_DEPTH {
+            // most likely a mapping which maps to itself, stop everything it queued
+            self.replay_queue.clear();
+            self.status_message.reset(Some(format!(
+                "Mapping stopped after {} nested mappings",
+                MAX_MAP_DEPTH
+            )));
+            return;
+        }
+        // keys it maps to starting with its own keys run those as they are, like `n` to `nzz`
+        let plain = match mapping.recursive {
+            true if keys.len() >= mapping.keys.len()
+                && keys.iter().zip(&mapping.keys).all(|(a, b)| same_key(a, b)) =>
+            {
+                mapping.keys.len()
+            }
+            true => 0,
+            false => keys.len(),
+        };
+        let events = keys
+            .into_iter()
+            .enumerate()
+            .map(|(idx, ev_key)| (InputEvent::Key(ev_key), idx >= plain))
+            .collect();
+        self.map_depth += 1;
+        self.run_queued(events);
+        self.map_depth -= 1;
+    }
+
+    // The next input, from whatever is being replayed first and the terminal after
+    fn next_event(&mut self) -> Option<Event> {
+        if let Some((event, _)) = self.replay_queue.pop_front() {
+            return Some(match event {
+                InputEvent::Key(ev_key) => Event::Key(ev_key),
+                InputEvent::Paste(text) => Event::Paste(text),
+            });
+        }
+
+        let event = match read() {
+            Ok(event) => event,
+            Err(e) => panic!("{}", e),
+        };
+        match &event {
+            Event::Key(ev_key) => self.input.record_macro_event(&InputEvent::Key(*ev_key)),
+            Event::Paste(text) => self
+                .input
+                .record_macro_event(&InputEvent::Paste(text.clone())),
+            _ => {}
+        }
+        Some(event)
+    }
+
+    fn play_macro(&mut self, register: char, count: usize) {
+        let register = match register {
+            '@' => match self.last_macro {
+                Some(register) => register,
+                None => return,
+            },
+            _ => register,
+        };
+        self.last_macro = Some(register);
+
+        if self.replay_depth >= MAX_MACRO_DEPTH {
+            // most likely a macro calling itself, stop everything left to replay
+            self.replay_queue.clear();
+            self.status_message.reset(Some(format!(
+                "Macro stopped after {} nested calls",
+                MAX_MACRO_DEPTH
+            )));
+            return;
+        }
+
+        let text = match self.registers.get(Some(register)) {
+            Ok(Some(register)) => register.text,
+            _ => return,
+        };
+        let keys = parse_keys(&text);
+        let events = (0..count)
+            .flat_map(|_| keys.iter().map(|ev_key| InputEvent::Key(*ev_key)))
+            .collect();
+        self.replay(events);
+    }
+
+    fn store_register(
+        &mut self,
+        name: Option<char>,
+        text: String,
+        linewise: bool,
+        kind: StoreKind,
+    ) {
+        let register = Register::new(text, linewise);
+        if self.cursor_turn.is_some() {
+            self.taken.push((name, register, kind));
+            return;
+        }
+        self.put_register(name, register, kind);
+    }
+
+    fn put_register(&mut self, name: Option<char>, register: Register, kind: StoreKind) {
+        if let Err(err) = self.registers.store(name, register, kind) {
+            self.status_message
+                .reset(Some(format!("Unable to copy to clipboard: {}", err)));
+        }
+    }
+
+    // The register's text, or the piece for the cursor being edited when as many cursors filled it
+    fn text_at_cursor(&self, register: Register) -> String {
+        match self.cursor_turn {
+            Some((idx, cursors)) if register.pieces.len() == cursors => {
+                register.pieces[idx].clone()
+            }
+            _ => register.text,
+        }
+    }
+
+    // Puts the register's text after or before the cursor, or below or above for whole rows
+    fn paste(&mut self, direction: Direction, count: usize, register: Option<char>) {
+        let register = match self.registers.get(register) {
+            Ok(Some(register)) => register,
+            Ok(None) => return,
+            Err(err) => {
+                self.status_message
+                    .reset(Some(format!("Unable to read clipboard: {}", err)));
+                return;
+            }
+        };
+        let linewise = register.linewise;
+        let text = self.text_at_cursor(register);
+        if text.is_empty() {
+            return;
+        }
+
+        if linewise {
+            let text = vec![text.as_str(); count].join("\n");
+            let y = match direction {
+                Direction::Left => self.cursor.y,
+                _ => cmp::min(self.cursor.y + 1, self.document.len()),
+            };
+            self.document.insert_rows(y, &text);
+            self.cursor = Position {
+                x: self.document.first_non_blank(y),
+                y,
+            };
+        } else {
+            let at = match direction {
+                Direction::Left => self.cursor,
+                _ => {
+                    let after = self.document.after(&self.cursor);
+                    if after.y == self.cursor.y {
+                        after
+                    } else {
+                        Position {
+                            x: self.document.current_row_length(&self.cursor),
+                            y: self.cursor.y,
+                        }
+                    }
+                }
+            };
+            let end = self.document.insert_text(&at, &text.repeat(count));
+            self.cursor = self.document.before(&end);
+        }
+    }
+
+    // Shows the lines under a title until the next key, it is left out while replaying
+    fn show_listing(&mut self, title: &str, lines: &[String]) {
+        if self.replay_depth > 0 {
+            return;
+        }
+        self.listing = Some((title.to_string(), lines.to_vec()));
+        self.status_message
+            .reset(Some("Press any key to continue".to_string()));
+    }
+
+    // Draws the listing over the bottom of the rows, as many of its lines as fit
+    fn draw_listing(&self) {
+        let Some((title, lines)) = &self.listing else {
+            return;
+        };
+        let height = self.terminal.size.height as usize;
+        let width = self.terminal.size.width as usize;
+        let shown = cmp::min(lines.len() + 1, height.saturating_sub(2));
+        let top = height.saturating_sub(2) - shown;
+
+        let entries =
+            std::iter::once(title.as_str().bold().to_string()).chain(lines.iter().cloned());
+        for (idx, line) in entries.take(shown).enumerate() {
+            Terminal::move_cursor(&Position { x: 0, y: top + idx });
+            Terminal::clear_line();
+            print!("{}", line.chars().take(width).collect::<String>());
+        }
+    }
+
+    // The visual selection, which includes the character under the cursor
+    fn selection_span(&self) -> Span {
+        let start = cmp::min(self.visual_anchor, self.cursor);
+        let last = cmp::max(self.visual_anchor, self.cursor);
+        Span {
+            start,
+            end: self.document.after(&last),
+        }
+    }
+
+    // Swaps a selection target for the span it covers and leaves visual mode
+    // Text objects from the syntax tree are swapped for their span too, count of them out
+    // None when there is no such object, the operator is then left undone
+    fn resolve_selection(&mut self, target: TextTarget, count: usize) -> Option<TextTarget> {
+        let target = match target {
+            TextTarget::Selection => {
+                let span = self.selection_span();
+                self.input.switch(InputMode::Normal(NormalInput::default()));
+                TextTarget::Span(span)
+            }
+            TextTarget::Object(
+                object @ (TextObject::Function | TextObject::Class | TextObject::Argument),
+                scope,
+            ) => {
+                let cursor = self.cursor;
+                let found = self
+                    .syntax()
+                    .and_then(|syntax| syntax.object_span(&cursor, object, scope, count));
+                match found {
+                    Some(span) => TextTarget::Span(span),
+                    None => {
+                        self.no_syntax_node();
+                        return None;
+                    }
+                }
+            }
+            _ => target,
+        };
+        Some(target)
+    }
+
+    // Moves the cursor to where the target resolves, staying put when it cannot be found
+    fn jump_to(&mut self, target: TextTarget, count: usize) {
+        if let TextTarget::Char(kind, c, repeat) = target {
+            if let Some(x) = self
+                .document
+                .find_char(&self.cursor, kind, c, count, repeat)
+            {
+                self.cursor.x = x;
+            }
+        }
+    }
+
+    // Puts the settings into effect, keeping the current theme when the new one cannot be loaded
+    fn apply_config(&mut self, mut config: Config) -> Result<(), String> {
+        let mut result = Ok(());
+        if config.theme != self.theme.name() {
+            match Theme::load(&config.theme) {
+                Ok(theme) => self.theme = theme,
+                Err(err) => {
+                    config.theme = self.theme.name().to_string();
+                    result = Err(err);
+                }
+            }
+        }
+        self.document.options = config.document.clone();
+        if config.clipboard != self.config.clipboard {
+            self.registers.set_clipboard(clipboard_provider(&config));
+        }
+        self.config = config;
+        result
+    }
+
+    // Columns taken by the sign column and by line numbers with the space after them
+    fn gutter_width(&self) -> usize {
+        self.sign_width() + self.number_width()
+    }
+
+    fn sign_width(&self) -> usize {
+        match self.config.sign_column.as_str() {
+            "yes" => 2,
+            "auto" if !self.signs.is_empty() => 2,
+            _ => 0,
+        }
+    }
+
+    fn number_width(&self) -> usize {
+        match self.config.number || self.config.relative_number {
+            true => cmp::max(self.document.rows.len().to_string().len(), 3) + 1,
+            false => 0,
+        }
+    }
+
+    // The sign column and line number beside the row
+    fn gutter(&self, row: usize) -> String {
+        let mut gutter = String::new();
+        if self.sign_width() > 0 {
+            gutter += &match self.signs.get(row) {
+                Some(sign) => self
+                    .theme
+                    .paint(Ui::Sign(sign.kind), &format!("{:2}", sign.text)),
+                None => "  ".to_string(),
+            };
+        }
+        let width = self.number_width();
+        if width == 0 {
+            return gutter;
+        }
+        let relative = self.config.relative_number;
+        let number = line_number(row, self.cursor.y, self.config.number, relative, width);
+        gutter + &self.theme.paint(Ui::LineNumber, &number)
+    }
+
+    // Takes the terminal's new size, bringing the view back around the cursor before a full repaint
+    fn resize(&mut self, width: u16, height: u16) {
+        self.terminal.resize(width, height);
+        self.bound_cursor_to_line();
+        self.pull_view_to_cursor();
+        Terminal::clear_screen();
+        self.dirty = true;
+    }
+
+    // Columns left for text beside the gutter, at least one however narrow the terminal
+    fn text_width(&self) -> usize {
+        cmp::max(
+            (self.terminal.size.width as usize).saturating_sub(self.gutter_width()),
+            1,
+        )
+    }
+
+    // Where each screen line of row y starts, only ever the one without wrap
+    fn screen_lines(&self, y: usize) -> Vec<usize> {
+        match (self.config.wrap, self.document.rows.get(y)) {
+            (true, Some(row)) => row.wrap_starts(
+                self.text_width(),
+                self.config.show_break.chars().count(),
+                self.config.line_break,
+                self.document.options.tab_stop,
+            ),
+            _ => vec![0],
+        }
+    }
+
+    // The cursor's screen line in its row, and the column that line starts at
+    fn cursor_line(&self) -> (usize, usize) {
+        let starts = self.screen_lines(self.cursor.y);
+        let line = starts.partition_point(|start| *start <= self.cursor.x) - 1;
+        (line, starts[line])
+    }
+
+    // How many screen columns the text of row y between the two byte columns takes
+    fn columns_between(&self, y: usize, from: usize, to: usize) -> usize {
+        let tab_stop = self.document.options.tab_stop;
+        self.document.rows.get(y).map_or(0, |row| {
+            row.column_of(to, tab_stop)
+                .saturating_sub(row.column_of(from, tab_stop))
+        })
+    }
+
+    // The screen column of the cursor in its row, before any scrolling
+    fn cursor_column(&self) -> usize {
+        self.columns_between(self.cursor.y, 0, self.cursor.x)
+    }
+
+    // The cursor is at the end of a row whose last screen line is full, so it shows on the line after
+    fn cursor_past_full_line(&self) -> bool {
+        if !self.config.wrap {
+            return false;
+        }
+        let (line, start) = self.cursor_line();
+        let room = match line {
+            0 => self.text_width(),
+            _ => self
+                .text_width()
+                .saturating_sub(self.config.show_break.chars().count()),
+        };
+        self.columns_between(self.cursor.y, start, self.cursor.x) >= cmp::max(room, 1)
+    }
+
+    // Screen lines are (row, line in the row)
+    fn next_line(&self, (y, line): (usize, usize)) -> Option<(usize, usize)> {
+        if line + 1 < self.screen_lines(y).len() {
+            Some((y, line + 1))
+        } else if y + 1 < self.document.rows.len() {
+            Some((y + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    fn previous_line(&self, (y, line): (usize, usize)) -> Option<(usize, usize)> {
+        match (line, y) {
+            (0, 0) => None,
+            (0, _) => Some((y - 1, self.screen_lines(y - 1).len() - 1)),
+            _ => Some((y, line - 1)),
+        }
+    }
+
+    // The screen lines from one to another further down, counting no further than limit
+    fn lines_between(&self, from: (usize, usize), to: (usize, usize), limit: usize) -> usize {
+        let mut count = 0;
+        let mut at = from;
+        while at < to && count < limit {
+            let Some(next) = self.next_line(at) else {
+                break;
+            };
+            at = next;
+            count += 1;
+        }
+        count
+    }
+
+    fn cursor_on_screen(&self) -> Position {
+        let gutter = self.gutter_width();
+        if !self.config.wrap {
+            return Position {
+                x: self.cursor_column().saturating_sub(self.offset.x) + gutter,
+                y: self.cursor.y.saturating_sub(self.offset.y),
+            };
+        }
+        let (line, start) = self.cursor_line();
+        let past = self.cursor_past_full_line();
+        let show_break = match line == 0 && !past {
+            true => 0,
+            false => self.config.show_break.chars().count(),
+        };
+        let column = match past {
+            true => 0,
+            false => self.columns_between(self.cursor.y, start, self.cursor.x),
+        };
+        let y = self.lines_between(
+            (self.offset.y, self.offset_line),
+            (self.cursor.y, line),
+            self.terminal.size.height as usize,
+        );
+        Position {
+            x: gutter + show_break + column,
+            y: y + past as usize,
+        }
+    }
+
     // Pulls the viewport (offset) to make the cursor be in it
     fn pull_view_to_cursor(&mut self) {
-        if self.cursor.x > self.offset.x + self.terminal.size.width as usize - 1 {
-            self.offset.x = self
-                .cursor
-                .x
-                .saturating_sub(self.terminal.size.width as usize)
-                + 1;
-        } else if self.cursor.x < self.offset.x {
-            self.offset.x = self.cursor.x;
+        // marks and git signs may have come, gone or moved, and the sign column with them
+        self.signs
+            .set(SignSource::Git, self.document.git_signs.clone());
+        self.signs.set(SignSource::Mark, mark_signs(&self.document));
+        if self.config.wrap {
+            self.pull_wrapped_view_to_cursor();
+            return;
         }
+        self.offset_line = 0;
+        // without wrap the view scrolls by screen columns
+        let column = self.cursor_column();
+        self.offset.x = scroll_to(self.offset.x, column, self.text_width(), 0, usize::MAX);
 
-        if self.cursor.y > self.offset.y + self.terminal.size.height as usize - 3 {
-            self.offset.y = self
-                .cursor
-                .y
-                .saturating_sub(self.terminal.size.height as usize)
-                + 3;
-        } else if self.cursor.y < self.offset.y {
-            self.offset.y = self.cursor.y;
+        // 2 rows for the status bar, and scrolloff rows kept either side of the cursor
+        let height = self.terminal.size.height as usize - 2;
+        let margin = cmp::min(self.config.scroll_off, height.saturating_sub(1) / 2);
+        let rows = self.document.rows.len();
+        self.offset.y = scroll_to(self.offset.y, self.cursor.y, height, margin, rows);
+    }
+
+    // As above counting screen lines, the top can be part way down a row taller than the screen
+    fn pull_wrapped_view_to_cursor(&mut self) {
+        self.offset.x = 0;
+        let height = self.terminal.size.height as usize - 2;
+        let margin = cmp::min(self.config.scroll_off, height.saturating_sub(1) / 2);
+        let cursor = (self.cursor.y, self.cursor_line().0);
+        // the top row may have got shorter since
+        let top_lines = self.screen_lines(self.offset.y).len();
+        let top = (self.offset.y, cmp::min(self.offset_line, top_lines - 1));
+
+        let mut new_top = top;
+        if cursor < top || self.lines_between(top, cursor, margin) < margin {
+            new_top = cursor;
+            for _ in 0..margin {
+                new_top = self.previous_line(new_top).unwrap_or(new_top);
+            }
+        } else {
+            // past the end of the file only as far as the cursor has to be
+            let mut below = 0;
+            let mut at = cursor;
+            while below < margin {
+                let Some(next) = self.next_line(at) else {
+                    break;
+                };
+                at = next;
+                below += 1;
+            }
+            // a cursor past a full line takes the screen line after it too
+            below += self.cursor_past_full_line() as usize;
+            if self.lines_between(top, cursor, height) + below > height - 1 {
+                new_top = cursor;
+                for _ in 0..(height - 1).saturating_sub(below) {
+                    new_top = self.previous_line(new_top).unwrap_or(new_top);
+                }
+            }
         }
+        (self.offset.y, self.offset_line) = new_top;
     }
 
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&mut self) {
         Terminal::move_cursor(&Position {
             x: 0,
             y: self.terminal.size.height as usize - 2,
         });
         Terminal::clear_line();
 
-        // config: status bar items
-        let cursor_pos = self.cursor.file_position();
-        let mode_text = self.input.to_string();
-        let status_notes: Vec<&str> = vec![&self.document.file_name, &mode_text, &cursor_pos];
-        let status_formatted = equispace_words(self.terminal.size.width.into(), &status_notes);
-
-        // config: status bar color
-        print!("{}", status_formatted.white().on_dark_blue());
+        let branch = match self.config.status_line.contains("{branch") {
+            true => self.git_branch.get(&self.document.file_name),
+            false => None,
+        };
+        let info = StatusInfo {
+            file_name: &self.document.file_name,
+            modified: self.document.modified,
+            mode: self.input.to_string(),
+            filetype: self.document.filetype.name(),
+            crlf: self.document.crlf,
+            line: self.cursor.y,
+            column: self.cursor.x,
+            rows: self.document.rows.len(),
+            branch,
+        };
+        print!(
+            "{}",
+            status_line::render(
+                &self.config.status_line,
+                &info,
+                self.terminal.size.width.into(),
+                &self.theme
+            )
+        );
     }
 
     // draws the status message (if there is one alive)
@@ -303,7 +2032,8 @@ impl Editor {
 
         print!(
             "{}",
-            self.status_message.render(self.terminal.size.width.into())
+            self.status_message
+                .render(self.terminal.size.width.into(), &self.theme)
         );
     }
 
@@ -318,35 +2048,42 @@ impl Editor {
         loop {
             self.status_message
                 .reset(Some(format!("{}{}", prompt, result)));
-            self.draw_status_message();
-            Terminal::flush();
-
-            match read() {
-                Ok(ev) => match ev {
-                    Event::Key(ev_key) => {
-                        if !matches!(ev_key.kind, KeyEventKind::Press) {
-                            continue;
-                        }
-                        match ev_key.code {
-                            KeyCode::Left => self.cursor.x -= 1,
-                            KeyCode::Right => self.cursor.x += 1,
-                            KeyCode::Char(c) => result.push(c),
-                            KeyCode::Enter => return Some(result),
-                            KeyCode::Esc => return None,
-                            KeyCode::Backspace => match result.pop() {
-                                Some(_) => {}
-                                None => return None,
-                            },
-                            _ => {}
-                        }
+            if self.replay_depth == 0 {
+                self.draw_status_message();
+                Terminal::flush();
+            }
+
+            match self.next_event() {
+                Some(Event::Key(ev_key)) => {
+                    if !matches!(ev_key.kind, KeyEventKind::Press) {
+                        continue;
+                    }
+                    match ev_key.code {
+                        KeyCode::Left => self.cursor.x -= 1,
+                        KeyCode::Right => self.cursor.x += 1,
+                        KeyCode::Char(c) => result.push(c),
+                        KeyCode::Enter => return Some(result),
+                        KeyCode::Esc => return None,
+                        KeyCode::Backspace => match result.pop() {
+                            Some(_) => {}
+                            None => return None,
+                        },
+                        _ => {}
+                    }
+                }
+                Some(Event::Paste(text)) => result += &text.replace(['\r', '\n'], " "),
+                // the prompt is drawn again over the repainted screen
+                Some(Event::Resize(width, height)) => {
+                    self.resize(width, height);
+                    if self.replay_depth == 0 {
+                        self.refresh_screen();
                     }
-                    _ => {}
-                },
-                Err(_) => {
-                    // ignore the errors lol
                 }
+                _ => {}
             };
-            Terminal::clear_line();
+            if self.replay_depth == 0 {
+                Terminal::clear_line();
+            }
         }
     }
 
@@ -359,12 +2096,17 @@ impl Editor {
                 }
             };
             self.document.file_name = name;
+            self.document.detect_filetype();
         }
 
         match self.document.save() {
-            Ok(_) => self
-                .status_message
-                .reset(Some(format!("{} was saved.", self.document.file_name))),
+            Ok(_) => {
+                self.status_message
+                    .reset(Some(format!("{} was saved.", self.document.file_name)));
+                self.sync_file_marks();
+                // git only sees the file as saved, so its signs are only read again on saving
+                self.document.git_signs = git_signs(&self.document.file_name);
+            }
             Err(err) => {
                 self.status_message.reset(
                     format!(
@@ -377,6 +2119,80 @@ impl Editor {
         }
     }
 
+    // Runs an edit at every cursor in turn, the furthest down first so the ones above hold
+    // Cursors after each edit move with their text, going by how far they are from the row end
+    fn at_each_cursor(&mut self, mut edit: impl FnMut(&mut Self)) {
+        let main = self.cursor;
+        let mut todo = std::mem::take(&mut self.cursors);
+        todo.push(main);
+        todo.sort();
+        todo.dedup();
+
+        let mut done: Vec<(Position, bool)> = vec![];
+        let cursors = todo.len();
+        for (turn, at) in todo.into_iter().enumerate().rev() {
+            let rows = self.document.len();
+            let row_len = self.document.row_len(at.y);
+            self.cursor = at;
+            self.cursor_turn = Some((turn, cursors));
+            self.bound_cursor_to_line();
+            edit(self);
+            let tail_y =
+                at.y.saturating_add_signed(self.document.len() as isize - rows as isize);
+            let tail_len = self.document.row_len(tail_y);
+            for (pos, _) in done.iter_mut() {
+                *pos = follow_edit(*pos, at, row_len, tail_y, tail_len);
+            }
+            done.push((self.cursor, at == main));
+        }
+        self.cursor_turn = None;
+
+        // what each cursor took goes into the register together, top to bottom
+        let mut taken = std::mem::take(&mut self.taken);
+        if let Some((name, _, kind)) = taken.first() {
+            let (name, kind) = (*name, *kind);
+            taken.reverse();
+            let linewise = taken.iter().any(|(_, register, _)| register.linewise);
+            let pieces = taken
+                .into_iter()
+                .map(|(_, register, _)| register.text)
+                .collect();
+            self.put_register(name, Register::from_pieces(pieces, linewise), kind);
+        }
+
+        done.sort_by_key(|(pos, _)| *pos);
+        done.dedup_by_key(|(pos, _)| *pos);
+        self.cursors.clear();
+        for (pos, is_main) in done {
+            match is_main {
+                true => self.cursor = pos,
+                false => self.cursors.push(pos),
+            }
+        }
+    }
+
+    // Each key outside insert mode is a change of its own, typing stays in the one it was entered with
+    fn end_change(&mut self) {
+        if !self.input.is_inserting() {
+            self.document.end_change();
+        }
+    }
+
+    // The document's syntax tree, None when tree-sitter has no grammar for it or is not built in
+    fn syntax(&mut self) -> Option<&Syntax> {
+        let edit = self.document.edit.take();
+        self.highlighter
+            .syntax(self.document.filetype, &self.document.rows, edit)
+    }
+
+    fn no_syntax_node(&mut self) {
+        let message = match cfg!(feature = "tree-sitter") {
+            true => "Nothing found in the syntax tree",
+            false => "Built without tree-sitter",
+        };
+        self.status_message.reset(Some(message.to_string()));
+    }
+
     // Pulls the cursor within the bounds of the current line and the document lines count
     fn bound_cursor_to_line(&mut self) {
         self.cursor.x = cmp::min(
@@ -388,31 +2204,128 @@ impl Editor {
     }
 }
 
-fn equispace_words(width: usize, words: &[&str]) -> String {
-    let total_word_len = words.iter().fold(0, |mut acc, s| {
-        acc += s.len();
-        acc
-    });
+// The config's clipboard, which was checked when it was set
+fn clipboard_provider(config: &Config) -> ClipboardProvider {
+    ClipboardProvider::from_name(&config.clipboard).unwrap_or(ClipboardProvider::Osc52)
+}
 
-    if total_word_len > width {
-        return "WORDS TOO BIG FOR WIDTH NO STATUS BAR FOR YOU ;(".to_string();
+// Opens the file along with the file marks saved in it
+fn open_with_marks(file_name: &str, file_marks: &FileMarks, options: DocumentOptions) -> Document {
+    let mut document = Document::open(file_name, options);
+    for (name, pos) in file_marks.in_file(&absolute_path(file_name)) {
+        document.set_mark(name, pos);
     }
+    document.git_signs = git_signs(file_name);
+    document
+}
 
-    let space_remaining = width - total_word_len;
-    let space_between = space_remaining / (words.len() - 1);
-    let mut output = "".to_string();
-    for (idx, word) in words.iter().enumerate() {
-        output += word;
-
-        if idx < words.len() - 1 {
-            output += &" ".repeat(space_between);
+// Where a cursor after `at` ends up once the row at `at`, row_len long, was edited
+// The edit left that row's tail on row tail_y, now tail_len long, and the rows below moved with it
+// A cursor on the same row keeps its distance from the row's end
+fn follow_edit(
+    pos: Position,
+    at: Position,
+    row_len: usize,
+    tail_y: usize,
+    tail_len: usize,
+) -> Position {
+    if pos.y == at.y {
+        Position {
+            x: tail_len.saturating_sub(row_len.saturating_sub(pos.x)),
+            y: tail_y,
         }
+    } else if pos.y > at.y {
+        Position {
+            x: pos.x,
+            y: pos.y + tail_y - at.y,
+        }
+    } else {
+        pos
+    }
+}
+
+// The first of size rows or columns in view once `at` is brought into it with margin either side
+// Scrolling forward goes past the end of len only as far as `at` has to be
+fn scroll_to(first: usize, at: usize, size: usize, margin: usize, len: usize) -> usize {
+    if at + margin > first + size - 1 {
+        let last_page = len.saturating_sub(size);
+        cmp::max(
+            cmp::min(at + margin + 1 - size, last_page),
+            (at + 1).saturating_sub(size),
+        )
+    } else if at < first + margin {
+        at.saturating_sub(margin)
+    } else {
+        first
+    }
+}
+
+// The row's number in a gutter width wide, counted from the cursor's row with relativenumber
+// With number as well the cursor's row has its own number, set to the left
+fn line_number(row: usize, cursor_y: usize, number: bool, relative: bool, width: usize) -> String {
+    match (number, relative) {
+        (true, true) if row == cursor_y => format!("{:<1$} ", row + 1, width - 1),
+        (_, true) => format!("{:>1$} ", row.abs_diff(cursor_y), width - 1),
+        _ => format!("{:>1$} ", row + 1, width - 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scroll_to_cursor() {
+        // already in view
+        assert_eq!(scroll_to(10, 15, 10, 0, 100), 10);
+        assert_eq!(scroll_to(10, 25, 10, 0, 100), 16);
+        assert_eq!(scroll_to(10, 5, 10, 0, 100), 5);
+        // margin rows are kept either side, but not past the end
+        assert_eq!(scroll_to(10, 18, 10, 3, 100), 12);
+        assert_eq!(scroll_to(10, 11, 10, 3, 100), 8);
+        assert_eq!(scroll_to(85, 98, 10, 3, 100), 90);
+        // a screen shrunk around the cursor brings it back into view
+        assert_eq!(scroll_to(0, 20, 5, 0, 100), 16);
+        assert_eq!(scroll_to(0, 20, 1, 0, 100), 20);
+        // columns have no end to stop at
+        assert_eq!(scroll_to(0, 120, 80, 0, usize::MAX), 41);
     }
 
-    // pads the text with space at the end
-    if output.len() < width {
-        output += &" ".repeat(width - output.len());
+    #[test]
+    fn line_numbers() {
+        assert_eq!(line_number(4, 2, true, false, 4), "  5 ");
+        assert_eq!(line_number(4, 2, false, true, 4), "  2 ");
+        assert_eq!(line_number(0, 2, true, true, 4), "  2 ");
+        // the cursor's row shows its own number to the left, or 0 without number
+        assert_eq!(line_number(2, 2, true, true, 4), "3   ");
+        assert_eq!(line_number(2, 2, false, true, 4), "  0 ");
+        // numbers wider than the gutter push past it
+        assert_eq!(line_number(1233, 0, true, false, 4), "1234 ");
     }
 
-    output
+    #[test]
+    fn cursors_on_one_row_follow_each_other() {
+        // x at each a of "ab ab ab", the last one first
+        let at = |x| Position { x, y: 0 };
+        // "ab ab b" after the third, the second leaves "ab b b"
+        let third = follow_edit(at(6), at(3), 7, 0, 6);
+        assert!(third == at(5));
+        // the first leaves "b b b"
+        let second = follow_edit(at(3), at(0), 6, 0, 5);
+        let third = follow_edit(third, at(0), 6, 0, 5);
+        assert!(second == at(2) && third == at(4));
+    }
+
+    #[test]
+    fn cursors_follow_split_and_joined_rows() {
+        let pos = |x, y| Position { x, y };
+        // Enter at 3 of "foo bar baz" leaves "bar baz" below
+        assert!(follow_edit(pos(8, 0), pos(3, 0), 11, 1, 8) == pos(5, 1));
+        assert!(follow_edit(pos(2, 4), pos(3, 0), 11, 1, 8) == pos(2, 5));
+        // Backspace at the start of row 2 joins it onto row 1, "ab" and "cd" into "abcd"
+        assert!(follow_edit(pos(1, 2), pos(0, 2), 2, 1, 4) == pos(3, 1));
+        assert!(follow_edit(pos(0, 3), pos(0, 2), 2, 1, 4) == pos(0, 2));
+        // cursors above are left alone
+        assert!(follow_edit(pos(5, 0), pos(0, 2), 2, 1, 4) == pos(5, 0));
+    }
 }
@@ -0,0 +1,190 @@
+// The language of a document, going by the file's extension or else its shebang line
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Filetype {
+    Rust,
+    C,
+    Cpp,
+    Go,
+    JavaScript,
+    TypeScript,
+    Java,
+    Python,
+    Shell,
+    Ruby,
+    Perl,
+    Lua,
+    Sql,
+    Haskell,
+    Toml,
+    Json,
+    Yaml,
+    Make,
+    Html,
+    Xml,
+    Markdown,
+    Css,
+    #[default]
+    Text,
+}
+
+// How a language writes a comment, either to the end of the line or wrapped around the text
+#[derive(Clone, Copy)]
+pub enum CommentStyle {
+    Line(&'static str),
+    Block(&'static str, &'static str),
+}
+
+impl Filetype {
+    pub fn detect(file_name: &str, first_line: Option<&str>) -> Self {
+        let base = file_name.rsplit('/').next().unwrap_or(file_name);
+        if let Some(filetype) = Self::from_base_name(base) {
+            return filetype;
+        }
+        first_line
+            .and_then(|line| line.strip_prefix("#!"))
+            .and_then(Self::from_shebang)
+            .unwrap_or_default()
+    }
+
+    fn from_base_name(base: &str) -> Option<Self> {
+        if matches!(base, "Makefile" | "makefile" | "GNUmakefile") {
+            return Some(Filetype::Make);
+        }
+        let (_, ext) = base.rsplit_once('.')?;
+        let filetype = match ext {
+            "rs" => Filetype::Rust,
+            "c" | "h" => Filetype::C,
+            "cc" | "cpp" | "cxx" | "hpp" | "hh" => Filetype::Cpp,
+            "go" => Filetype::Go,
+            "js" | "mjs" | "cjs" | "jsx" => Filetype::JavaScript,
+            "ts" | "tsx" => Filetype::TypeScript,
+            "java" => Filetype::Java,
+            "py" | "pyw" => Filetype::Python,
+            "sh" | "bash" | "zsh" => Filetype::Shell,
+            "rb" => Filetype::Ruby,
+            "pl" | "pm" => Filetype::Perl,
+            "lua" => Filetype::Lua,
+            "sql" => Filetype::Sql,
+            "hs" => Filetype::Haskell,
+            "toml" => Filetype::Toml,
+            "json" => Filetype::Json,
+            "yaml" | "yml" => Filetype::Yaml,
+            "mk" => Filetype::Make,
+            "html" | "htm" => Filetype::Html,
+            "xml" | "svg" => Filetype::Xml,
+            "md" | "markdown" => Filetype::Markdown,
+            "css" => Filetype::Css,
+            "txt" => Filetype::Text,
+            _ => return None,
+        };
+        Some(filetype)
+    }
+
+    // The interpreter's name, skipping the path and any `env`, e.g. `/usr/bin/env python3`
+    fn from_shebang(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let mut program = words.next()?.rsplit('/').next()?;
+        if program == "env" {
+            program = words.find(|word| !word.starts_with('-'))?;
+        }
+        let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        let filetype = match program {
+            "sh" | "bash" | "zsh" | "dash" | "ksh" => Filetype::Shell,
+            "python" => Filetype::Python,
+            "ruby" => Filetype::Ruby,
+            "perl" => Filetype::Perl,
+            "lua" => Filetype::Lua,
+            "node" => Filetype::JavaScript,
+            _ => return None,
+        };
+        Some(filetype)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filetype::Rust => "rust",
+            Filetype::C => "c",
+            Filetype::Cpp => "cpp",
+            Filetype::Go => "go",
+            Filetype::JavaScript => "javascript",
+            Filetype::TypeScript => "typescript",
+            Filetype::Java => "java",
+            Filetype::Python => "python",
+            Filetype::Shell => "sh",
+            Filetype::Ruby => "ruby",
+            Filetype::Perl => "perl",
+            Filetype::Lua => "lua",
+            Filetype::Sql => "sql",
+            Filetype::Haskell => "haskell",
+            Filetype::Toml => "toml",
+            Filetype::Json => "json",
+            Filetype::Yaml => "yaml",
+            Filetype::Make => "make",
+            Filetype::Html => "html",
+            Filetype::Xml => "xml",
+            Filetype::Markdown => "markdown",
+            Filetype::Css => "css",
+            Filetype::Text => "text",
+        }
+    }
+
+    pub fn comment_style(&self) -> Option<CommentStyle> {
+        let style = match self {
+            Filetype::Rust
+            | Filetype::C
+            | Filetype::Cpp
+            | Filetype::Go
+            | Filetype::JavaScript
+            | Filetype::TypeScript
+            | Filetype::Java => CommentStyle::Line("//"),
+            Filetype::Python
+            | Filetype::Shell
+            | Filetype::Ruby
+            | Filetype::Perl
+            | Filetype::Toml
+            | Filetype::Yaml
+            | Filetype::Make => CommentStyle::Line("#"),
+            Filetype::Lua | Filetype::Sql | Filetype::Haskell => CommentStyle::Line("--"),
+            Filetype::Html | Filetype::Xml | Filetype::Markdown => {
+                CommentStyle::Block("<!--", "-->")
+            }
+            Filetype::Css => CommentStyle::Block("/*", "*/"),
+            Filetype::Json | Filetype::Text => return None,
+        };
+        Some(style)
+    }
+
+    // Whether blocks are closed by brackets, otherwise only their indent ends them
+    pub fn indent_by_braces(&self) -> bool {
+        !matches!(self, Filetype::Python | Filetype::Yaml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_by_name() {
+        assert!(Filetype::detect("src/main.rs", None) == Filetype::Rust);
+        assert!(Filetype::detect("a/b.tar.yml", None) == Filetype::Yaml);
+        assert!(Filetype::detect("dir/Makefile", None) == Filetype::Make);
+        // the name wins over the shebang
+        assert!(Filetype::detect("run.py", Some("#!/bin/sh")) == Filetype::Python);
+        assert!(Filetype::detect("notes", None) == Filetype::Text);
+        assert!(Filetype::detect("a.unknown", None) == Filetype::Text);
+    }
+
+    #[test]
+    fn detect_by_shebang() {
+        let detect = |line| Filetype::detect("script", Some(line));
+        assert!(detect("#!/bin/bash") == Filetype::Shell);
+        assert!(detect("#!/usr/bin/env python3") == Filetype::Python);
+        assert!(detect("#!/usr/bin/env -S node --flag") == Filetype::JavaScript);
+        assert!(detect("#!/usr/bin/perl5.36 -w") == Filetype::Perl);
+        assert!(detect("#!/usr/bin/env") == Filetype::Text);
+        // without #! the line is just text
+        assert!(detect("/bin/sh") == Filetype::Text);
+    }
+}
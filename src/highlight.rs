@@ -0,0 +1,839 @@
+// Syntax highlighting, each row is split into tokens which the theme gives a colour
+// A row starts in the state the row above ended in, so comments and strings can span rows
+
+use std::{cmp, ops::Range};
+
+use crate::{
+    document::{Row, RowEdit},
+    filetype::Filetype,
+    syntax::Syntax,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Comment,
+    String,
+    Number,
+    Constant,
+    Keyword,
+    Type,
+    Function,
+    Macro,
+    Attribute,
+    Variable,
+    Key,
+    Heading,
+    Emphasis,
+    Code,
+    Link,
+}
+
+impl Scope {
+    // The scope's key in a theme's [syntax] table
+    pub fn from_name(name: &str) -> Option<Self> {
+        let scope = match name {
+            "comment" => Scope::Comment,
+            "string" => Scope::String,
+            "number" => Scope::Number,
+            "constant" => Scope::Constant,
+            "keyword" => Scope::Keyword,
+            "type" => Scope::Type,
+            "function" => Scope::Function,
+            "macro" => Scope::Macro,
+            "attribute" => Scope::Attribute,
+            "variable" => Scope::Variable,
+            "key" => Scope::Key,
+            "heading" => Scope::Heading,
+            "emphasis" => Scope::Emphasis,
+            "code" => Scope::Code,
+            "link" => Scope::Link,
+            _ => return None,
+        };
+        Some(scope)
+    }
+}
+
+pub struct Token {
+    pub columns: Range<usize>,
+    pub scope: Scope,
+}
+
+// What a row leaves open at its end for the row after it
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum LineState {
+    #[default]
+    Normal,
+    Comment(usize), // in a block comment, nested this deep
+    String(usize),  // in a string, the index of its quote in the grammar
+    CodeBlock,      // in a fenced markdown code block
+}
+
+// Splits a row into tokens given the state the row before ended in
+pub trait Grammar {
+    fn tokenize(&self, line: &str, state: LineState) -> (Vec<Token>, LineState);
+}
+
+pub fn grammar_for(filetype: Filetype) -> Option<&'static dyn Grammar> {
+    let grammar: &'static dyn Grammar = match filetype {
+        Filetype::Rust => &RUST,
+        Filetype::Toml => &TOML,
+        Filetype::Json => &JSON,
+        Filetype::Python => &PYTHON,
+        Filetype::Shell => &SHELL,
+        Filetype::Markdown => &Markdown,
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+struct HighlightedLine {
+    start: LineState,
+    end: LineState,
+    tokens: Vec<Token>,
+}
+
+// Keeps the tokens of each row from the top down to the last one drawn
+// Edited rows are dropped and the walk starts from the first of them, tokenizing a row again
+// only when it was edited or the state it starts in has changed
+// Filetypes with a syntax tree are highlighted from that instead, just for the visible rows
+#[derive(Default)]
+pub struct Highlighter {
+    filetype: Filetype,
+    lines: Vec<Option<HighlightedLine>>,
+    // the rows above this are tokenized from the state the row before them ended in
+    walked: usize,
+    syntax: Syntax,
+    // tokens from the syntax tree, for the visible rows from tree_start
+    tree_lines: Vec<Vec<Token>>,
+    tree_start: usize,
+}
+
+impl Highlighter {
+    // Brings the visible rows up to date after the rows in edit changed
+    pub fn update(
+        &mut self,
+        filetype: Filetype,
+        rows: &[Row],
+        edit: Option<RowEdit>,
+        visible: Range<usize>,
+    ) {
+        if filetype != self.filetype {
+            self.filetype = filetype;
+            self.lines.clear();
+            self.walked = 0;
+        }
+        self.edited(edit);
+        self.tree_lines.clear();
+        if self.syntax.update(filetype, rows, edit) {
+            self.tree_start = visible.start;
+            self.tree_lines = self.syntax.highlights(visible);
+            return;
+        }
+        let Some(grammar) = grammar_for(filetype) else {
+            return;
+        };
+        self.lines.truncate(rows.len());
+        self.walked = cmp::min(self.walked, self.lines.len());
+
+        let mut state = match self.walked.checked_sub(1).map(|y| &self.lines[y]) {
+            Some(Some(line)) => line.end,
+            _ => LineState::Normal,
+        };
+        let end = cmp::min(visible.end, rows.len());
+        for (y, row) in rows.iter().enumerate().take(end).skip(self.walked) {
+            if let Some(Some(line)) = self.lines.get(y) {
+                if line.start == state {
+                    state = line.end;
+                    continue;
+                }
+            }
+            let (tokens, end) = grammar.tokenize(row.as_str(), state);
+            let line = Some(HighlightedLine {
+                start: state,
+                end,
+                tokens,
+            });
+            match self.lines.get_mut(y) {
+                Some(old) => *old = line,
+                None => self.lines.push(line),
+            }
+            state = end;
+        }
+        self.walked = cmp::max(self.walked, end);
+    }
+
+    // Drops the lines of the rows the edit replaced, leaving the rows after it to move up or down
+    fn edited(&mut self, edit: Option<RowEdit>) {
+        let Some(edit) = edit else {
+            return;
+        };
+        self.walked = cmp::min(self.walked, edit.start);
+        if edit.old_end >= self.lines.len() {
+            self.lines.truncate(edit.start);
+            return;
+        }
+        let added = edit.new_end - edit.start;
+        self.lines.splice(
+            edit.start..edit.old_end,
+            std::iter::repeat_with(|| None).take(added),
+        );
+    }
+
+    pub fn tokens(&self, y: usize) -> &[Token] {
+        if !self.tree_lines.is_empty() {
+            return y
+                .checked_sub(self.tree_start)
+                .and_then(|i| self.tree_lines.get(i))
+                .map_or(&[], |tokens| tokens);
+        }
+        match self.lines.get(y) {
+            Some(Some(line)) => &line.tokens,
+            _ => &[],
+        }
+    }
+
+    // The document's syntax tree brought up to date, None when it has no tree-sitter grammar
+    pub fn syntax(
+        &mut self,
+        filetype: Filetype,
+        rows: &[Row],
+        edit: Option<RowEdit>,
+    ) -> Option<&Syntax> {
+        self.edited(edit);
+        self.syntax
+            .update(filetype, rows, edit)
+            .then_some(&self.syntax)
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// The length of the run at the start of text matching f
+fn run_len(text: &str, f: impl Fn(char) -> bool) -> usize {
+    text.find(|c| !f(c)).unwrap_or(text.len())
+}
+
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    // whether the string carries on to the next row when it is not closed
+    multiline: bool,
+    escapes: bool,
+}
+
+const fn quote(open: &'static str, multiline: bool, escapes: bool) -> Quote {
+    Quote {
+        open,
+        close: open,
+        multiline,
+        escapes,
+    }
+}
+
+// A grammar built from a language's comments, quotes and word lists
+struct Lexical {
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_comments: bool,
+    // longer quotes before the ones they start with
+    quotes: &'static [Quote],
+    // 'a' is a character but 'a on its own a lifetime
+    char_literals: bool,
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    capitalized_types: bool,
+    // words followed by ( and !
+    functions: bool,
+    macros: bool,
+    // #[...] or @name
+    attribute: Option<char>,
+    // $name, ${name} and $1
+    variables: bool,
+    // words and strings followed by it are keys
+    key_separator: Option<char>,
+    // rows starting with [
+    table_headers: bool,
+}
+
+impl Lexical {
+    // Where the comment from `from` ends and how deep it still is there
+    fn comment_end(&self, line: &str, from: usize, mut depth: usize) -> (usize, usize) {
+        let Some((open, close)) = self.block_comment else {
+            return (line.len(), 0);
+        };
+        let mut i = from;
+        while i < line.len() {
+            let rest = &line[i..];
+            if rest.starts_with(close) {
+                depth -= 1;
+                i += close.len();
+                if depth == 0 {
+                    return (i, 0);
+                }
+            } else if self.nested_comments && rest.starts_with(open) {
+                depth += 1;
+                i += open.len();
+            } else {
+                i += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        (line.len(), depth)
+    }
+
+    // Where the string from `from` ends and whether its closing quote was found
+    fn string_end(&self, line: &str, from: usize, quote: &Quote) -> (usize, bool) {
+        let mut chars = line[from..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if quote.escapes && c == '\\' {
+                chars.next();
+            } else if line[from + i..].starts_with(quote.close) {
+                return (from + i + quote.close.len(), true);
+            }
+        }
+        (line.len(), false)
+    }
+
+    // The length of a character literal at the start of text, None for a lifetime
+    fn char_literal_len(text: &str) -> Option<usize> {
+        let mut chars = text.char_indices().skip(1);
+        let (_, c) = chars.next()?;
+        if c == '\\' {
+            // the character after the backslash could be the quote itself
+            let end = text.get(3..)?.find('\'')?;
+            return Some(end + 4);
+        }
+        match chars.next()? {
+            (i, '\'') => Some(i + 1),
+            _ => None,
+        }
+    }
+
+    fn word_scope(&self, word: &str, after: &str) -> Option<Scope> {
+        if self
+            .key_separator
+            .is_some_and(|sep| after.trim_start().starts_with(sep))
+        {
+            return Some(Scope::Key);
+        }
+        if self.keywords.contains(&word) {
+            Some(Scope::Keyword)
+        } else if self.constants.contains(&word) {
+            Some(Scope::Constant)
+        } else if self.types.contains(&word) {
+            Some(Scope::Type)
+        } else if self.macros && after.starts_with('!') && !after.starts_with("!=") {
+            Some(Scope::Macro)
+        } else if self.functions && after.starts_with('(') {
+            Some(Scope::Function)
+        } else if word.len() > 1
+            && word.chars().any(|c| c.is_uppercase())
+            && !word.chars().any(|c| c.is_lowercase())
+        {
+            Some(Scope::Constant)
+        } else if self.capitalized_types && word.starts_with(|c: char| c.is_uppercase()) {
+            Some(Scope::Type)
+        } else {
+            None
+        }
+    }
+
+    // The length of an attribute at the start of text
+    fn attribute_len(&self, text: &str) -> Option<usize> {
+        match self.attribute? {
+            '@' if text.starts_with('@') => {
+                Some(1 + run_len(&text[1..], |c| is_word(c) || c == '.'))
+            }
+            '#' if text.starts_with("#[") || text.starts_with("#![") => {
+                let mut depth = 0;
+                for (i, c) in text.char_indices() {
+                    match c {
+                        '[' => depth += 1,
+                        ']' if depth == 1 => return Some(i + 1),
+                        ']' => depth -= 1,
+                        _ => {}
+                    }
+                }
+                Some(text.len())
+            }
+            _ => None,
+        }
+    }
+
+    // The length of a shell variable at the start of text
+    fn variable_len(text: &str) -> Option<usize> {
+        let rest = text.strip_prefix('$')?;
+        if rest.starts_with('{') {
+            return Some(rest.find('}').map_or(text.len(), |i| i + 2));
+        }
+        match rest.chars().next()? {
+            c if c.is_ascii_digit() || "@#?$!*-".contains(c) => Some(2),
+            c if is_word(c) => Some(1 + run_len(rest, is_word)),
+            _ => None,
+        }
+    }
+}
+
+impl Grammar for Lexical {
+    fn tokenize(&self, line: &str, state: LineState) -> (Vec<Token>, LineState) {
+        let mut tokens = vec![];
+        let push = |tokens: &mut Vec<Token>, columns: Range<usize>, scope| {
+            if !columns.is_empty() {
+                tokens.push(Token { columns, scope })
+            }
+        };
+
+        // finish whatever the row above left open
+        let mut i = 0;
+        match state {
+            LineState::Comment(depth) => {
+                let (end, depth) = self.comment_end(line, 0, depth);
+                push(&mut tokens, 0..end, Scope::Comment);
+                if depth > 0 {
+                    return (tokens, LineState::Comment(depth));
+                }
+                i = end;
+            }
+            LineState::String(q) => {
+                let (end, closed) = self.string_end(line, 0, &self.quotes[q]);
+                push(&mut tokens, 0..end, Scope::String);
+                if !closed {
+                    return (tokens, state);
+                }
+                i = end;
+            }
+            _ => {}
+        }
+
+        if self.table_headers && i == 0 && line.trim_start().starts_with('[') {
+            let end = line.find(" #").unwrap_or(line.len());
+            push(&mut tokens, 0..end, Scope::Type);
+            push(&mut tokens, end + 1..line.len(), Scope::Comment);
+            return (tokens, LineState::Normal);
+        }
+
+        while i < line.len() {
+            let rest = &line[i..];
+            // unwrap - i is before the end of the line
+            let c = rest.chars().next().unwrap();
+            let after_word = line[..i].chars().next_back().is_some_and(is_word);
+
+            if let Some(leader) = self.line_comment.filter(|leader| rest.starts_with(leader)) {
+                // a shell # in the middle of a word is part of it
+                if !(self.variables && leader == "#" && after_word) {
+                    push(&mut tokens, i..line.len(), Scope::Comment);
+                    break;
+                }
+            }
+            if let Some((open, _)) = self
+                .block_comment
+                .filter(|(open, _)| rest.starts_with(open))
+            {
+                let (end, depth) = self.comment_end(line, i + open.len(), 1);
+                push(&mut tokens, i..end, Scope::Comment);
+                if depth > 0 {
+                    return (tokens, LineState::Comment(depth));
+                }
+                i = end;
+                continue;
+            }
+            if let Some(q) = self.quotes.iter().position(|q| rest.starts_with(q.open)) {
+                let quote = &self.quotes[q];
+                let (end, closed) = self.string_end(line, i + quote.open.len(), quote);
+                let scope = match self.key_separator {
+                    Some(sep) if line[end..].trim_start().starts_with(sep) => Scope::Key,
+                    _ => Scope::String,
+                };
+                push(&mut tokens, i..end, scope);
+                if !closed && quote.multiline {
+                    return (tokens, LineState::String(q));
+                }
+                i = end;
+                continue;
+            }
+            if self.char_literals && c == '\'' {
+                if let Some(len) = Self::char_literal_len(rest) {
+                    push(&mut tokens, i..i + len, Scope::String);
+                    i += len;
+                    continue;
+                }
+            }
+            if let Some(len) = self.attribute_len(rest) {
+                push(&mut tokens, i..i + len, Scope::Attribute);
+                i += len;
+                continue;
+            }
+            if self.variables {
+                if let Some(len) = Self::variable_len(rest) {
+                    push(&mut tokens, i..i + len, Scope::Variable);
+                    i += len;
+                    continue;
+                }
+            }
+            if c.is_ascii_digit() && !after_word {
+                let mut len = 0;
+                for (j, c) in rest.char_indices() {
+                    let decimal_point =
+                        c == '.' && rest[j + 1..].starts_with(|c: char| c.is_ascii_digit());
+                    if !(is_word(c) || decimal_point) {
+                        break;
+                    }
+                    len = j + c.len_utf8();
+                }
+                push(&mut tokens, i..i + len, Scope::Number);
+                i += len;
+                continue;
+            }
+            if is_word(c) {
+                let len = run_len(rest, is_word);
+                let after = &rest[len..];
+                match self.word_scope(&rest[..len], after) {
+                    Some(Scope::Macro) => push(&mut tokens, i..i + len + 1, Scope::Macro),
+                    Some(scope) => push(&mut tokens, i..i + len, scope),
+                    None => {}
+                }
+                i += len;
+                continue;
+            }
+            i += c.len_utf8();
+        }
+        (tokens, LineState::Normal)
+    }
+}
+
+// Headings, lists, code and emphasis, with fenced code blocks running over rows
+struct Markdown;
+
+impl Markdown {
+    // Where the inline span opened by `open` at the start of text closes
+    fn closed_at(text: &str, open: &str) -> Option<usize> {
+        let inner = &text[open.len()..];
+        if inner.starts_with(char::is_whitespace) {
+            return None;
+        }
+        inner
+            .find(open)
+            .filter(|&end| end > 0)
+            .map(|end| open.len() + end + open.len())
+    }
+}
+
+impl Grammar for Markdown {
+    fn tokenize(&self, line: &str, state: LineState) -> (Vec<Token>, LineState) {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        let whole = |scope| {
+            vec![Token {
+                columns: 0..line.len(),
+                scope,
+            }]
+        };
+
+        match state {
+            LineState::CodeBlock if fence => return (whole(Scope::Code), LineState::Normal),
+            LineState::CodeBlock => return (whole(Scope::Code), LineState::CodeBlock),
+            _ if fence => return (whole(Scope::Code), LineState::CodeBlock),
+            _ => {}
+        }
+        let hashes = run_len(trimmed, |c| c == '#');
+        if (1..=6).contains(&hashes) && trimmed[hashes..].chars().next().is_none_or(|c| c == ' ') {
+            return (whole(Scope::Heading), LineState::Normal);
+        }
+        if trimmed.starts_with('>') {
+            return (whole(Scope::Comment), LineState::Normal);
+        }
+
+        let mut tokens = vec![];
+        let mut i = 0;
+        if let LineState::Comment(_) = state {
+            match line.find("-->") {
+                Some(end) => i = end + 3,
+                None => return (whole(Scope::Comment), state),
+            }
+            tokens.push(Token {
+                columns: 0..i,
+                scope: Scope::Comment,
+            });
+        }
+
+        // list markers
+        let digits = run_len(trimmed, |c| c.is_ascii_digit());
+        let marker = match trimmed.chars().next() {
+            Some('-' | '*' | '+') => 1,
+            _ if digits > 0 && trimmed[digits..].starts_with(['.', ')']) => digits + 1,
+            _ => 0,
+        };
+        if i == 0 && marker > 0 && trimmed[marker..].starts_with(' ') {
+            tokens.push(Token {
+                columns: indent..indent + marker,
+                scope: Scope::Keyword,
+            });
+            i = indent + marker;
+        }
+
+        while i < line.len() {
+            let rest = &line[i..];
+            // unwrap - i is before the end of the line
+            let c = rest.chars().next().unwrap();
+            let found = if rest.starts_with("<!--") {
+                match rest.find("-->") {
+                    Some(end) => Some((end + 3, Scope::Comment)),
+                    None => {
+                        tokens.push(Token {
+                            columns: i..line.len(),
+                            scope: Scope::Comment,
+                        });
+                        return (tokens, LineState::Comment(1));
+                    }
+                }
+            } else if c == '`' {
+                Self::closed_at(rest, "`").map(|len| (len, Scope::Code))
+            } else if rest.starts_with("**") || rest.starts_with("__") {
+                Self::closed_at(rest, &rest[..2]).map(|len| (len, Scope::Emphasis))
+            } else if c == '*' || (c == '_' && !line[..i].ends_with(is_word)) {
+                Self::closed_at(rest, &rest[..1]).map(|len| (len, Scope::Emphasis))
+            } else if c == '[' {
+                rest.find("](")
+                    .and_then(|mid| rest[mid..].find(')').map(|end| mid + end + 1))
+                    .map(|len| (len, Scope::Link))
+            } else {
+                None
+            };
+            match found {
+                Some((len, scope)) => {
+                    tokens.push(Token {
+                        columns: i..i + len,
+                        scope,
+                    });
+                    i += len;
+                }
+                None => i += c.len_utf8(),
+            }
+        }
+        (tokens, LineState::Normal)
+    }
+}
+
+static RUST: Lexical = Lexical {
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    nested_comments: true,
+    quotes: &[quote("\"", true, true)],
+    char_literals: true,
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "static", "struct", "super", "trait", "type", "unsafe",
+        "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64",
+    ],
+    constants: &["true", "false"],
+    capitalized_types: true,
+    functions: true,
+    macros: true,
+    attribute: Some('#'),
+    variables: false,
+    key_separator: None,
+    table_headers: false,
+};
+
+static TOML: Lexical = Lexical {
+    line_comment: Some("#"),
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        quote("\"\"\"", true, true),
+        quote("'''", true, false),
+        quote("\"", false, true),
+        quote("'", false, false),
+    ],
+    char_literals: false,
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "inf", "nan"],
+    capitalized_types: false,
+    functions: false,
+    macros: false,
+    attribute: None,
+    variables: false,
+    key_separator: Some('='),
+    table_headers: true,
+};
+
+static JSON: Lexical = Lexical {
+    line_comment: None,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[quote("\"", false, true)],
+    char_literals: false,
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "null"],
+    capitalized_types: false,
+    functions: false,
+    macros: false,
+    attribute: None,
+    variables: false,
+    key_separator: Some(':'),
+    table_headers: false,
+};
+
+static PYTHON: Lexical = Lexical {
+    line_comment: Some("#"),
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        quote("\"\"\"", true, true),
+        quote("'''", true, true),
+        quote("\"", false, true),
+        quote("'", false, true),
+    ],
+    char_literals: false,
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+        "yield", "self",
+    ],
+    types: &[
+        "int", "float", "str", "bool", "bytes", "list", "dict", "set", "tuple", "object",
+    ],
+    constants: &["True", "False", "None"],
+    capitalized_types: true,
+    functions: true,
+    macros: false,
+    attribute: Some('@'),
+    variables: false,
+    key_separator: None,
+    table_headers: false,
+};
+
+static SHELL: Lexical = Lexical {
+    line_comment: Some("#"),
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[quote("\"", true, true), quote("'", true, false)],
+    char_literals: false,
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+        "in", "function", "select", "return", "local", "export", "readonly", "break", "continue",
+    ],
+    types: &[],
+    constants: &["true", "false"],
+    capitalized_types: false,
+    functions: false,
+    macros: false,
+    attribute: None,
+    variables: true,
+    key_separator: None,
+    table_headers: false,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text of each token and its scope
+    fn spans<'a>(line: &'a str, tokens: &[Token]) -> Vec<(&'a str, Scope)> {
+        tokens
+            .iter()
+            .map(|token| (&line[token.columns.clone()], token.scope))
+            .collect()
+    }
+
+    fn tokenize(
+        filetype: Filetype,
+        line: &str,
+        state: LineState,
+    ) -> (Vec<(&str, Scope)>, LineState) {
+        let (tokens, end) = grammar_for(filetype).unwrap().tokenize(line, state);
+        (spans(line, &tokens), end)
+    }
+
+    #[test]
+    fn rust_tokens() {
+        use Scope::*;
+        let line = "#[test] fn a(x: Vec<u8>) { b!(1.5, 'c', \"d\") } // e";
+        let (spans, end) = tokenize(Filetype::Rust, line, LineState::Normal);
+        let expected = [
+            ("#[test]", Attribute),
+            ("fn", Keyword),
+            ("a", Function),
+            ("Vec", Type),
+            ("u8", Type),
+            ("b!", Macro),
+            ("1.5", Number),
+            ("'c'", String),
+            ("\"d\"", String),
+            ("// e", Comment),
+        ];
+        assert!(spans == expected);
+        assert!(end == LineState::Normal);
+
+        // 'a on its own is a lifetime rather than a character
+        let (spans, _) = tokenize(Filetype::Rust, "&'a str", LineState::Normal);
+        assert!(spans == [("str", Type)]);
+    }
+
+    #[test]
+    fn states_across_rows() {
+        use Scope::*;
+        // rust block comments nest
+        let (spans, end) = tokenize(Filetype::Rust, "a /* b /* c */", LineState::Normal);
+        assert!(spans == [("/* b /* c */", Comment)] && end == LineState::Comment(1));
+        let (spans, end) = tokenize(Filetype::Rust, "d */ true", LineState::Comment(1));
+        assert!(spans == [("d */", Comment), ("true", Constant)] && end == LineState::Normal);
+
+        let (spans, end) = tokenize(Filetype::Toml, "a = \"\"\"b", LineState::Normal);
+        assert!(spans == [("a", Key), ("\"\"\"b", String)]);
+        let (spans, end) = tokenize(Filetype::Toml, "c\"\"\" # d", end);
+        assert!(spans == [("c\"\"\"", String), ("# d", Comment)] && end == LineState::Normal);
+    }
+
+    #[test]
+    fn only_edited_rows_are_tokenized_again() {
+        let mut rows: Vec<Row> = ["a = '''", "b", "'''", "c = 1"]
+            .into_iter()
+            .map(Row::from)
+            .collect();
+        let mut highlighter = Highlighter::default();
+        let scopes = |highlighter: &Highlighter, y| {
+            highlighter
+                .tokens(y)
+                .iter()
+                .map(|token| token.scope)
+                .collect::<Vec<_>>()
+        };
+        highlighter.update(Filetype::Toml, &rows, None, 0..4);
+        assert!(scopes(&highlighter, 1) == [Scope::String]);
+        assert!(scopes(&highlighter, 3) == [Scope::Key, Scope::Number]);
+
+        // closing the string on the first row changes the state of every row after it
+        rows[0] = Row::from("a = ''''''");
+        let edit = RowEdit {
+            start: 0,
+            old_end: 1,
+            new_end: 1,
+        };
+        highlighter.update(Filetype::Toml, &rows, Some(edit), 0..4);
+        assert!(scopes(&highlighter, 1).is_empty());
+        assert!(scopes(&highlighter, 2) == [Scope::String]);
+
+        // rows taken out take their tokens with them
+        rows.remove(2);
+        let edit = RowEdit {
+            start: 2,
+            old_end: 3,
+            new_end: 2,
+        };
+        highlighter.update(Filetype::Toml, &rows, Some(edit), 0..3);
+        assert!(scopes(&highlighter, 1).is_empty());
+        assert!(scopes(&highlighter, 2) == [Scope::Key, Scope::Number]);
+    }
+}
@@ -1,5 +1,407 @@
-use crossterm::event::{KeyEvent, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 pub fn control_held(ev_key: KeyEvent) -> bool {
     ev_key.modifiers.contains(KeyModifiers::CONTROL)
 }
+
+pub fn alt_held(ev_key: KeyEvent) -> bool {
+    ev_key.modifiers.contains(KeyModifiers::ALT)
+}
+
+// Writes a key the way it is typed in a mapping or macro, e.g. `x`, `<Esc>`, `<C-w>`
+pub fn key_to_notation(ev_key: &KeyEvent) -> String {
+    let mut modifiers = String::new();
+    if ev_key.modifiers.contains(KeyModifiers::CONTROL) {
+        modifiers += "C-";
+    }
+    if ev_key.modifiers.contains(KeyModifiers::ALT) {
+        modifiers += "A-";
+    }
+    let name = match ev_key.code {
+        KeyCode::Char('<') if modifiers.is_empty() => "lt".to_string(),
+        KeyCode::Char(c) if modifiers.is_empty() => return c.to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "S-Tab".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::Insert => "Insert".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return String::new(),
+    };
+    format!("<{}{}>", modifiers, name)
+}
+
+// Reads keys written with key_to_notation, anything in <> that is not a key name is typed as is
+pub fn parse_keys(text: &str) -> Vec<KeyEvent> {
+    let mut keys = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                if let Some(key) = parse_key_name(&rest[1..end]) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        let key = match c {
+            '\n' => KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+            '\t' => KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE),
+            c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+        };
+        keys.push(key);
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+// A single key name from inside <>, with any C- A- S- modifiers in front
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        match rest.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => modifiers |= KeyModifiers::CONTROL,
+            b'A' | b'M' => modifiers |= KeyModifiers::ALT,
+            b'S' => modifiers |= KeyModifiers::SHIFT,
+            _ => return None,
+        }
+        rest = &rest[2..];
+    }
+
+    let code = match rest.to_ascii_lowercase().as_str() {
+        "lt" => KeyCode::Char('<'),
+        "space" => KeyCode::Char(' '),
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+            modifiers.remove(KeyModifiers::SHIFT);
+            KeyCode::BackTab
+        }
+        "tab" => KeyCode::Tab,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "insert" => KeyCode::Insert,
+        lower => match (lower.strip_prefix('f'), rest.chars().count()) {
+            (Some(n), _) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => {
+                KeyCode::F(n.parse().ok()?)
+            }
+            (_, 1) if modifiers != KeyModifiers::NONE => {
+                // unwrap - there is exactly one char
+                let c = rest.chars().next().unwrap();
+                if modifiers.contains(KeyModifiers::CONTROL) {
+                    KeyCode::Char(c.to_ascii_lowercase())
+                } else {
+                    KeyCode::Char(c)
+                }
+            }
+            _ => return None,
+        },
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+// The modes a mapping can apply in, replace mode uses the insert mode ones
+#[derive(Clone, Copy, PartialEq)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    Insert,
+}
+
+impl MapMode {
+    fn letter(&self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::Insert => 'i',
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum MapTo {
+    Keys(Vec<KeyEvent>),
+    // a named editor action, written <Action>name
+    Action(String),
+}
+
+#[derive(Clone)]
+pub struct Mapping {
+    pub mode: MapMode,
+    pub keys: Vec<KeyEvent>,
+    pub to: MapTo,
+    // whether the keys it maps to are looked up in the keymap again
+    pub recursive: bool,
+    // the file or command which made it, for :verbose map
+    pub set_from: String,
+}
+
+// What the keys typed so far come to
+pub enum Lookup<'a> {
+    // they begin a longer mapping, more keys or the timeout will tell
+    Wait,
+    // the first key is not the start of any mapping
+    Unmapped,
+    Mapped(&'a Mapping),
+}
+
+// User mappings from keys to other keys or actions, checked before the built in keys
+#[derive(Clone, Default)]
+pub struct Keymap {
+    mappings: Vec<Mapping>,
+}
+
+impl Keymap {
+    // Replaces any mapping of the same keys in the same mode
+    pub fn add(&mut self, mapping: Mapping) {
+        self.remove(mapping.mode, &mapping.keys);
+        self.mappings.push(mapping);
+    }
+
+    pub fn remove(&mut self, mode: MapMode, keys: &[KeyEvent]) -> bool {
+        let len = self.mappings.len();
+        self.mappings
+            .retain(|mapping| mapping.mode != mode || !same_keys(&mapping.keys, keys));
+        self.mappings.len() != len
+    }
+
+    // The longest mapping the keys start with, unless they could still become a longer one
+    // Once flushed, by the timeout or the end of a mapping's keys, nothing waits
+    pub fn lookup(&self, mode: MapMode, keys: &[KeyEvent], flush: bool) -> Lookup<'_> {
+        let in_mode = || self.mappings.iter().filter(|mapping| mapping.mode == mode);
+        let longer = in_mode().any(|mapping| {
+            mapping.keys.len() > keys.len() && same_keys(&mapping.keys[..keys.len()], keys)
+        });
+        if longer && !flush {
+            return Lookup::Wait;
+        }
+        in_mode()
+            .filter(|mapping| {
+                mapping.keys.len() <= keys.len()
+                    && same_keys(&keys[..mapping.keys.len()], &mapping.keys)
+            })
+            .max_by_key(|mapping| mapping.keys.len())
+            .map_or(Lookup::Unmapped, Lookup::Mapped)
+    }
+
+    // One line per mapping in the modes whose keys start with prefix, as :map lists them
+    pub fn listing(&self, modes: &[MapMode], prefix: &[KeyEvent], verbose: bool) -> Vec<String> {
+        let mut lines = vec![];
+        for mapping in &self.mappings {
+            if !modes.contains(&mapping.mode)
+                || mapping.keys.len() < prefix.len()
+                || !same_keys(&mapping.keys[..prefix.len()], prefix)
+            {
+                continue;
+            }
+            let keys: String = mapping.keys.iter().map(map_notation).collect();
+            let to = match &mapping.to {
+                MapTo::Keys(to) => to.iter().map(map_notation).collect(),
+                MapTo::Action(name) => format!("<Action>{}", name),
+            };
+            let remap = if mapping.recursive { ' ' } else { '*' };
+            lines.push(format!(
+                "{}  {:12} {}{}",
+                mapping.mode.letter(),
+                keys,
+                remap,
+                to
+            ));
+            if verbose {
+                lines.push(format!("        Last set from {}", mapping.set_from));
+            }
+        }
+        lines
+    }
+}
+
+// Spaces are easy to miss in a listing, so they are written out
+fn map_notation(ev_key: &KeyEvent) -> String {
+    match ev_key.code {
+        KeyCode::Char(' ') if ev_key.modifiers.is_empty() => "<Space>".to_string(),
+        _ => key_to_notation(ev_key),
+    }
+}
+
+// Keys the way a mapping is written, with <leader> standing for the leader's keys
+pub fn parse_mapped_keys(text: &str, leader: &str) -> Vec<KeyEvent> {
+    let mut keys = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.to_ascii_lowercase().find("<leader>") {
+        keys.extend(parse_keys(&rest[..start]));
+        keys.extend(parse_keys(leader));
+        rest = &rest[start + "<leader>".len()..];
+    }
+    keys.extend(parse_keys(rest));
+    keys
+}
+
+// What a mapping maps to, keys or <Action>name
+pub fn parse_map_to(text: &str, leader: &str) -> MapTo {
+    match text.strip_prefix("<Action>") {
+        Some(name) => MapTo::Action(name.trim().to_string()),
+        None => MapTo::Keys(parse_mapped_keys(text, leader)),
+    }
+}
+
+// Keys from the terminal come with shift set for capital letters, parsed ones do not
+pub fn same_key(a: &KeyEvent, b: &KeyEvent) -> bool {
+    let modifiers = |key: &KeyEvent| match key.code {
+        KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+        _ => key.modifiers,
+    };
+    a.code == b.code && modifiers(a) == modifiers(b)
+}
+
+fn same_keys(a: &[KeyEvent], b: &[KeyEvent]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_key(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(maps: &[(MapMode, &str, &str)]) -> Keymap {
+        let mut keymap = Keymap::default();
+        for (mode, keys, to) in maps {
+            keymap.add(Mapping {
+                mode: *mode,
+                keys: parse_keys(keys),
+                to: parse_map_to(to, "\\"),
+                recursive: false,
+                set_from: String::new(),
+            });
+        }
+        keymap
+    }
+
+    // The keys of the mapping found, with Wait and Unmapped written out
+    fn found(keymap: &Keymap, mode: MapMode, keys: &str, flush: bool) -> String {
+        match keymap.lookup(mode, &parse_keys(keys), flush) {
+            Lookup::Wait => "wait".to_string(),
+            Lookup::Unmapped => "unmapped".to_string(),
+            Lookup::Mapped(mapping) => mapping.keys.iter().map(key_to_notation).collect(),
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let keymap = keymap(&[
+            (MapMode::Normal, "g", "x"),
+            (MapMode::Normal, "gq", "y"),
+            (MapMode::Normal, "<C-w>c", "z"),
+            (MapMode::Insert, "jk", "<Esc>"),
+        ]);
+        let normal = MapMode::Normal;
+        assert_eq!(found(&keymap, normal, "x", false), "unmapped");
+        assert_eq!(found(&keymap, normal, "gq", false), "gq");
+        // g may still become gq
+        assert_eq!(found(&keymap, normal, "g", false), "wait");
+        assert_eq!(found(&keymap, normal, "g", true), "g");
+        // the longest mapping the keys start with wins
+        assert_eq!(found(&keymap, normal, "gx", false), "g");
+        assert_eq!(found(&keymap, normal, "gqq", false), "gq");
+        assert_eq!(found(&keymap, normal, "<C-w>", false), "wait");
+        assert_eq!(found(&keymap, normal, "<C-w>", true), "unmapped");
+        assert_eq!(found(&keymap, normal, "<C-w>c", false), "<C-w>c");
+        // other modes' mappings are left alone
+        assert_eq!(found(&keymap, normal, "jk", false), "unmapped");
+        assert_eq!(found(&keymap, MapMode::Insert, "jk", false), "jk");
+        assert_eq!(found(&keymap, MapMode::Visual, "g", false), "unmapped");
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut keymap = keymap(&[(MapMode::Normal, "gq", "x"), (MapMode::Visual, "gq", "y")]);
+        keymap.add(Mapping {
+            mode: MapMode::Normal,
+            keys: parse_keys("gq"),
+            to: MapTo::Keys(parse_keys("z")),
+            recursive: true,
+            set_from: String::new(),
+        });
+        assert_eq!(keymap.mappings.len(), 2);
+        assert!(keymap.remove(MapMode::Normal, &parse_keys("gq")));
+        assert!(!keymap.remove(MapMode::Normal, &parse_keys("gq")));
+        assert_eq!(found(&keymap, MapMode::Visual, "gq", false), "gq");
+    }
+
+    #[test]
+    fn leader_and_actions() {
+        let keys = parse_mapped_keys("<Leader>w<leader>", ",");
+        assert_eq!(keys.iter().map(key_to_notation).collect::<String>(), ",w,");
+
+        match parse_map_to("<Action>save ", ",") {
+            MapTo::Action(name) => assert_eq!(name, "save"),
+            MapTo::Keys(_) => panic!("expected an action"),
+        }
+        match parse_map_to("<leader>dd", " ") {
+            MapTo::Keys(keys) => assert_eq!(keys.len(), 3),
+            MapTo::Action(_) => panic!("expected keys"),
+        }
+    }
+
+    #[test]
+    fn shifted_chars_match() {
+        let typed = KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT);
+        let parsed = KeyEvent::new(KeyCode::Char('G'), KeyModifiers::NONE);
+        assert!(same_key(&typed, &parsed));
+        let back_tab = KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT);
+        assert!(!same_key(
+            &back_tab,
+            &KeyEvent::new(KeyCode::BackTab, KeyModifiers::NONE)
+        ));
+    }
+
+    #[test]
+    fn notation_round_trip() {
+        for text in [
+            "dd",
+            "<lt>a b",
+            " ",
+            "<Esc>:w<CR>",
+            "<C-w><C-A-x><A-j>",
+            "<C-Space><S-Tab><Tab>",
+            "<BS><Del><Up><Down><Left><Right>",
+            "<Home><End><PageUp><PageDown><Insert><F1><F12>",
+        ] {
+            let keys = parse_keys(text);
+            let written: String = keys.iter().map(key_to_notation).collect();
+            assert_eq!(written, text);
+            assert!(same_keys(&parse_keys(&written), &keys));
+        }
+    }
+
+    #[test]
+    fn notation_aliases() {
+        let written = |text| -> String { parse_keys(text).iter().map(key_to_notation).collect() };
+        assert_eq!(written("<enter><ESC><c-W><M-x>"), "<CR><Esc><C-w><A-x>");
+        assert_eq!(written("<Space>"), " ");
+        assert_eq!(written("a\tb\n"), "a<Tab>b<CR>");
+        // anything else in <> is typed as is
+        assert_eq!(written("<foo><"), "<lt>foo><lt>");
+        assert_eq!(written("<C-"), "<lt>C-");
+    }
+}
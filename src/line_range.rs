@@ -0,0 +1,151 @@
+// Line addresses typed ahead of a command, like `:.,$` or `:'a,'b+1`
+// They are parsed here and resolved against the document by the editor
+
+#[derive(Clone, Copy)]
+pub enum LineAddress {
+    Current,       // .
+    Last,          // $
+    Number(usize), // a 1-based line, 0 being before the first
+    Mark(char),    // 'a
+}
+
+#[derive(Clone, Copy)]
+pub struct Address {
+    pub line: LineAddress,
+    pub offset: isize,
+}
+
+#[derive(Clone, Copy)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+}
+
+impl LineRange {
+    // %, every line in the document
+    pub fn whole() -> Self {
+        Self {
+            start: Address {
+                line: LineAddress::Number(1),
+                offset: 0,
+            },
+            end: Address {
+                line: LineAddress::Last,
+                offset: 0,
+            },
+        }
+    }
+}
+
+// Reads an address off the front of text, returning it with the text after it
+pub fn parse_address(text: &str) -> Option<(Address, &str)> {
+    let (line, mut rest) = match text.chars().next()? {
+        '.' => (LineAddress::Current, &text[1..]),
+        '$' => (LineAddress::Last, &text[1..]),
+        '\'' => {
+            let mark = text[1..].chars().next()?;
+            (LineAddress::Mark(mark), &text[1 + mark.len_utf8()..])
+        }
+        c if c.is_ascii_digit() => {
+            let len = digits_len(text);
+            (LineAddress::Number(text[..len].parse().ok()?), &text[len..])
+        }
+        '+' | '-' => (LineAddress::Current, text),
+        _ => return None,
+    };
+
+    let mut offset = 0;
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        rest = &rest[1..];
+        let len = digits_len(rest);
+        // a sign by itself counts as one
+        let by: isize = match len {
+            0 => 1,
+            _ => rest[..len].parse().ok()?,
+        };
+        offset += if sign == '+' { by } else { -by };
+        rest = &rest[len..];
+    }
+    Some((Address { line, offset }, rest))
+}
+
+// Reads a range off the front of text, None when the command has no range
+pub fn parse_range(text: &str) -> (Option<LineRange>, &str) {
+    if let Some(rest) = text.strip_prefix('%') {
+        return (Some(LineRange::whole()), rest);
+    }
+    let Some((start, rest)) = parse_address(text) else {
+        return (None, text);
+    };
+    match rest.strip_prefix(',').and_then(parse_address) {
+        Some((end, rest)) => (Some(LineRange { start, end }), rest),
+        None => (Some(LineRange { start, end: start }), rest),
+    }
+}
+
+fn digits_len(text: &str) -> usize {
+    text.find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_forms() {
+        let (address, rest) = parse_address(".+2d").unwrap();
+        assert!(matches!(address.line, LineAddress::Current));
+        assert_eq!((address.offset, rest), (2, "d"));
+
+        let (address, rest) = parse_address("$-").unwrap();
+        assert!(matches!(address.line, LineAddress::Last));
+        assert_eq!((address.offset, rest), (-1, ""));
+
+        let (address, rest) = parse_address("'a+1-3,").unwrap();
+        assert!(matches!(address.line, LineAddress::Mark('a')));
+        assert_eq!((address.offset, rest), (-2, ","));
+
+        let (address, rest) = parse_address("12sort").unwrap();
+        assert!(matches!(address.line, LineAddress::Number(12)));
+        assert_eq!((address.offset, rest), (0, "sort"));
+
+        // an offset alone is from the current line
+        let (address, _) = parse_address("+3").unwrap();
+        assert!(matches!(address.line, LineAddress::Current));
+        assert_eq!(address.offset, 3);
+    }
+
+    #[test]
+    fn address_missing() {
+        assert!(parse_address("").is_none());
+        assert!(parse_address("w").is_none());
+        assert!(parse_address("'").is_none());
+    }
+
+    #[test]
+    fn ranges() {
+        let (range, rest) = parse_range("%s");
+        let range = range.unwrap();
+        assert!(matches!(range.start.line, LineAddress::Number(1)));
+        assert!(matches!(range.end.line, LineAddress::Last));
+        assert_eq!(rest, "s");
+
+        let (range, rest) = parse_range("3,'b+1m0");
+        let range = range.unwrap();
+        assert!(matches!(range.start.line, LineAddress::Number(3)));
+        assert!(matches!(range.end.line, LineAddress::Mark('b')));
+        assert_eq!((range.end.offset, rest), (1, "m0"));
+
+        // one address is a range of one line
+        let (range, rest) = parse_range(".d");
+        let range = range.unwrap();
+        assert!(matches!(range.start.line, LineAddress::Current));
+        assert!(matches!(range.end.line, LineAddress::Current));
+        assert_eq!(rest, "d");
+
+        let (range, rest) = parse_range("sort");
+        assert!(range.is_none());
+        assert_eq!(rest, "sort");
+    }
+}
@@ -2,13 +2,24 @@
 // Modified to use crossterm
 use editor::Editor;
 
+mod clipboard;
+mod config;
 mod document;
 mod editor;
+mod filetype;
+mod highlight;
 mod keybinds;
+mod line_range;
+mod marks;
 mod modal;
+mod registers;
+mod signs;
+mod status_line;
 mod status_message;
+mod syntax;
 mod terminal;
 mod text_target;
+mod theme;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,126 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::editor::Position;
+
+// File marks A-Z, which remember the file they are in and are kept between sessions
+// Stored one per line as `A row column /absolute/path`
+#[derive(Default)]
+pub struct FileMarks {
+    marks: BTreeMap<char, (PathBuf, Position)>,
+}
+
+impl FileMarks {
+    // Reads the saved marks, a missing or unreadable file just means there are none
+    pub fn load() -> Self {
+        let mut marks = BTreeMap::new();
+        let Some(text) = marks_path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return Self { marks };
+        };
+        for line in text.lines() {
+            let mut parts = line.splitn(4, ' ');
+            let (Some(name), Some(y), Some(x), Some(path)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let (Some(name), Ok(y), Ok(x)) = (name.chars().next(), y.parse(), x.parse()) else {
+                continue;
+            };
+            if name.is_ascii_uppercase() {
+                marks.insert(name, (PathBuf::from(path), Position { x, y }));
+            }
+        }
+        Self { marks }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = marks_path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::create(path)?;
+        for (name, (path, pos)) in &self.marks {
+            writeln!(file, "{} {} {} {}", name, pos.y, pos.x, path.display())?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: char) -> Option<&(PathBuf, Position)> {
+        self.marks.get(&name)
+    }
+
+    // The marks in the given file
+    pub fn in_file<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (char, Position)> + 'a {
+        self.marks
+            .iter()
+            .filter(move |(_, (file, _))| file == path)
+            .map(|(name, (_, pos))| (*name, *pos))
+    }
+
+    // Replaces every mark in the file with the given ones
+    pub fn set_file(&mut self, path: &Path, marks: impl Iterator<Item = (char, Position)>) {
+        self.marks.retain(|_, (file, _)| file != path);
+        for (name, pos) in marks {
+            self.marks.insert(name, (path.to_path_buf(), pos));
+        }
+    }
+}
+
+// The file's path from the root so marks find it from any directory
+pub fn absolute_path(file_name: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => env::current_dir().unwrap_or_default().join(path),
+    }
+}
+
+fn marks_path() -> Option<PathBuf> {
+    let data = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data.join("textist").join("marks"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_by_file() {
+        let mut marks = FileMarks::default();
+        let (a, b) = (Path::new("/a.rs"), Path::new("/b.rs"));
+        marks.set_file(
+            a,
+            [('A', Position { x: 1, y: 2 }), ('B', Position::default())].into_iter(),
+        );
+        marks.set_file(b, [('C', Position { x: 0, y: 5 })].into_iter());
+        assert_eq!(
+            marks.in_file(a).map(|(name, _)| name).collect::<String>(),
+            "AB"
+        );
+
+        // setting a file's marks again drops those no longer in it
+        marks.set_file(a, [('A', Position { x: 3, y: 4 })].into_iter());
+        assert_eq!(
+            marks.in_file(a).map(|(name, _)| name).collect::<String>(),
+            "A"
+        );
+        assert!(marks.get('B').is_none());
+        let (path, pos) = marks.get('A').unwrap();
+        assert!(path == a && *pos == Position { x: 3, y: 4 });
+
+        // a mark set in another file moves there
+        marks.set_file(b, [('A', Position::default())].into_iter());
+        assert!(marks.in_file(a).next().is_none());
+        assert_eq!(marks.in_file(b).count(), 1);
+    }
+}
@@ -4,46 +4,376 @@ use std::{fmt::Display, io};
 // TODO: figure out ways to handle actions 100gg which require a number and a second press
 use crossterm::{
     cursor::SetCursorStyle,
-    event::{KeyCode, KeyEvent, KeyEventKind},
+    event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     execute,
 };
 
-use crate::{keybinds::control_held, text_target::TextTarget};
+use crate::{
+    document::{CaseChange, SortOptions},
+    keybinds::{alt_held, control_held, MapMode},
+    line_range::{parse_address, parse_range, Address, LineRange},
+    registers::Registers,
+    text_target::{FindKind, ObjectScope, TextObject, TextTarget},
+};
 
+#[derive(Default)]
 pub struct ModalInputter {
     mode: InputMode,
+    // the last f F t T search, repeated by ; and ,
+    last_find: Option<(FindKind, char)>,
+
+    // keys of the normal command being typed, leaving out its count
+    command_keys: Vec<InputEvent>,
+    // the last change made, repeated by .
+    last_change: Option<Change>,
+    // the last change went into insert mode so typed keys belong to it
+    recording_insert: bool,
+    // set while replaying events so they are not recorded again
+    replaying: bool,
+    // the register and keys of the macro being recorded
+    recording: Option<(char, Vec<InputEvent>)>,
+
+    // what is typed in insert mode when it was entered with a count
+    insert_repeat: Option<InsertRepeat>,
+    // Ctrl-r in insert mode waiting on the register name
+    insert_register_pending: bool,
+    // Ctrl-o in insert mode, back to insert mode after one normal command
+    resume_insert: bool,
 }
 
-impl Default for ModalInputter {
-    fn default() -> Self {
-        Self {
-            mode: Default::default(),
-        }
+// Raw input as it came from the terminal
+#[derive(Clone)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Paste(String),
+}
+
+// A normal command that changed the document and whatever was typed in insert mode after it
+struct Change {
+    count: Option<usize>,
+    events: Vec<InputEvent>,
+}
+
+// The count an insert was started with, the text typed is repeated count times in all
+struct InsertRepeat {
+    count: usize,
+    // o and O put each repeat on a row of its own
+    new_line: bool,
+    events: Vec<InputEvent>,
+}
+
+impl InsertRepeat {
+    // the events to type again for the rest of the count
+    fn repeated(self) -> Vec<InputEvent> {
+        let new_line = self
+            .new_line
+            .then(|| InputEvent::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)));
+        (1..self.count)
+            .flat_map(|_| {
+                new_line
+                    .clone()
+                    .into_iter()
+                    .chain(self.events.iter().cloned())
+            })
+            .collect()
     }
 }
 
 impl Display for ModalInputter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.mode.to_string())
+        if self.resume_insert {
+            return write!(f, "(Insert)");
+        }
+        match &self.recording {
+            Some((register, _)) => write!(f, "{} recording @{}", self.mode, register),
+            None => write!(f, "{}", self.mode),
+        }
     }
 }
 
+#[derive(Clone)]
 pub enum InputAction {
     NoAction,       // used when key press cannot resolve into an action
     InvalidCommand, // when command mode does not produce a valid command
     Save,
     Quit,
     SaveAndQuit,
-    MoveCursor { direction: Direction, count: usize },
+    MoveCursor {
+        direction: Direction,
+        count: usize,
+    },
+    // gj gk, by the screen lines of wrapped rows
+    MoveScreenLine {
+        direction: VerticalDirection,
+        count: usize,
+    },
     InsertChar(char),
+    InsertText(String),
+    // spaces up to the next tab stop
+    InsertTab,
+    // Ctrl-r{register} in insert mode
+    InsertRegister(char),
+    Insert(InsertAt),
+    // leaves insert mode after typing the events again for the count it was entered with
+    FinishInsert(Vec<InputEvent>),
+    // a normal command run from insert mode with Ctrl-o, insert mode comes back after it
+    ThenInsert(Box<InputAction>),
+    DeleteWordBehind,
+    DeleteToRowStart,
+    // indents or outdents count rows from the cursor
+    ShiftRows {
+        right: bool,
+        count: usize,
+    },
+    MoveToRowStart,
+    MoveToRowEnd,
+    Page(VerticalDirection),
     SwitchMode(InputMode),
-    NewLine { count: usize },
-    DeleteBehind { count: usize },
-    DeleteAhead { count: usize },
-    PasteYanked(Direction),
+    NewLine {
+        count: usize,
+    },
+    DeleteBehind {
+        count: usize,
+    },
+    DeleteAhead {
+        count: usize,
+    },
+    Paste {
+        direction: Direction,
+        count: usize,
+        register: Option<char>,
+    },
     NewLineAndInsert(VerticalDirection),
     CommandPrompt,
-    Delete(TextTarget),
+    Delete {
+        target: TextTarget,
+        count: usize,
+        register: Option<char>,
+    },
+    Yank {
+        target: TextTarget,
+        count: usize,
+        register: Option<char>,
+    },
+    // deletes the target then switches to insert mode
+    Change {
+        target: TextTarget,
+        count: usize,
+        register: Option<char>,
+    },
+    ReplaceChar {
+        c: char,
+        count: usize,
+    },
+    // > and < on the rows a target covers
+    Shift {
+        right: bool,
+        target: TextTarget,
+        count: usize,
+    },
+    Reindent {
+        target: TextTarget,
+        count: usize,
+    },
+    Comment {
+        target: TextTarget,
+        count: usize,
+    },
+    ChangeCase {
+        case: CaseChange,
+        target: TextTarget,
+        count: usize,
+    },
+    // Ctrl-a and Ctrl-x, on each row of a selection in visual mode
+    // progressive adds amount once more for each row after the first, g Ctrl-a
+    // there is no block mode, so only the selection's first and last rows are limited to its columns
+    Increment {
+        amount: i64,
+        progressive: bool,
+    },
+    // typed in replace mode, writes over the character under the cursor
+    OverwriteChar(char),
+    // backspace in replace mode, puts back what was written over
+    RestoreBehind,
+    ShowRegisters,
+    // J and gJ on the rows a target covers, at least two
+    Join {
+        target: TextTarget,
+        count: usize,
+        spaces: bool,
+    },
+    // moves or copies the rows to below the line `to`
+    MoveRows {
+        range: Option<LineRange>,
+        to: Address,
+    },
+    CopyRows {
+        range: Option<LineRange>,
+        to: Address,
+    },
+    // without a range these go over the whole document
+    SortRows {
+        range: Option<LineRange>,
+        options: SortOptions,
+    },
+    UniqRows {
+        range: Option<LineRange>,
+    },
+    // a range by itself moves to its last line
+    GotoRange(LineRange),
+    Jump {
+        target: TextTarget,
+        count: usize,
+    },
+    // sets the visual selection to the target
+    Select {
+        target: TextTarget,
+        count: usize,
+    },
+    RepeatChange {
+        count: Option<usize>,
+    },
+    RecordMacro(char),
+    StopRecording,
+    PlayMacro {
+        register: char,
+        count: usize,
+    },
+    SetMark(char),
+    // exact goes to the marked column, otherwise the first non blank of its row
+    JumpToMark {
+        mark: char,
+        exact: bool,
+    },
+    ShowMarks,
+    // switches to the named theme, or lists them when no name is given
+    ColorScheme(String),
+    // :map and the like, args being the keys and what they map to
+    // with only keys, or none, it lists the mappings starting with them
+    Map {
+        modes: Vec<MapMode>,
+        recursive: bool,
+        verbose: bool,
+        args: String,
+    },
+    Unmap {
+        modes: Vec<MapMode>,
+        keys: String,
+    },
+    // :set with its arguments
+    SetOptions(String),
+    // reads settings from the file, or from config.toml again
+    Source(Option<String>),
+    // adds a cursor where the word under the cursor next appears
+    AddCursorAtNext,
+    // a cursor on each row of the selection
+    CursorsOnRows,
+    // a cursor at each match of a pattern asked for inside the selection
+    SplitSelection,
+    ClearCursors,
+    // 1-based line, the last line when there is none
+    GotoLine(Option<usize>),
+    JumpBack {
+        count: usize,
+    },
+    // to the start of the next or previous function in the syntax tree
+    JumpFunction {
+        forward: bool,
+        count: usize,
+    },
+    // selects the syntax node around the selection, or goes back to the one before
+    ExpandSelection,
+    ShrinkSelection,
+    JumpForward {
+        count: usize,
+    },
+    // u and Ctrl-r, a change being one normal command or everything typed in one insert
+    Undo {
+        count: usize,
+    },
+    Redo {
+        count: usize,
+    },
+}
+
+impl InputAction {
+    // actions which change the document, the ones . can repeat
+    fn is_change(&self) -> bool {
+        matches!(
+            self,
+            InputAction::Delete { .. }
+                | InputAction::Change { .. }
+                | InputAction::ReplaceChar { .. }
+                | InputAction::Shift { .. }
+                | InputAction::Join { .. }
+                | InputAction::Comment { .. }
+                | InputAction::ChangeCase { .. }
+                | InputAction::Increment { .. }
+                | InputAction::Reindent { .. }
+                | InputAction::Paste { .. }
+                | InputAction::NewLineAndInsert(_)
+                | InputAction::Insert(_)
+                | InputAction::SwitchMode(InputMode::Replace)
+        )
+    }
+
+    // typing and the normal mode edits and motions, which happen at every cursor when there are several
+    pub fn is_per_cursor(&self) -> bool {
+        matches!(
+            self,
+            InputAction::InsertChar(_)
+                | InputAction::InsertText(_)
+                | InputAction::InsertTab
+                | InputAction::InsertRegister(_)
+                | InputAction::Insert(_)
+                | InputAction::DeleteWordBehind
+                | InputAction::DeleteToRowStart
+                | InputAction::ShiftRows { .. }
+                | InputAction::NewLine { .. }
+                | InputAction::DeleteBehind { .. }
+                | InputAction::DeleteAhead { .. }
+                | InputAction::MoveCursor { .. }
+                | InputAction::MoveScreenLine { .. }
+                | InputAction::MoveToRowStart
+                | InputAction::MoveToRowEnd
+                | InputAction::Jump { .. }
+                | InputAction::NewLineAndInsert(_)
+                | InputAction::Paste { .. }
+                | InputAction::Delete { .. }
+                | InputAction::Yank { .. }
+                | InputAction::Change { .. }
+                | InputAction::ReplaceChar { .. }
+                | InputAction::Shift { .. }
+                | InputAction::Reindent { .. }
+                | InputAction::Comment { .. }
+                | InputAction::ChangeCase { .. }
+                | InputAction::Increment { .. }
+                | InputAction::Join { .. }
+        )
+    }
+
+    // actions taking the cursor somewhere else or moving rows about, which extra cursors cannot follow
+    pub fn drops_cursors(&self) -> bool {
+        matches!(
+            self,
+            InputAction::Page(_)
+                | InputAction::GotoLine(_)
+                | InputAction::GotoRange(_)
+                | InputAction::JumpToMark { .. }
+                | InputAction::JumpBack { .. }
+                | InputAction::JumpForward { .. }
+                | InputAction::JumpFunction { .. }
+                | InputAction::Select { .. }
+                | InputAction::ExpandSelection
+                | InputAction::ShrinkSelection
+                | InputAction::MoveRows { .. }
+                | InputAction::CopyRows { .. }
+                | InputAction::SortRows { .. }
+                | InputAction::UniqRows { .. }
+                | InputAction::Undo { .. }
+                | InputAction::Redo { .. }
+        )
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -54,7 +384,7 @@ pub enum Direction {
     Right,
 }
 
-//NOTE: couples keycode to direction, may be difficult to implment keybinds for directions
+// The built in keys for each direction, user mappings are looked up before these
 impl From<KeyCode> for Direction {
     fn from(value: KeyCode) -> Self {
         match value {
@@ -74,16 +404,29 @@ impl From<KeyCode> for Direction {
     }
 }
 
+// Where the cursor goes when entering insert mode, i a I A and gi respectively
+#[derive(Clone, Copy)]
+pub enum InsertAt {
+    Cursor,
+    AfterCursor,
+    FirstNonBlank,
+    RowEnd,
+    LastInsert,
+}
+
 #[derive(Clone, Copy)]
 pub enum VerticalDirection {
     Up,
     Down,
 }
 
+#[derive(Clone, Copy)]
 pub enum InputMode {
     Normal(NormalInput),
     Insert,
     Command,
+    Visual(NormalInput),
+    Replace,
 }
 
 impl Display for InputMode {
@@ -92,6 +435,8 @@ impl Display for InputMode {
             InputMode::Normal(_) => "Normal",
             InputMode::Insert => "Insert",
             InputMode::Command => "Command",
+            InputMode::Visual(_) => "Visual",
+            InputMode::Replace => "Replace",
         };
         write!(f, "{}", mode)
     }
@@ -108,6 +453,24 @@ pub struct NormalInput {
     num_modifier: Option<usize>,
     command: Option<Command>,
     target: Option<TextTarget>,
+    // set while a key is waiting on the character after it
+    pending: Option<Pending>,
+    register: Option<char>,
+}
+
+// Keys which take the character typed after them
+#[derive(Clone, Copy)]
+enum Pending {
+    Find(FindKind),      // f F t T waiting on the character to search for
+    Object(ObjectScope), // i or a waiting on which text object to use
+    Register,            // " waiting on the register name
+    Record,              // q waiting on the register to record into
+    Play,                // @ waiting on the register to play
+    Replace,             // r waiting on the replacement character
+    Mark,                // m waiting on the mark to set
+    MarkJump(bool),      // ' or ` waiting on the mark to jump to, exact for `
+    G,                   // g waiting on the rest of a g command
+    Bracket(bool),       // ] or [ waiting on what to jump to, forward for ]
 }
 
 impl NormalInput {
@@ -118,7 +481,7 @@ impl NormalInput {
         let num: u32 = num.to_digit(10).unwrap();
 
         self.num_modifier = match self.num_modifier {
-            Some(n) => Some(n * 10 + n),
+            Some(n) => Some(n.saturating_mul(10).saturating_add(num as usize)),
             None => {
                 if num == 0 {
                     None
@@ -128,18 +491,72 @@ impl NormalInput {
             }
         };
     }
+
+    // Waits on the next key press for the character to find, motion by itself or target after a command
+    fn start_find(&mut self, kind: FindKind) {
+        self.command.get_or_insert(Command::Jump);
+        self.pending = Some(Pending::Find(kind));
+    }
+
+    // nothing has been typed towards a command
+    fn is_empty(&self) -> bool {
+        self.command.is_none()
+            && self.num_modifier.is_none()
+            && self.register.is_none()
+            && !self.is_pending()
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
 }
 
 #[derive(Clone, Copy)]
 enum Command {
-    SwitchInsert,
+    Insert(InsertAt),
     Move(Direction),
+    MoveScreenLine(VerticalDirection),
     Quit,
     NewLineAndInsert(VerticalDirection),
-    None,
-    CommandInput,
+    None, // No command, currently used when an unbound key is pressed when waiting on a command
+    Prompt,
     Delete,
-    Paste(Direction), // No command, currently used when an unbound key is pressed when waiting on a command
+    Yank,
+    Change,
+    Shift(bool), // true for >
+    Join(bool),  // true when spaces go between the joined rows
+    Case(CaseChange),
+    Comment,
+    AddCursor,
+    CursorsOnRows,
+    SplitSelection,
+    ClearCursors,
+    Increment(i64, bool), // the step and whether it grows each row
+    Reindent,
+    ReplaceChar(char),
+    SwitchReplace,
+    Paste(Direction),
+    Jump, // moves the cursor to wherever the target resolves to
+    RepeatChange,
+    RecordMacro(char),
+    StopRecording,
+    PlayMacro(char),
+    SetMark(char),
+    JumpToMark(char, bool),
+    GotoLine(bool), // true when no count means the last line
+    JumpBack,
+    JumpForward,
+    JumpFunction(bool),
+    Undo,
+    Redo,
+    ExpandSelection,
+    ShrinkSelection,
+    SwitchVisual,
+    SwitchNormal,
+    Select,
+    RowStart,
+    RowEnd,
+    Page(VerticalDirection),
 }
 
 impl ModalInputter {
@@ -147,10 +564,41 @@ impl ModalInputter {
         match self.mode {
             InputMode::Normal(input) => {
                 let new_input = self.handle_normal_input(ev_key, input);
+                if new_input.is_empty() {
+                    self.command_keys.clear();
+                } else if ev_key.kind == KeyEventKind::Press
+                    && new_input.num_modifier == input.num_modifier
+                {
+                    self.command_keys.push(InputEvent::Key(ev_key));
+                }
                 match evaluate_normal_input(new_input) {
                     Some(action) => {
                         // the input buffer is reset since we are issuing the action
                         self.mode = InputMode::Normal(NormalInput::default());
+                        let keys = std::mem::take(&mut self.command_keys);
+                        if action.is_change() && !self.replaying {
+                            self.last_change = Some(Change {
+                                count: new_input.num_modifier,
+                                events: keys,
+                            });
+                            self.recording_insert = true;
+                        }
+                        self.insert_repeat = match action {
+                            InputAction::Insert(_) | InputAction::NewLineAndInsert(_) => {
+                                Some(InsertRepeat {
+                                    count: new_input.num_modifier.unwrap_or(1),
+                                    new_line: matches!(action, InputAction::NewLineAndInsert(_)),
+                                    events: vec![],
+                                })
+                            }
+                            _ => None,
+                        };
+                        if self.resume_insert {
+                            self.resume_insert = false;
+                            if !matches!(action, InputAction::SwitchMode(_)) {
+                                return InputAction::ThenInsert(Box::new(action));
+                            }
+                        }
                         action
                     }
                     None => {
@@ -160,21 +608,380 @@ impl ModalInputter {
                     }
                 }
             }
-            InputMode::Insert => self.handle_insert_input(ev_key),
+            InputMode::Visual(input) => {
+                let new_input = self.handle_visual_input(ev_key, input);
+                match evaluate_normal_input(new_input) {
+                    Some(action) => {
+                        self.mode = InputMode::Visual(NormalInput::default());
+                        action
+                    }
+                    None => {
+                        self.mode = InputMode::Visual(new_input);
+                        InputAction::NoAction
+                    }
+                }
+            }
+            InputMode::Insert => {
+                self.record_insert(InputEvent::Key(ev_key));
+                let action = self.handle_insert_input(ev_key);
+                self.record_insert_repeat(InputEvent::Key(ev_key));
+                action
+            }
+            InputMode::Replace => {
+                self.record_insert(InputEvent::Key(ev_key));
+                self.handle_replace_input(ev_key)
+            }
             InputMode::Command => InputAction::CommandPrompt,
         }
     }
 
-    fn handle_normal_input(&self, ev_key: KeyEvent, input_buffer: NormalInput) -> NormalInput {
+    fn record_insert(&mut self, event: InputEvent) {
+        if !self.recording_insert || self.replaying {
+            return;
+        }
+        if let Some(change) = self.last_change.as_mut() {
+            change.events.push(event);
+        }
+    }
+
+    // Keeps typed input to repeat for the insert's count, the Esc ending it has already taken it
+    fn record_insert_repeat(&mut self, event: InputEvent) {
+        match (&event, self.insert_repeat.as_mut()) {
+            (InputEvent::Key(ev_key), _) if ev_key.kind != KeyEventKind::Press => {}
+            (_, Some(repeat)) => repeat.events.push(event),
+            _ => {}
+        }
+    }
+
+    // The events to replay the last change, using count instead of the original if given
+    pub fn change_to_repeat(&self, count: Option<usize>) -> Vec<InputEvent> {
+        let Some(change) = &self.last_change else {
+            return vec![];
+        };
+        let digits = match count.or(change.count) {
+            Some(count) => count.to_string(),
+            None => String::new(),
+        };
+        digits
+            .chars()
+            .map(|d| InputEvent::Key(KeyEvent::new(KeyCode::Char(d), KeyModifiers::NONE)))
+            .chain(change.events.iter().cloned())
+            .collect()
+    }
+
+    pub fn start_recording(&mut self, register: char) {
+        self.recording = Some((register, vec![]));
+    }
+
+    // Keeps input typed while recording a macro, replayed input should not call this
+    pub fn record_macro_event(&mut self, event: &InputEvent) {
+        let Some((_, events)) = self.recording.as_mut() else {
+            return;
+        };
+        match event {
+            InputEvent::Key(ev_key) if ev_key.kind != KeyEventKind::Press => {}
+            _ => events.push(event.clone()),
+        }
+    }
+
+    // Finishes the macro, leaving out the q which stopped it
+    pub fn stop_recording(&mut self) -> Option<(char, Vec<InputEvent>)> {
+        let (register, mut events) = self.recording.take()?;
+        events.pop();
+        Some((register, events))
+    }
+
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    // Pasted text is typed as is in insert mode, other modes have no use for it
+    pub fn process_paste(&mut self, text: String) -> InputAction {
+        match self.mode {
+            InputMode::Insert => {
+                self.record_insert(InputEvent::Paste(text.clone()));
+                self.record_insert_repeat(InputEvent::Paste(text.clone()));
+                InputAction::InsertText(text.replace("\r\n", "\n").replace('\r', "\n"))
+            }
+            _ => InputAction::NoAction,
+        }
+    }
+
+    // The mode whose mappings apply to the next key, None when it is a character argument like f's
+    pub(crate) fn map_mode(&self) -> Option<MapMode> {
+        match self.mode {
+            InputMode::Normal(input) if !input.is_pending() => Some(MapMode::Normal),
+            InputMode::Visual(input) if !input.is_pending() => Some(MapMode::Visual),
+            InputMode::Insert | InputMode::Replace if !self.insert_register_pending => {
+                Some(MapMode::Insert)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_visual(&self) -> bool {
+        matches!(self.mode, InputMode::Visual(_))
+    }
+
+    pub(crate) fn is_inserting(&self) -> bool {
+        matches!(self.mode, InputMode::Insert | InputMode::Replace)
+    }
+
+    // Completes a two key command like f{char} or i{object}
+    fn handle_pending_input(
+        &mut self,
+        ev_key: KeyEvent,
+        mut new_input: NormalInput,
+    ) -> NormalInput {
+        let (Some(pending), KeyCode::Char(c)) = (new_input.pending.take(), ev_key.code) else {
+            new_input.command = Some(Command::None);
+            return new_input;
+        };
+
+        match pending {
+            Pending::Find(kind) => {
+                self.last_find = Some((kind, c));
+                new_input.target = Some(TextTarget::Char(kind, c, false));
+            }
+            Pending::Object(scope) => match TextObject::from_key(c) {
+                Some(object) => new_input.target = Some(TextTarget::Object(object, scope)),
+                None => new_input.command = Some(Command::None),
+            },
+            Pending::Register if Registers::is_valid_name(c) => new_input.register = Some(c),
+            Pending::Record if c.is_ascii_alphabetic() => {
+                new_input.command = Some(Command::RecordMacro(c))
+            }
+            Pending::Play if Registers::is_valid_name(c) || c == '@' => {
+                new_input.command = Some(Command::PlayMacro(c))
+            }
+            Pending::Replace => new_input.command = Some(Command::ReplaceChar(c)),
+            Pending::Mark if c.is_ascii_alphabetic() => {
+                new_input.command = Some(Command::SetMark(c))
+            }
+            Pending::MarkJump(exact) if c.is_ascii_alphabetic() || matches!(c, '\'' | '`') => {
+                new_input.command = Some(Command::JumpToMark(c, exact))
+            }
+            // dgg and the like take the rows up to the first line, or the count'th
+            Pending::G if new_input.command.is_some() => match c {
+                'g' => {
+                    new_input.target = Some(TextTarget::ToLine(Some(
+                        new_input.num_modifier.unwrap_or(1),
+                    )))
+                }
+                _ => new_input.command = Some(Command::None),
+            },
+            Pending::G => match c {
+                'a' | 'x' if control_held(ev_key) => {
+                    let step = if c == 'a' { 1 } else { -1 };
+                    new_input.command = Some(Command::Increment(step, true))
+                }
+                '~' => new_input.command = Some(Command::Case(CaseChange::Toggle)),
+                'u' => new_input.command = Some(Command::Case(CaseChange::Lower)),
+                'U' => new_input.command = Some(Command::Case(CaseChange::Upper)),
+                'c' => {
+                    new_input.command = Some(Command::Comment);
+                    if self.is_visual() {
+                        new_input.target = Some(TextTarget::Selection);
+                    }
+                }
+                'g' => new_input.command = Some(Command::GotoLine(false)),
+                'j' => new_input.command = Some(Command::MoveScreenLine(VerticalDirection::Down)),
+                'k' => new_input.command = Some(Command::MoveScreenLine(VerticalDirection::Up)),
+                'i' => new_input.command = Some(Command::Insert(InsertAt::LastInsert)),
+                'J' => {
+                    new_input.command = Some(Command::Join(false));
+                    new_input.target = Some(match self.is_visual() {
+                        true => TextTarget::Selection,
+                        false => TextTarget::WholeRow,
+                    });
+                }
+                _ => new_input.command = Some(Command::None),
+            },
+            Pending::Bracket(forward) if c == 'm' => {
+                new_input.command = Some(Command::JumpFunction(forward))
+            }
+            Pending::Register
+            | Pending::Record
+            | Pending::Play
+            | Pending::Mark
+            | Pending::MarkJump(_)
+            | Pending::Bracket(_) => new_input.command = Some(Command::None),
+        }
+        new_input
+    }
+
+    // Visual mode shares motions with normal mode but operators act on the selection
+    fn handle_visual_input(&mut self, ev_key: KeyEvent, input_buffer: NormalInput) -> NormalInput {
+        if ev_key.kind != KeyEventKind::Press {
+            return input_buffer;
+        }
+        if input_buffer.is_pending() {
+            return self.handle_pending_input(ev_key, input_buffer);
+        }
+
+        let mut new_input = input_buffer;
+        match ev_key.code {
+            KeyCode::Char('o' | 'i') if alt_held(ev_key) => {
+                return self.handle_normal_input(ev_key, input_buffer);
+            }
+            KeyCode::Char('a') if control_held(ev_key) => {
+                new_input.command = Some(Command::Increment(1, false))
+            }
+            KeyCode::Char('x') if control_held(ev_key) => {
+                new_input.command = Some(Command::Increment(-1, false))
+            }
+            KeyCode::Char('n') if control_held(ev_key) => {
+                new_input.command = Some(Command::CursorsOnRows)
+            }
+            KeyCode::Esc | KeyCode::Char('v') => new_input.command = Some(Command::SwitchNormal),
+            KeyCode::Char('S') => new_input.command = Some(Command::SplitSelection),
+            KeyCode::Char(c @ ('~' | 'u' | 'U')) => {
+                new_input.command = Some(Command::Case(match c {
+                    '~' => CaseChange::Toggle,
+                    'u' => CaseChange::Lower,
+                    _ => CaseChange::Upper,
+                }));
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('d') | KeyCode::Char('x') => {
+                new_input.command = Some(Command::Delete);
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('y') => {
+                new_input.command = Some(Command::Yank);
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('c') | KeyCode::Char('s') => {
+                new_input.command = Some(Command::Change);
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('>') | KeyCode::Char('<') => {
+                new_input.command = Some(Command::Shift(ev_key.code == KeyCode::Char('>')));
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('=') => {
+                new_input.command = Some(Command::Reindent);
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char('J') => {
+                new_input.command = Some(Command::Join(true));
+                new_input.target = Some(TextTarget::Selection);
+            }
+            KeyCode::Char(':') => new_input.command = Some(Command::Prompt),
+            KeyCode::Char('"') => new_input.pending = Some(Pending::Register),
+            KeyCode::Char('i') => {
+                new_input.command = Some(Command::Select);
+                new_input.pending = Some(Pending::Object(ObjectScope::Inner));
+            }
+            KeyCode::Char('a') => {
+                new_input.command = Some(Command::Select);
+                new_input.pending = Some(Pending::Object(ObjectScope::Around));
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || "hjklfFtT;,gG'`[]".contains(c) => {
+                return self.handle_normal_input(ev_key, input_buffer);
+            }
+            KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
+                new_input.command = Some(Command::Move(Direction::from(ev_key.code)));
+            }
+            _ => {}
+        }
+        new_input
+    }
+
+    fn handle_normal_input(&mut self, ev_key: KeyEvent, input_buffer: NormalInput) -> NormalInput {
         if ev_key.kind != KeyEventKind::Press {
             return input_buffer;
         }
 
-        let mut new_input = input_buffer.clone();
+        if input_buffer.is_pending() {
+            return self.handle_pending_input(ev_key, input_buffer);
+        }
+
+        let mut new_input = input_buffer;
+
         match ev_key.code {
-            KeyCode::Char(num) if num.is_digit(10) => {
+            KeyCode::Esc => {
+                // Esc with nothing typed also drops any extra cursors
+                let idle = new_input.command.is_none();
+                new_input = NormalInput::default();
+                if idle {
+                    new_input.command = Some(Command::ClearCursors);
+                }
+                self.resume_insert = false;
+            }
+            KeyCode::Char('n') if control_held(ev_key) => {
+                new_input.command = Some(Command::AddCursor)
+            }
+            KeyCode::Char(num) if num.is_ascii_digit() => {
                 new_input.insert_num_modifier(num);
             }
+            // Ctrl-i arrives as a tab from most terminals
+            KeyCode::Char('o') if control_held(ev_key) => {
+                new_input.command = Some(Command::JumpBack)
+            }
+            KeyCode::Char('i') if control_held(ev_key) => {
+                new_input.command = Some(Command::JumpForward)
+            }
+            KeyCode::Char('r') if control_held(ev_key) => new_input.command = Some(Command::Redo),
+            KeyCode::Tab => new_input.command = Some(Command::JumpForward),
+            KeyCode::Char('a') if control_held(ev_key) => {
+                new_input.command = Some(Command::Increment(1, false))
+            }
+            KeyCode::Char('x') if control_held(ev_key) => {
+                new_input.command = Some(Command::Increment(-1, false))
+            }
+            KeyCode::Char('~') => match new_input.command {
+                Some(Command::Case(CaseChange::Toggle)) => {
+                    new_input.target = Some(TextTarget::WholeRow)
+                }
+                Some(_) => new_input.command = Some(Command::None),
+                None => {
+                    new_input.command = Some(Command::Case(CaseChange::Toggle));
+                    new_input.target = Some(TextTarget::UnderCursor);
+                }
+            },
+            // guu and gUU
+            KeyCode::Char(c @ ('u' | 'U')) if new_input.command.is_some() => {
+                let case = if c == 'u' {
+                    CaseChange::Lower
+                } else {
+                    CaseChange::Upper
+                };
+                match new_input.command {
+                    Some(Command::Case(current)) if current == case => {
+                        new_input.target = Some(TextTarget::WholeRow)
+                    }
+                    _ => new_input.command = Some(Command::None),
+                }
+            }
+            KeyCode::Char('u') => new_input.command = Some(Command::Undo),
+            KeyCode::Char('m') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::Mark)
+            }
+            KeyCode::Char('\'') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::MarkJump(false))
+            }
+            KeyCode::Char('`') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::MarkJump(true))
+            }
+            KeyCode::Char('g') => new_input.pending = Some(Pending::G),
+            KeyCode::Char('G') => match new_input.command {
+                Some(_) => new_input.target = Some(TextTarget::ToLine(new_input.num_modifier)),
+                None => new_input.command = Some(Command::GotoLine(true)),
+            },
+            KeyCode::Char(c @ (']' | '[')) if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::Bracket(c == ']'))
+            }
+            KeyCode::Char('o') if alt_held(ev_key) => {
+                new_input.command = Some(Command::ExpandSelection)
+            }
+            KeyCode::Char('i') if alt_held(ev_key) => {
+                new_input.command = Some(Command::ShrinkSelection)
+            }
             KeyCode::Char('o') => {
                 new_input.command = Some(Command::NewLineAndInsert(VerticalDirection::Down));
             }
@@ -188,6 +995,64 @@ impl ModalInputter {
                 },
                 None => new_input.command = Some(Command::Delete),
             },
+            KeyCode::Char('y') => match new_input.command {
+                Some(Command::Yank) => new_input.target = Some(TextTarget::WholeRow),
+                Some(_) => new_input.command = Some(Command::None),
+                None => new_input.command = Some(Command::Yank),
+            },
+            KeyCode::Char('c') => match new_input.command {
+                Some(Command::Change | Command::Comment) => {
+                    new_input.target = Some(TextTarget::WholeRow)
+                }
+                Some(_) => new_input.command = Some(Command::None),
+                None => new_input.command = Some(Command::Change),
+            },
+            KeyCode::Char(c @ ('>' | '<')) => match new_input.command {
+                Some(Command::Shift(right)) if right == (c == '>') => {
+                    new_input.target = Some(TextTarget::WholeRow)
+                }
+                Some(_) => new_input.command = Some(Command::None),
+                None => new_input.command = Some(Command::Shift(c == '>')),
+            },
+            KeyCode::Char('=') => match new_input.command {
+                Some(Command::Reindent) => new_input.target = Some(TextTarget::WholeRow),
+                Some(_) => new_input.command = Some(Command::None),
+                None => new_input.command = Some(Command::Reindent),
+            },
+            KeyCode::Char('J') if new_input.command.is_none() => {
+                new_input.command = Some(Command::Join(true));
+                new_input.target = Some(TextTarget::WholeRow);
+            }
+            KeyCode::Char('C') => {
+                new_input.command = Some(Command::Change);
+                new_input.target = Some(TextTarget::RowAfterCursor);
+            }
+            KeyCode::Char('s') => {
+                new_input.command = Some(Command::Change);
+                new_input.target = Some(TextTarget::UnderCursor);
+            }
+            KeyCode::Char('S') => {
+                new_input.command = Some(Command::Change);
+                new_input.target = Some(TextTarget::WholeRow);
+            }
+            KeyCode::Char('r') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::Replace)
+            }
+            KeyCode::Char('R') => new_input.command = Some(Command::SwitchReplace),
+            KeyCode::Char('Y') => {
+                new_input.command = Some(Command::Yank);
+                new_input.target = Some(TextTarget::WholeRow);
+            }
+            KeyCode::Char('"') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::Register)
+            }
+            KeyCode::Char('q') if new_input.command.is_none() => match self.recording {
+                Some(_) => new_input.command = Some(Command::StopRecording),
+                None => new_input.pending = Some(Pending::Record),
+            },
+            KeyCode::Char('@') if new_input.command.is_none() => {
+                new_input.pending = Some(Pending::Play)
+            }
             KeyCode::Char('x') => {
                 new_input.command = Some(Command::Delete);
                 new_input.target = Some(TextTarget::UnderCursor);
@@ -198,30 +1063,63 @@ impl ModalInputter {
                 new_input.command = Some(Command::Delete);
                 new_input.target = Some(TextTarget::RowAfterCursor);
             }
-            KeyCode::Char('i') => {
-                new_input.command = Some(Command::SwitchInsert);
+            KeyCode::Char('i') => match new_input.command {
+                Some(_) => new_input.pending = Some(Pending::Object(ObjectScope::Inner)),
+                None => new_input.command = Some(Command::Insert(InsertAt::Cursor)),
+            },
+            KeyCode::Char('a') => match new_input.command {
+                Some(_) => new_input.pending = Some(Pending::Object(ObjectScope::Around)),
+                None => new_input.command = Some(Command::Insert(InsertAt::AfterCursor)),
+            },
+            KeyCode::Char('I') => {
+                new_input.command = Some(Command::Insert(InsertAt::FirstNonBlank))
+            }
+            KeyCode::Char('A') => new_input.command = Some(Command::Insert(InsertAt::RowEnd)),
+            KeyCode::Home => new_input.command = Some(Command::RowStart),
+            KeyCode::End => new_input.command = Some(Command::RowEnd),
+            KeyCode::PageUp => new_input.command = Some(Command::Page(VerticalDirection::Up)),
+            KeyCode::PageDown => new_input.command = Some(Command::Page(VerticalDirection::Down)),
+            KeyCode::Char('v') => new_input.command = Some(Command::SwitchVisual),
+            // after an operator j and k take whole rows, h and l the characters either side
+            KeyCode::Char(c @ ('h' | 'l' | 'k' | 'j')) if new_input.command.is_some() => {
+                new_input.target = Some(match c {
+                    'j' => TextTarget::Rows(VerticalDirection::Down),
+                    'k' => TextTarget::Rows(VerticalDirection::Up),
+                    'h' => TextTarget::BeforeCursor,
+                    _ => TextTarget::UnderCursor,
+                });
             }
             KeyCode::Char('h') | KeyCode::Char('l') | KeyCode::Char('k') | KeyCode::Char('j') => {
                 new_input.command = Some(Command::Move(Direction::from(ev_key.code)));
             }
             KeyCode::Char(':') => {
-                new_input.command = Some(Command::CommandInput);
+                new_input.command = Some(Command::Prompt);
             }
-            KeyCode::Char('Q') => match new_input.command {
-                Some(_) => {
-                    new_input.target = Some(TextTarget::Nothing);
-                }
-                None => {
-                    new_input.command = Some(Command::None);
+            KeyCode::Char('.') => new_input.command = Some(Command::RepeatChange),
+            KeyCode::Char('f') => new_input.start_find(FindKind::Forward),
+            KeyCode::Char('F') => new_input.start_find(FindKind::Backward),
+            KeyCode::Char('t') => new_input.start_find(FindKind::TillForward),
+            KeyCode::Char('T') => new_input.start_find(FindKind::TillBackward),
+            KeyCode::Char(';') | KeyCode::Char(',') => match self.last_find {
+                Some((kind, c)) => {
+                    let kind = match ev_key.code {
+                        KeyCode::Char(',') => kind.reversed(),
+                        _ => kind,
+                    };
+                    new_input.command.get_or_insert(Command::Jump);
+                    new_input.target = Some(TextTarget::Char(kind, c, true));
                 }
+                None => new_input.command = Some(Command::None),
+            },
+            // ZQ and ZZ, after any other command they are not a target
+            KeyCode::Char('Q') => match new_input.command {
+                Some(Command::Quit) => new_input.target = Some(TextTarget::Nothing),
+                _ => new_input.command = Some(Command::None),
             },
             KeyCode::Char('Z') => match new_input.command {
-                Some(_) => {
-                    new_input.target = Some(TextTarget::All);
-                }
-                None => {
-                    new_input.command = Some(Command::Quit);
-                }
+                Some(Command::Quit) => new_input.target = Some(TextTarget::All),
+                Some(_) => new_input.command = Some(Command::None),
+                None => new_input.command = Some(Command::Quit),
             },
             _ => {}
         }
@@ -229,15 +1127,50 @@ impl ModalInputter {
         new_input
     }
 
-    fn handle_insert_input(&self, ev_key: KeyEvent) -> InputAction {
+    fn handle_insert_input(&mut self, ev_key: KeyEvent) -> InputAction {
         if ev_key.kind != KeyEventKind::Press {
             return InputAction::NoAction;
         }
+        if self.insert_register_pending {
+            self.insert_register_pending = false;
+            return match ev_key.code {
+                KeyCode::Char(c) if Registers::is_valid_name(c) => InputAction::InsertRegister(c),
+                _ => InputAction::NoAction,
+            };
+        }
 
         match ev_key.code {
             KeyCode::Char('s') if control_held(ev_key) => InputAction::Save,
+            KeyCode::Char('w') if control_held(ev_key) => InputAction::DeleteWordBehind,
+            KeyCode::Char('u') if control_held(ev_key) => InputAction::DeleteToRowStart,
+            KeyCode::Char('r') if control_held(ev_key) => {
+                self.insert_register_pending = true;
+                InputAction::NoAction
+            }
+            KeyCode::Char('o') if control_held(ev_key) => {
+                // the count no longer applies once a normal command runs
+                self.insert_repeat = None;
+                self.resume_insert = true;
+                InputAction::SwitchMode(InputMode::Normal(NormalInput::default()))
+            }
+            KeyCode::Char('t') if control_held(ev_key) => InputAction::ShiftRows {
+                right: true,
+                count: 1,
+            },
+            KeyCode::Char('d') if control_held(ev_key) => InputAction::ShiftRows {
+                right: false,
+                count: 1,
+            },
             KeyCode::Char(c) => InputAction::InsertChar(c),
-            KeyCode::Esc => InputAction::SwitchMode(InputMode::Normal(NormalInput::default())),
+            KeyCode::Tab => InputAction::InsertTab,
+            KeyCode::Home => InputAction::MoveToRowStart,
+            KeyCode::End => InputAction::MoveToRowEnd,
+            KeyCode::PageUp => InputAction::Page(VerticalDirection::Up),
+            KeyCode::PageDown => InputAction::Page(VerticalDirection::Down),
+            KeyCode::Esc => match self.insert_repeat.take() {
+                Some(repeat) => InputAction::FinishInsert(repeat.repeated()),
+                None => InputAction::FinishInsert(vec![]),
+            },
             KeyCode::Up | KeyCode::Left | KeyCode::Right | KeyCode::Down => {
                 InputAction::MoveCursor {
                     direction: ev_key.code.into(),
@@ -251,7 +1184,24 @@ impl ModalInputter {
         }
     }
 
+    // Like insert mode but typed characters take the place of the ones under the cursor
+    fn handle_replace_input(&mut self, ev_key: KeyEvent) -> InputAction {
+        if ev_key.kind != KeyEventKind::Press {
+            return InputAction::NoAction;
+        }
+
+        match ev_key.code {
+            KeyCode::Char('s') if control_held(ev_key) => InputAction::Save,
+            KeyCode::Char(c) => InputAction::OverwriteChar(c),
+            KeyCode::Backspace => InputAction::RestoreBehind,
+            _ => self.handle_insert_input(ev_key),
+        }
+    }
+
     pub(crate) fn switch(&mut self, new_mode: InputMode) {
+        if !matches!(new_mode, InputMode::Insert | InputMode::Replace) {
+            self.recording_insert = false;
+        }
         match new_mode {
             InputMode::Normal(_) => {
                 let _ = execute!(io::stdout(), SetCursorStyle::BlinkingBlock);
@@ -259,6 +1209,12 @@ impl ModalInputter {
             InputMode::Insert => {
                 let _ = execute!(io::stdout(), SetCursorStyle::BlinkingBar);
             }
+            InputMode::Visual(_) => {
+                let _ = execute!(io::stdout(), SetCursorStyle::SteadyBlock);
+            }
+            InputMode::Replace => {
+                let _ = execute!(io::stdout(), SetCursorStyle::BlinkingUnderScore);
+            }
             InputMode::Command => {}
         }
         self.mode = new_mode;
@@ -269,29 +1225,190 @@ impl ModalInputter {
             "w" => InputAction::Save,
             "q" => InputAction::Quit,
             "wq" => InputAction::SaveAndQuit,
+            "reg" | "registers" | "di" | "display" => InputAction::ShowRegisters,
+            "marks" => InputAction::ShowMarks,
+            _ => evaluate_ranged_cmd(cmd_input),
+        }
+    }
+}
+
+// Commands working on lines, which take a range in front of them
+fn evaluate_ranged_cmd(cmd_input: &str) -> InputAction {
+    let (range, rest) = parse_range(cmd_input.trim());
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (name, args) = rest.split_at(name_len);
+    let args = args.trim();
+    // commands can be shortened down to their first min letters
+    let is = |full: &str, min: usize| name.len() >= min && full.starts_with(name);
+
+    match name {
+        "" => match (range, args.is_empty()) {
+            (Some(range), true) => InputAction::GotoRange(range),
             _ => InputAction::InvalidCommand,
+        },
+        _ if is("move", 1) || is("copy", 2) || name == "t" => match parse_address(args) {
+            Some((to, "")) if name.starts_with('m') => InputAction::MoveRows { range, to },
+            Some((to, "")) => InputAction::CopyRows { range, to },
+            _ => InputAction::InvalidCommand,
+        },
+        _ if is("sort", 3) => match parse_sort_options(args) {
+            Some(options) => InputAction::SortRows { range, options },
+            None => InputAction::InvalidCommand,
+        },
+        _ if is("uniq", 3) && args.is_empty() => InputAction::UniqRows { range },
+        _ if is("colorscheme", 4) && range.is_none() => InputAction::ColorScheme(args.to_string()),
+        _ if is("set", 2) && range.is_none() => InputAction::SetOptions(args.to_string()),
+        _ if is("verbose", 4) && range.is_none() => match evaluate_ranged_cmd(args) {
+            InputAction::Map {
+                modes,
+                recursive,
+                args,
+                ..
+            } => InputAction::Map {
+                modes,
+                recursive,
+                verbose: true,
+                args,
+            },
+            _ => InputAction::InvalidCommand,
+        },
+        _ if range.is_none() && map_command(name).is_some() => {
+            // unwrap - checked just above
+            let (modes, kind) = map_command(name).unwrap();
+            match kind {
+                "unmap" => InputAction::Unmap {
+                    modes,
+                    keys: args.to_string(),
+                },
+                _ => InputAction::Map {
+                    modes,
+                    recursive: kind == "map",
+                    verbose: false,
+                    args: args.to_string(),
+                },
+            }
+        }
+        _ if is("source", 2) && range.is_none() => {
+            InputAction::Source((!args.is_empty()).then(|| args.to_string()))
+        }
+        _ => InputAction::InvalidCommand,
+    }
+}
+
+// The modes of a map, noremap or unmap command, and which of those it is
+// Without a mode letter in front it is for normal and visual mode
+fn map_command(name: &str) -> Option<(Vec<MapMode>, &str)> {
+    let is_kind = |kind: &str| matches!(kind, "map" | "noremap" | "unmap");
+    if is_kind(name) {
+        return Some((vec![MapMode::Normal, MapMode::Visual], name));
+    }
+    let (letter, kind) = name.split_at(1);
+    let mode = match letter {
+        "n" => MapMode::Normal,
+        "v" | "x" => MapMode::Visual,
+        "i" => MapMode::Insert,
+        _ => return None,
+    };
+    is_kind(kind).then(|| (vec![mode], kind))
+}
+
+// Actions a mapping can name with <Action>name instead of giving keys
+pub fn named_action(name: &str) -> Option<InputAction> {
+    let action = match name {
+        "save" => InputAction::Save,
+        "quit" => InputAction::Quit,
+        "save_and_quit" => InputAction::SaveAndQuit,
+        "command_prompt" => InputAction::CommandPrompt,
+        "page_up" => InputAction::Page(VerticalDirection::Up),
+        "page_down" => InputAction::Page(VerticalDirection::Down),
+        "row_start" => InputAction::MoveToRowStart,
+        "row_end" => InputAction::MoveToRowEnd,
+        "last_line" => InputAction::GotoLine(None),
+        "show_registers" => InputAction::ShowRegisters,
+        "show_marks" => InputAction::ShowMarks,
+        "add_cursor" => InputAction::AddCursorAtNext,
+        "clear_cursors" => InputAction::ClearCursors,
+        "expand_selection" => InputAction::ExpandSelection,
+        "shrink_selection" => InputAction::ShrinkSelection,
+        "jump_back" => InputAction::JumpBack { count: 1 },
+        "jump_forward" => InputAction::JumpForward { count: 1 },
+        "undo" => InputAction::Undo { count: 1 },
+        "redo" => InputAction::Redo { count: 1 },
+        "next_function" => InputAction::JumpFunction {
+            forward: true,
+            count: 1,
+        },
+        "previous_function" => InputAction::JumpFunction {
+            forward: false,
+            count: 1,
+        },
+        _ => {
+            let direction = match name {
+                "left" => Direction::Left,
+                "right" => Direction::Right,
+                "up" => Direction::Up,
+                "down" => Direction::Down,
+                _ => return None,
+            };
+            InputAction::MoveCursor {
+                direction,
+                count: 1,
+            }
+        }
+    };
+    Some(action)
+}
+
+// Reads `[!] [n][u][i][r] [/pattern/]`, ! and r both reverse the order
+fn parse_sort_options(args: &str) -> Option<SortOptions> {
+    let mut options = SortOptions::default();
+    let mut rest = args;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '!' | 'r' => options.reverse = true,
+            'n' => options.numeric = true,
+            'u' => options.unique = true,
+            'i' => options.ignore_case = true,
+            ' ' => {}
+            '/' => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                options.pattern = Some(rest[..end].to_string());
+                rest = rest.get(end + 1..).unwrap_or_default();
+            }
+            _ => return None,
         }
     }
+    Some(options)
 }
 
 // Attempts to find a valid input action based on the buffered inputs
 // If the input buffer is not valid, then InputAction::NoAction will be returned
 // If the input buffer is not ready, i.e. waiting for a target, then None will be returned
 fn evaluate_normal_input(input: NormalInput) -> Option<InputAction> {
-    let command = match input.command {
-        Some(c) => c,
-        None => return None,
-    };
-    let count = match input.num_modifier {
-        Some(num) => num,
-        None => 1, // we always want to do the action atleast once
-    };
+    let command = input.command?;
+    // we always want to do the action atleast once
+    let count = input.num_modifier.unwrap_or(1);
 
     let action = match command {
-        Command::Paste(direction) => InputAction::PasteYanked(direction),
-        Command::CommandInput => InputAction::CommandPrompt,
-        Command::SwitchInsert => InputAction::SwitchMode(InputMode::Insert),
+        Command::Paste(direction) => InputAction::Paste {
+            direction,
+            count,
+            register: input.register,
+        },
+        Command::Prompt => InputAction::CommandPrompt,
+        Command::AddCursor => InputAction::AddCursorAtNext,
+        Command::CursorsOnRows => InputAction::CursorsOnRows,
+        Command::SplitSelection => InputAction::SplitSelection,
+        Command::ClearCursors => InputAction::ClearCursors,
+        Command::Insert(at) => InputAction::Insert(at),
+        Command::RowStart => InputAction::MoveToRowStart,
+        Command::RowEnd => InputAction::MoveToRowEnd,
+        Command::Page(direction) => InputAction::Page(direction),
         Command::Move(direction) => InputAction::MoveCursor { direction, count },
+        Command::MoveScreenLine(direction) => InputAction::MoveScreenLine { direction, count },
         Command::NewLineAndInsert(v_direction) => InputAction::NewLineAndInsert(v_direction),
         Command::Quit => match input.target {
             Some(target) => match target {
@@ -302,11 +1419,322 @@ fn evaluate_normal_input(input: NormalInput) -> Option<InputAction> {
             None => return None,
         },
         Command::Delete => match input.target {
-            Some(target) => InputAction::Delete(target),
+            Some(target) => InputAction::Delete {
+                target,
+                count,
+                register: input.register,
+            },
+            None => return None,
+        },
+        Command::Yank => match input.target {
+            Some(target) => InputAction::Yank {
+                target,
+                count,
+                register: input.register,
+            },
             None => return None,
         },
+        Command::Change => match input.target {
+            Some(target) => InputAction::Change {
+                target,
+                count,
+                register: input.register,
+            },
+            None => return None,
+        },
+        Command::Shift(right) => match input.target {
+            Some(target) => InputAction::Shift {
+                right,
+                target,
+                count,
+            },
+            None => return None,
+        },
+        Command::Join(spaces) => match input.target {
+            Some(target) => InputAction::Join {
+                target,
+                count,
+                spaces,
+            },
+            None => return None,
+        },
+        Command::Comment => match input.target {
+            Some(target) => InputAction::Comment { target, count },
+            None => return None,
+        },
+        Command::Case(case) => match input.target {
+            Some(target) => InputAction::ChangeCase {
+                case,
+                target,
+                count,
+            },
+            None => return None,
+        },
+        Command::Increment(step, progressive) => InputAction::Increment {
+            amount: step * count as i64,
+            progressive,
+        },
+        Command::Reindent => match input.target {
+            Some(target) => InputAction::Reindent { target, count },
+            None => return None,
+        },
+        Command::ReplaceChar(c) => InputAction::ReplaceChar { c, count },
+        Command::SwitchReplace => InputAction::SwitchMode(InputMode::Replace),
+        Command::Jump => match input.target {
+            Some(target) => InputAction::Jump { target, count },
+            None => return None,
+        },
+        Command::Select => match input.target {
+            Some(target) => InputAction::Select { target, count },
+            None => return None,
+        },
+        Command::RepeatChange => InputAction::RepeatChange {
+            count: input.num_modifier,
+        },
+        Command::RecordMacro(register) => InputAction::RecordMacro(register),
+        Command::StopRecording => InputAction::StopRecording,
+        Command::PlayMacro(register) => InputAction::PlayMacro { register, count },
+        Command::SetMark(mark) => InputAction::SetMark(mark),
+        Command::JumpToMark(mark, exact) => InputAction::JumpToMark { mark, exact },
+        Command::GotoLine(last) => match last {
+            true => InputAction::GotoLine(input.num_modifier),
+            false => InputAction::GotoLine(Some(count)),
+        },
+        Command::JumpBack => InputAction::JumpBack { count },
+        Command::JumpFunction(forward) => InputAction::JumpFunction { forward, count },
+        Command::ExpandSelection => InputAction::ExpandSelection,
+        Command::ShrinkSelection => InputAction::ShrinkSelection,
+        Command::JumpForward => InputAction::JumpForward { count },
+        Command::Undo => InputAction::Undo { count },
+        Command::Redo => InputAction::Redo { count },
+        Command::SwitchVisual => InputAction::SwitchMode(InputMode::Visual(NormalInput::default())),
+        Command::SwitchNormal => InputAction::SwitchMode(InputMode::Normal(NormalInput::default())),
         Command::None => InputAction::NoAction,
     };
 
     Some(action)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The action each key gives, typed one after another
+    fn press(inputter: &mut ModalInputter, keys: &str) -> Vec<InputAction> {
+        keys.chars()
+            .map(|c| {
+                inputter.process_key_press(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sort_options() {
+        let options = parse_sort_options("! n u").unwrap();
+        assert!(options.reverse && options.numeric && options.unique && !options.ignore_case);
+        assert!(options.pattern.is_none());
+
+        let options = parse_sort_options("ri /\\d+/").unwrap();
+        assert!(options.reverse && options.ignore_case);
+        assert_eq!(options.pattern.as_deref(), Some("\\d+"));
+
+        // an unclosed pattern runs to the end
+        let options = parse_sort_options("/[a-z]").unwrap();
+        assert_eq!(options.pattern.as_deref(), Some("[a-z]"));
+
+        assert!(parse_sort_options("").is_some());
+        assert!(parse_sort_options("x").is_none());
+    }
+
+    #[test]
+    fn quit_keys_only_after_z() {
+        let mut inputter = ModalInputter::default();
+        for keys in ["cZ", "dQ", "dZ", "yZ", "cQ"] {
+            let actions = press(&mut inputter, keys);
+            assert!(actions
+                .iter()
+                .all(|action| matches!(action, InputAction::NoAction)));
+        }
+        assert!(matches!(press(&mut inputter, "ZQ")[1], InputAction::Quit));
+        assert!(matches!(
+            press(&mut inputter, "ZZ")[1],
+            InputAction::SaveAndQuit
+        ));
+    }
+
+    #[test]
+    fn undo_keys() {
+        let mut inputter = ModalInputter::default();
+        assert!(matches!(
+            press(&mut inputter, "3u")[1],
+            InputAction::Undo { count: 3 }
+        ));
+        let redo =
+            inputter.process_key_press(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL));
+        assert!(matches!(redo, InputAction::Redo { count: 1 }));
+        // gu is the lowercase operator
+        assert!(press(&mut inputter, "gu")
+            .iter()
+            .all(|action| matches!(action, InputAction::NoAction)));
+    }
+
+    #[test]
+    fn paste_in_insert_mode() {
+        let mut inputter = ModalInputter::default();
+        // outside insert mode a paste does nothing
+        let action = inputter.process_paste("x".to_string());
+        assert!(matches!(action, InputAction::NoAction));
+
+        // the editor switches modes on the actions it is given
+        press(&mut inputter, "i");
+        inputter.switch(InputMode::Insert);
+        let action = inputter.process_paste("a\r\n\tb\rc".to_string());
+        assert!(matches!(action, InputAction::InsertText(text) if text == "a\n\tb\nc"));
+
+        // the paste is part of the change . repeats
+        inputter.process_key_press(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        inputter.switch(InputMode::Normal(NormalInput::default()));
+        let repeat = inputter.change_to_repeat(None);
+        assert!(repeat
+            .iter()
+            .any(|event| matches!(event, InputEvent::Paste(text) if text == "a\r\n\tb\rc")));
+    }
+
+    // The typed characters of replayed input, with Esc and Enter as <Esc> and <CR>
+    fn typed(events: &[InputEvent]) -> String {
+        events
+            .iter()
+            .map(|event| match event {
+                InputEvent::Key(ev_key) => match ev_key.code {
+                    KeyCode::Char(c) => c.to_string(),
+                    KeyCode::Esc => "<Esc>".to_string(),
+                    KeyCode::Enter => "<CR>".to_string(),
+                    _ => "?".to_string(),
+                },
+                InputEvent::Paste(text) => text.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeat_last_change() {
+        let mut inputter = ModalInputter::default();
+        assert!(inputter.change_to_repeat(None).is_empty());
+
+        press(&mut inputter, "3dd");
+        assert_eq!(typed(&inputter.change_to_repeat(None)), "3dd");
+        // a new count takes the place of the old one
+        assert_eq!(typed(&inputter.change_to_repeat(Some(5))), "5dd");
+
+        // motions and yanks are not changes
+        press(&mut inputter, "2jyy");
+        assert_eq!(typed(&inputter.change_to_repeat(None)), "3dd");
+
+        // what was typed after the command is kept with it
+        press(&mut inputter, "o");
+        inputter.switch(InputMode::Insert);
+        press(&mut inputter, "hi");
+        inputter.process_key_press(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        inputter.switch(InputMode::Normal(NormalInput::default()));
+        assert_eq!(typed(&inputter.change_to_repeat(None)), "ohi<Esc>");
+        press(&mut inputter, "x");
+        assert_eq!(typed(&inputter.change_to_repeat(Some(2))), "2x");
+
+        assert!(matches!(
+            press(&mut inputter, "4.")[1],
+            InputAction::RepeatChange { count: Some(4) }
+        ));
+    }
+
+    fn control(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn insert_entry_keys() {
+        let mut inputter = ModalInputter::default();
+        let entered = |inputter: &mut ModalInputter, keys| match press(inputter, keys).pop() {
+            Some(InputAction::Insert(at)) => Some(at),
+            _ => None,
+        };
+        assert!(matches!(
+            entered(&mut inputter, "a"),
+            Some(InsertAt::AfterCursor)
+        ));
+        assert!(matches!(
+            entered(&mut inputter, "A"),
+            Some(InsertAt::RowEnd)
+        ));
+        assert!(matches!(
+            entered(&mut inputter, "I"),
+            Some(InsertAt::FirstNonBlank)
+        ));
+        assert!(matches!(
+            entered(&mut inputter, "gi"),
+            Some(InsertAt::LastInsert)
+        ));
+    }
+
+    #[test]
+    fn counted_inserts() {
+        let mut inputter = ModalInputter::default();
+        let mut insert = |keys: &str, typed_keys: &str| {
+            press(&mut inputter, keys);
+            inputter.switch(InputMode::Insert);
+            press(&mut inputter, typed_keys);
+            let action =
+                inputter.process_key_press(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+            inputter.switch(InputMode::Normal(NormalInput::default()));
+            match action {
+                InputAction::FinishInsert(events) => typed(&events),
+                _ => "not finished".to_string(),
+            }
+        };
+        // what was typed goes in count - 1 more times, o's on new rows
+        assert_eq!(insert("3i", "ab"), "abab");
+        assert_eq!(insert("2o", "x"), "<CR>x");
+        assert_eq!(insert("i", "x"), "");
+    }
+
+    #[test]
+    fn insert_mode_keys() {
+        let mut inputter = ModalInputter::default();
+        inputter.switch(InputMode::Insert);
+        // Ctrl-r takes the next key as the register to put in
+        assert!(matches!(
+            inputter.process_key_press(control('r')),
+            InputAction::NoAction
+        ));
+        assert!(matches!(
+            press(&mut inputter, "a")[0],
+            InputAction::InsertRegister('a')
+        ));
+        assert!(matches!(
+            press(&mut inputter, "a")[0],
+            InputAction::InsertChar('a')
+        ));
+        assert!(matches!(
+            inputter.process_key_press(control('w')),
+            InputAction::DeleteWordBehind
+        ));
+        assert!(matches!(
+            inputter.process_key_press(control('t')),
+            InputAction::ShiftRows {
+                right: true,
+                count: 1
+            }
+        ));
+
+        // Ctrl-o runs one normal command and then goes back to inserting
+        let action = inputter.process_key_press(control('o'));
+        assert!(matches!(
+            action,
+            InputAction::SwitchMode(InputMode::Normal(_))
+        ));
+        inputter.switch(InputMode::Normal(NormalInput::default()));
+        assert!(matches!(
+            press(&mut inputter, "x")[0],
+            InputAction::ThenInsert(_)
+        ));
+    }
+}
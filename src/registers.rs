@@ -0,0 +1,247 @@
+use std::{collections::BTreeMap, io};
+
+use crate::clipboard::{Clipboard, ClipboardProvider};
+
+#[derive(Clone, Default)]
+pub struct Register {
+    pub text: String,
+    // whole rows were taken, so pasting puts them on their own rows
+    pub linewise: bool,
+    // what each cursor took, top to bottom, when several filled it at once
+    pub pieces: Vec<String>,
+}
+
+impl Register {
+    pub fn new(text: String, linewise: bool) -> Self {
+        Self {
+            text,
+            linewise,
+            pieces: vec![],
+        }
+    }
+
+    // The texts taken at each cursor as one register, joined by line breaks
+    pub fn from_pieces(pieces: Vec<String>, linewise: bool) -> Self {
+        Self {
+            text: pieces.join("\n"),
+            linewise,
+            pieces,
+        }
+    }
+}
+
+// What put the text into a register, deletes shuffle the numbered history and yanks go to "0
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+    Yank,
+    Delete,
+}
+
+// Holds the unnamed register, named registers "a to "z and the numbered registers "0 to "9
+// "+ and "* go to the system clipboard and primary selection
+#[derive(Default)]
+pub struct Registers {
+    unnamed: Register,
+    named: BTreeMap<char, Register>,
+    numbered: [Register; 10],
+    clipboard: Clipboard,
+    // what was last sent to "+ and "*, read back when the clipboard cannot be
+    selections: BTreeMap<char, Register>,
+}
+
+impl Registers {
+    pub fn set_clipboard(&mut self, provider: ClipboardProvider) {
+        self.clipboard = Clipboard::new(provider);
+    }
+
+    // Stores the text in the register with the given name, or the default registers without one
+    // Uppercase names append to their lowercase register and "_ throws the text away
+    pub fn store(
+        &mut self,
+        name: Option<char>,
+        register: Register,
+        kind: StoreKind,
+    ) -> io::Result<()> {
+        match name {
+            Some('_') => return Ok(()),
+            Some(c @ ('+' | '*')) => {
+                let mut text = register.text.clone();
+                if register.linewise {
+                    text.push('\n');
+                }
+                self.selections.insert(c, register.clone());
+                self.unnamed = register;
+                return self.clipboard.copy(&text, c == '*');
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.set(c, register);
+                self.unnamed = self.named[&c.to_ascii_lowercase()].clone();
+                return Ok(());
+            }
+            _ => match kind {
+                StoreKind::Yank => self.numbered[0] = register.clone(),
+                StoreKind::Delete => {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = register.clone();
+                }
+            },
+        }
+        self.unnamed = register;
+        Ok(())
+    }
+
+    // Writes straight into a named register without touching the unnamed or numbered ones
+    // Uppercase names append to their lowercase register
+    pub fn set(&mut self, name: char, register: Register) {
+        let lower = name.to_ascii_lowercase();
+        let register = match self.named.remove(&lower) {
+            Some(mut existing) if name.is_ascii_uppercase() => {
+                if existing.linewise || register.linewise {
+                    existing.text.push('\n');
+                }
+                existing.text += &register.text;
+                existing.linewise |= register.linewise;
+                existing.pieces.clear();
+                existing
+            }
+            _ => register,
+        };
+        self.named.insert(lower, register);
+    }
+
+    pub fn get(&self, name: Option<char>) -> io::Result<Option<Register>> {
+        let register = match name {
+            None | Some('"') => Some(&self.unnamed),
+            Some(c @ '0'..='9') => self.numbered.get(c.to_digit(10).unwrap_or(0) as usize),
+            Some(c) if c.is_ascii_alphabetic() => self.named.get(&c.to_ascii_lowercase()),
+            Some(c @ ('+' | '*')) => {
+                if let Some(text) = self.clipboard.paste()? {
+                    // a trailing line break means whole rows were copied
+                    let register = match text.strip_suffix('\n') {
+                        Some(text) => Register::new(text.to_string(), true),
+                        None => Register::new(text, false),
+                    };
+                    return Ok(Some(register));
+                }
+                self.selections.get(&c)
+            }
+            _ => None,
+        };
+        Ok(register.cloned())
+    }
+
+    // Every register holding text paired with its name, in the order :registers shows them
+    pub fn list(&self) -> Vec<(char, &Register)> {
+        let mut registers = vec![('"', &self.unnamed)];
+        for (idx, register) in self.numbered.iter().enumerate() {
+            registers.push((char::from(b'0' + idx as u8), register));
+        }
+        for (name, register) in self.named.iter().chain(&self.selections) {
+            registers.push((*name, register));
+        }
+        registers.retain(|(_, register)| !register.text.is_empty());
+        registers
+    }
+
+    pub fn is_valid_name(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '"' | '_' | '+' | '*')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(registers: &Registers, name: char) -> String {
+        match registers.get(Some(name)).unwrap() {
+            Some(register) => register.text,
+            None => String::new(),
+        }
+    }
+
+    fn store(registers: &mut Registers, name: Option<char>, text: &str, kind: StoreKind) {
+        let register = Register::new(text.to_string(), false);
+        registers.store(name, register, kind).unwrap();
+    }
+
+    #[test]
+    fn yanks_and_deletes() {
+        let mut registers = Registers::default();
+        store(&mut registers, None, "yanked", StoreKind::Yank);
+        assert_eq!(
+            (text(&registers, '"'), text(&registers, '0')),
+            ("yanked".into(), "yanked".into())
+        );
+
+        // deletes shift "1 down to "9, dropping the oldest, and leave "0 alone
+        for idx in 1..=10 {
+            store(&mut registers, None, &idx.to_string(), StoreKind::Delete);
+        }
+        assert_eq!(text(&registers, '"'), "10");
+        assert_eq!(text(&registers, '1'), "10");
+        assert_eq!(text(&registers, '2'), "9");
+        assert_eq!(text(&registers, '9'), "2");
+        assert_eq!(text(&registers, '0'), "yanked");
+
+        // a named register leaves the numbered ones alone but fills the unnamed one
+        store(&mut registers, Some('q'), "named", StoreKind::Delete);
+        assert_eq!(text(&registers, '"'), "named");
+        assert_eq!(text(&registers, '1'), "10");
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut registers = Registers::default();
+        store(&mut registers, None, "kept", StoreKind::Delete);
+        store(&mut registers, Some('_'), "gone", StoreKind::Delete);
+        assert_eq!(text(&registers, '"'), "kept");
+        assert_eq!(text(&registers, '1'), "kept");
+        assert_eq!(text(&registers, '2'), "");
+        assert!(registers.get(Some('_')).unwrap().is_none());
+    }
+
+    #[test]
+    fn uppercase_appends() {
+        let mut registers = Registers::default();
+        store(&mut registers, Some('A'), "new", StoreKind::Yank);
+        assert_eq!(text(&registers, 'a'), "new");
+        store(&mut registers, Some('A'), " more", StoreKind::Yank);
+        assert_eq!(text(&registers, 'a'), "new more");
+        assert_eq!(text(&registers, '"'), "new more");
+
+        // a linewise register puts what is added on its own row
+        let rows = Register::new("row".to_string(), true);
+        registers.store(Some('A'), rows, StoreKind::Yank).unwrap();
+        let register = registers.get(Some('a')).unwrap().unwrap();
+        assert_eq!(register.text, "new more\nrow");
+        assert!(register.linewise);
+
+        // lowercase writes over it
+        store(&mut registers, Some('a'), "over", StoreKind::Yank);
+        assert_eq!(text(&registers, 'A'), "over");
+    }
+
+    #[test]
+    fn pieces_from_each_cursor() {
+        let mut registers = Registers::default();
+        let pieces = vec!["one".to_string(), "two".to_string()];
+        registers
+            .store(
+                Some('a'),
+                Register::from_pieces(pieces, false),
+                StoreKind::Yank,
+            )
+            .unwrap();
+        let register = registers.get(None).unwrap().unwrap();
+        assert_eq!(
+            (register.text.as_str(), register.pieces.len()),
+            ("one\ntwo", 2)
+        );
+
+        // appending makes it one text again
+        registers.set('A', Register::new("three".to_string(), false));
+        let register = registers.get(Some('a')).unwrap().unwrap();
+        assert_eq!(register.text, "one\ntwothree");
+        assert!(register.pieces.is_empty());
+    }
+}
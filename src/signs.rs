@@ -0,0 +1,227 @@
+// Two column markers left of the line numbers, set by whatever has something to say about a row
+// Each source replaces all of its own signs at once, and a row shows the sign of the highest source
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::document::Document;
+
+// Where signs come from, lowest to highest priority
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignSource {
+    Git,
+    Mark,
+}
+
+// What a sign means, which the theme colours it by
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignKind {
+    Added,
+    Changed,
+    Removed,
+    Mark,
+}
+
+impl SignKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let kind = match name {
+            "added" => SignKind::Added,
+            "changed" => SignKind::Changed,
+            "removed" => SignKind::Removed,
+            "mark" => SignKind::Mark,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+#[derive(Clone)]
+pub struct Sign {
+    pub kind: SignKind,
+    // at most two characters
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Signs {
+    sources: HashMap<SignSource, BTreeMap<usize, Sign>>,
+}
+
+impl Signs {
+    // Replaces every sign from the source with the given ones, by row
+    pub fn set(&mut self, source: SignSource, signs: BTreeMap<usize, Sign>) {
+        self.sources.insert(source, signs);
+    }
+
+    pub fn get(&self, row: usize) -> Option<&Sign> {
+        self.sources
+            .iter()
+            .filter_map(|(source, signs)| Some((source, signs.get(&row)?)))
+            .max_by_key(|(source, _)| **source)
+            .map(|(_, sign)| sign)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.values().all(BTreeMap::is_empty)
+    }
+}
+
+// The a-z and A-Z marks in the document, the row's first two by name
+pub fn mark_signs(document: &Document) -> BTreeMap<usize, Sign> {
+    let mut signs: BTreeMap<usize, Sign> = BTreeMap::new();
+    for (name, pos) in &document.marks {
+        if !name.is_ascii_alphabetic() {
+            continue;
+        }
+        let sign = signs.entry(pos.y).or_insert(Sign {
+            kind: SignKind::Mark,
+            text: String::new(),
+        });
+        if sign.text.chars().count() < 2 {
+            sign.text.push(*name);
+        }
+    }
+    signs
+}
+
+// Rows changed since the last commit, going by git diff on the saved file
+// Nothing when git is missing or the file is not in a repository
+pub fn git_signs(file_name: &str) -> BTreeMap<usize, Sign> {
+    let path = Path::new(file_name);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return BTreeMap::new();
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["diff", "--no-color", "--no-ext-diff", "-U0", "--"])
+        .arg(name)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => diff_signs(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+// The signs for each hunk of a diff made with no lines of context
+fn diff_signs(diff: &str) -> BTreeMap<usize, Sign> {
+    let mut signs = BTreeMap::new();
+    // each hunk starts @@ -old_start,old_count +new_start,new_count @@
+    for line in diff.lines() {
+        let Some(ranges) = line.strip_prefix("@@ -") else {
+            continue;
+        };
+        let mut ranges = ranges.split(' ');
+        let (Some(old), Some(new)) = (ranges.next(), ranges.next()) else {
+            continue;
+        };
+        let count = |range: &str| -> Option<(usize, usize)> {
+            match range.split_once(',') {
+                Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+                None => Some((range.parse().ok()?, 1)),
+            }
+        };
+        let (Some((_, old_count)), Some((start, new_count))) =
+            (count(old), count(new.trim_start_matches('+')))
+        else {
+            continue;
+        };
+        let (kind, text, rows) = match (old_count, new_count) {
+            // removed lines are marked on the row before them, the first row when there is none
+            (_, 0) => (
+                SignKind::Removed,
+                "_",
+                start.saturating_sub(1)..start.max(1),
+            ),
+            (0, _) => (SignKind::Added, "+", start - 1..start - 1 + new_count),
+            _ => (SignKind::Changed, "~", start - 1..start - 1 + new_count),
+        };
+        for row in rows {
+            signs.insert(
+                row,
+                Sign {
+                    kind,
+                    text: text.to_string(),
+                },
+            );
+        }
+    }
+    signs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Position;
+
+    // Each row with a sign and its text
+    fn texts(signs: &BTreeMap<usize, Sign>) -> Vec<(usize, &str)> {
+        signs
+            .iter()
+            .map(|(row, sign)| (*row, sign.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn signs_from_hunks() {
+        let diff = "\
+diff --git a/a.rs b/a.rs
+@@ -2,0 +3,2 @@ fn a() {
++added
++added
+@@ -10 +12 @@
+-old
++new
+@@ -20,3 +21,0 @@
+@@ -1 +0,0 @@
+";
+        let signs = diff_signs(diff);
+        assert_eq!(
+            texts(&signs),
+            [(0, "_"), (2, "+"), (3, "+"), (11, "~"), (20, "_")]
+        );
+        assert!(signs[&2].kind == SignKind::Added && signs[&20].kind == SignKind::Removed);
+        assert!(diff_signs("@@ -x +1 @@").is_empty());
+    }
+
+    #[test]
+    fn higher_sources_win() {
+        let sign = |text: &str| Sign {
+            kind: SignKind::Changed,
+            text: text.to_string(),
+        };
+        let mut signs = Signs::default();
+        assert!(signs.is_empty());
+        signs.set(SignSource::Mark, BTreeMap::from([(1, sign("a"))]));
+        signs.set(
+            SignSource::Git,
+            BTreeMap::from([(1, sign("~")), (2, sign("+"))]),
+        );
+        assert_eq!(signs.get(1).unwrap().text, "a");
+        assert_eq!(signs.get(2).unwrap().text, "+");
+        assert!(signs.get(3).is_none());
+
+        // setting a source again replaces all of its signs
+        signs.set(SignSource::Mark, BTreeMap::new());
+        assert_eq!(signs.get(1).unwrap().text, "~");
+    }
+
+    #[test]
+    fn marks_as_signs() {
+        let mut document = Document::default();
+        for (name, y) in [('a', 0), ('C', 0), ('b', 0), ('d', 4), ('[', 4)] {
+            document.set_mark(name, Position { x: 0, y });
+        }
+        // the first two by name fit in the column
+        assert_eq!(texts(&mark_signs(&document)), [(0, "Ca"), (4, "d")]);
+    }
+}
@@ -0,0 +1,371 @@
+// The status bar laid out from a format string, e.g. "{file} {modified}{=}{mode}{=}{position}"
+// {=} splits it into left, centre and right sections, {{ and }} are literal braces
+// Each segment is drawn in the theme's status_<segment> colours, or the status bar's otherwise
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+
+use crate::theme::{Theme, Ui};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segment {
+    File,
+    Modified,
+    Mode,
+    Filetype,
+    Encoding,
+    LineEnding,
+    Position,
+    Line,
+    Column,
+    Percent,
+    Branch,
+    Clock,
+}
+
+impl Segment {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let segment = match name {
+            "file" => Segment::File,
+            "modified" => Segment::Modified,
+            "mode" => Segment::Mode,
+            "filetype" => Segment::Filetype,
+            "encoding" => Segment::Encoding,
+            "line_ending" => Segment::LineEnding,
+            "position" => Segment::Position,
+            "line" => Segment::Line,
+            "column" => Segment::Column,
+            "percent" => Segment::Percent,
+            "branch" => Segment::Branch,
+            "clock" => Segment::Clock,
+            _ => return None,
+        };
+        Some(segment)
+    }
+}
+
+enum Part {
+    Text(String),
+    // with what came after a : in the braces, the time format for clock
+    Segment(Segment, Option<String>),
+}
+
+// What the segments show, gathered from the editor before drawing
+pub struct StatusInfo<'a> {
+    pub file_name: &'a str,
+    pub modified: bool,
+    pub mode: String,
+    pub filetype: &'static str,
+    pub crlf: bool,
+    // 0-based like the cursor
+    pub line: usize,
+    pub column: usize,
+    pub rows: usize,
+    pub branch: Option<&'a str>,
+}
+
+// The sections of the format, at most three
+fn parse(format: &str) -> Result<Vec<Vec<Part>>, String> {
+    let mut sections = vec![vec![]];
+    let mut text = String::new();
+    let mut rest = format;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '{' if rest.starts_with('{') => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+            '}' if rest.starts_with('}') => {
+                text.push('}');
+                rest = &rest[1..];
+            }
+            '{' => {
+                let end = rest.find('}').ok_or("Unclosed { in status line")?;
+                let (name, arg) = match rest[..end].split_once(':') {
+                    Some((name, arg)) => (name, Some(arg.to_string())),
+                    None => (&rest[..end], None),
+                };
+                rest = &rest[end + 1..];
+                // unwrap - there is always a section
+                let section = sections.last_mut().unwrap();
+                if !text.is_empty() {
+                    section.push(Part::Text(std::mem::take(&mut text)));
+                }
+                if name == "=" {
+                    sections.push(vec![]);
+                    continue;
+                }
+                let segment = Segment::from_name(name)
+                    .ok_or(format!("No status line segment called {}", name))?;
+                if let (Segment::Clock, Some(arg)) = (segment, &arg) {
+                    if StrftimeItems::new(arg).any(|item| item == Item::Error) {
+                        return Err(format!("Bad clock format {}", arg));
+                    }
+                }
+                section.push(Part::Segment(segment, arg));
+            }
+            '}' => return Err("Unopened } in status line".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        // unwrap - there is always a section
+        sections.last_mut().unwrap().push(Part::Text(text));
+    }
+    if sections.len() > 3 {
+        return Err("A status line has at most three sections".to_string());
+    }
+    Ok(sections)
+}
+
+// Checks a format before it is used
+pub fn validate(format: &str) -> Result<(), String> {
+    parse(format).map(|_| ())
+}
+
+// Runs of text and how to draw them
+type Section = Vec<(String, Ui)>;
+
+fn width_of(section: &Section) -> usize {
+    section.iter().map(|(text, _)| text.chars().count()).sum()
+}
+
+// Keeps the first width characters, marking the cut with …
+fn truncate(section: &mut Section, width: usize) {
+    if width_of(section) <= width {
+        return;
+    }
+    let mut left = width.saturating_sub(1);
+    for (text, _) in section.iter_mut() {
+        let len = text.chars().count();
+        if len > left {
+            *text = text.chars().take(left).collect();
+        }
+        left -= len.min(left);
+    }
+    if width > 0 {
+        section.push(("…".to_string(), Ui::StatusBar));
+    }
+}
+
+fn segment_text(segment: Segment, arg: Option<&str>, info: &StatusInfo) -> String {
+    match segment {
+        Segment::File if info.file_name.is_empty() => "[No Name]".to_string(),
+        Segment::File => info.file_name.to_string(),
+        Segment::Modified if info.modified => "[+]".to_string(),
+        Segment::Modified => String::new(),
+        Segment::Mode => info.mode.clone(),
+        Segment::Filetype => info.filetype.to_string(),
+        // rows are kept as strings, so files are always read and written as utf-8
+        Segment::Encoding => "utf-8".to_string(),
+        Segment::LineEnding if info.crlf => "crlf".to_string(),
+        Segment::LineEnding => "lf".to_string(),
+        Segment::Position => format!("{:2}, {:2}", info.column + 1, info.line + 1),
+        Segment::Line => (info.line + 1).to_string(),
+        Segment::Column => (info.column + 1).to_string(),
+        Segment::Percent => match info.rows {
+            0 | 1 => "All".to_string(),
+            rows => format!("{}%", (info.line * 100) / (rows - 1)),
+        },
+        Segment::Branch => info.branch.unwrap_or_default().to_string(),
+        Segment::Clock => Local::now().format(arg.unwrap_or("%H:%M")).to_string(),
+    }
+}
+
+// The status bar width columns wide, an invalid format shows its error instead
+// When the sections do not fit the centre goes first, then the left is cut short
+pub fn render(format: &str, info: &StatusInfo, width: usize, theme: &Theme) -> String {
+    let mut sections: Vec<Section> = match parse(format) {
+        Ok(sections) => sections
+            .iter()
+            .map(|parts| {
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Text(text) => (text.clone(), Ui::StatusBar),
+                        Part::Segment(segment, arg) => (
+                            segment_text(*segment, arg.as_deref(), info),
+                            Ui::Status(*segment),
+                        ),
+                    })
+                    .collect()
+            })
+            .collect(),
+        Err(err) => vec![vec![(err, Ui::StatusBar)]],
+    };
+    let (mut left, mut centre, mut right) = match sections.len() {
+        1 => (sections.remove(0), vec![], vec![]),
+        2 => (sections.remove(0), vec![], sections.remove(0)),
+        _ => (sections.remove(0), sections.remove(0), sections.remove(0)),
+    };
+
+    truncate(&mut right, width);
+    let gap = |section: &Section| usize::from(width_of(section) > 0);
+    if width_of(&left) + gap(&left) + width_of(&centre) + gap(&centre) + width_of(&right) > width {
+        centre.clear();
+    }
+    truncate(
+        &mut left,
+        width.saturating_sub(width_of(&right) + gap(&right)),
+    );
+
+    // the centre is centred on the bar, unless that would run into either side
+    let (left_width, centre_width, right_width) =
+        (width_of(&left), width_of(&centre), width_of(&right));
+    let centre_start = ((width - centre_width) / 2)
+        .max(left_width + gap(&left))
+        .min(width.saturating_sub(right_width + gap(&right) + centre_width));
+
+    let mut output = String::new();
+    let mut column = 0;
+    for (start, section) in [
+        (0, left),
+        (centre_start, centre),
+        (width - right_width, right),
+    ] {
+        if width_of(&section) == 0 {
+            continue;
+        }
+        output += &theme.paint(Ui::StatusBar, &" ".repeat(start.saturating_sub(column)));
+        for (text, ui) in &section {
+            output += &theme.paint(*ui, text);
+        }
+        column = start + width_of(&section);
+    }
+    output += &theme.paint(Ui::StatusBar, &" ".repeat(width.saturating_sub(column)));
+    output
+}
+
+// The git branch of the repository a file is in, looked up again every few seconds
+#[derive(Default)]
+pub struct GitBranch {
+    // the file and the directory it resolved to, only worked out again for another file
+    file: Option<(String, PathBuf)>,
+    checked: Option<Instant>,
+    branch: Option<String>,
+}
+
+impl GitBranch {
+    pub fn get(&mut self, file_name: &str) -> Option<&str> {
+        if !matches!(&self.file, Some((file, _)) if file == file_name) {
+            let dir = Path::new(file_name)
+                .canonicalize()
+                .ok()
+                .and_then(|path| path.parent().map(Path::to_path_buf))
+                .or_else(|| std::env::current_dir().ok())?;
+            self.file = Some((file_name.to_string(), dir));
+            self.checked = None;
+        }
+        // unwrap - the file was set above
+        let (_, dir) = self.file.as_ref().unwrap();
+        if self
+            .checked
+            .is_none_or(|at| at.elapsed() >= Duration::from_secs(5))
+        {
+            self.branch = find_branch(dir);
+            self.checked = Some(Instant::now());
+        }
+        self.branch.as_deref()
+    }
+}
+
+// From .git/HEAD in dir or the nearest directory above it, a commit's short hash when detached
+fn find_branch(dir: &Path) -> Option<String> {
+    let head = dir
+        .ancestors()
+        .find_map(|dir| fs::read_to_string(dir.join(".git").join("HEAD")).ok())?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(file_name: &str) -> StatusInfo<'_> {
+        StatusInfo {
+            file_name,
+            modified: true,
+            mode: "NORMAL".to_string(),
+            filetype: "rust",
+            crlf: false,
+            line: 4,
+            column: 0,
+            rows: 9,
+            branch: None,
+        }
+    }
+
+    // The bar without its colours
+    fn plain(format: &str, info: &StatusInfo, width: usize) -> String {
+        let painted = render(format, info, width, &Theme::default());
+        let mut text = String::new();
+        let mut chars = painted.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn parse_sections() {
+        let sections = parse("{file} {modified}{=}{mode}{=}{line}:{column}").unwrap();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0].len(), 3);
+        assert_eq!(sections[2].len(), 3);
+
+        let sections = parse("{{{clock:%H}}}").unwrap();
+        assert!(matches!(&sections[0][0], Part::Text(text) if text == "{"));
+        assert!(matches!(
+            &sections[0][1],
+            Part::Segment(Segment::Clock, Some(arg)) if arg == "%H"
+        ));
+        assert!(matches!(&sections[0][2], Part::Text(text) if text == "}"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("{file").is_err());
+        assert!(parse("file}").is_err());
+        assert!(parse("{files}").is_err());
+        assert!(parse("{=}{=}{=}").is_err());
+        assert!(parse("{clock:%Q}").is_err());
+        assert!(validate("{file}{=}{=}").is_ok());
+    }
+
+    #[test]
+    fn render_sections() {
+        let format = "{file}{=}{mode}{=}{percent}";
+        assert_eq!(plain(format, &info("a.rs"), 20), "a.rs   NORMAL    50%");
+        assert_eq!(plain("{file}", &info(""), 12), "[No Name]   ");
+        assert_eq!(plain("{bad}", &info(""), 12), "No status l…");
+    }
+
+    #[test]
+    fn render_truncation() {
+        let format = "{file}{=}{mode}{=}{percent}";
+        // the centre goes first
+        assert_eq!(plain(format, &info("main.rs"), 14), "main.rs    50%");
+        // then the left is cut short
+        assert_eq!(plain(format, &info("editor.rs"), 10), "edito… 50%");
+        // and the right only once nothing else fits
+        assert_eq!(plain("{=}{file}", &info("editor.rs"), 6), "edito…");
+        assert_eq!(plain(format, &info("editor.rs"), 0), "");
+    }
+}
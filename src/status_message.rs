@@ -1,6 +1,6 @@
 use std::time::{Duration, Instant};
 
-use crossterm::style::Stylize;
+use crate::theme::{Theme, Ui};
 
 pub struct StatusMessage {
     text: String,
@@ -28,12 +28,12 @@ impl StatusMessage {
         Instant::now() - self.born_at < self.show_time
     }
 
-    pub(crate) fn render(&self, width: usize) -> String {
+    pub(crate) fn render(&self, width: usize, theme: &Theme) -> String {
         let mut text = self.text.clone();
         text.truncate(width);
-        format!("{}{}", text, &" ".repeat(width - text.len()))
-            .black()
-            .on_grey()
-            .to_string()
+        theme.paint(
+            Ui::Message,
+            &format!("{}{}", text, &" ".repeat(width - text.len())),
+        )
     }
 }
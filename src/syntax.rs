@@ -0,0 +1,532 @@
+// Syntax trees from tree-sitter, used for highlighting and structural selection
+// Built only with the tree-sitter feature, otherwise there is never a tree and callers fall back
+
+#[cfg(not(feature = "tree-sitter"))]
+pub use disabled::Syntax;
+#[cfg(feature = "tree-sitter")]
+pub use tree::Syntax;
+
+#[cfg(not(feature = "tree-sitter"))]
+mod disabled {
+    use std::ops::Range;
+
+    use crate::{
+        document::{Row, RowEdit, Span},
+        editor::Position,
+        filetype::Filetype,
+        highlight::Token,
+        text_target::{ObjectScope, TextObject},
+    };
+
+    // Stands in for the syntax tree when tree-sitter is not built in
+    #[derive(Default)]
+    pub struct Syntax;
+
+    impl Syntax {
+        pub fn update(
+            &mut self,
+            _filetype: Filetype,
+            _rows: &[Row],
+            _edit: Option<RowEdit>,
+        ) -> bool {
+            false
+        }
+
+        pub fn highlights(&self, _visible: Range<usize>) -> Vec<Vec<Token>> {
+            vec![]
+        }
+
+        pub fn enclosing_node(&self, _span: &Span) -> Option<Span> {
+            None
+        }
+
+        pub fn object_span(
+            &self,
+            _at: &Position,
+            _object: TextObject,
+            _scope: ObjectScope,
+            _count: usize,
+        ) -> Option<Span> {
+            None
+        }
+
+        pub fn function_start(
+            &self,
+            _at: &Position,
+            _forward: bool,
+            _count: usize,
+        ) -> Option<Position> {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "tree-sitter")]
+mod tree {
+    use std::ops::Range;
+
+    use streaming_iterator::StreamingIterator;
+    use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+    use crate::{
+        document::{Row, RowEdit, Span},
+        editor::Position,
+        filetype::Filetype,
+        highlight::{Scope, Token},
+        text_target::{ObjectScope, TextObject},
+    };
+
+    // The node kinds each structural text object is made of, per language
+    struct Kinds {
+        functions: &'static [&'static str],
+        classes: &'static [&'static str],
+        argument_lists: &'static [&'static str],
+    }
+
+    const RUST_KINDS: Kinds = Kinds {
+        functions: &[
+            "function_item",
+            "function_signature_item",
+            "closure_expression",
+        ],
+        classes: &[
+            "struct_item",
+            "enum_item",
+            "union_item",
+            "trait_item",
+            "impl_item",
+        ],
+        argument_lists: &[
+            "arguments",
+            "parameters",
+            "closure_parameters",
+            "type_arguments",
+            "type_parameters",
+        ],
+    };
+
+    const PYTHON_KINDS: Kinds = Kinds {
+        functions: &["function_definition", "lambda"],
+        classes: &["class_definition"],
+        argument_lists: &["argument_list", "parameters", "lambda_parameters"],
+    };
+
+    struct Grammar {
+        filetype: Filetype,
+        query: Query,
+        kinds: &'static Kinds,
+    }
+
+    fn language(filetype: Filetype) -> Option<(Language, &'static str, &'static Kinds)> {
+        match filetype {
+            Filetype::Rust => Some((
+                tree_sitter_rust::LANGUAGE.into(),
+                tree_sitter_rust::HIGHLIGHTS_QUERY,
+                &RUST_KINDS,
+            )),
+            Filetype::Python => Some((
+                tree_sitter_python::LANGUAGE.into(),
+                tree_sitter_python::HIGHLIGHTS_QUERY,
+                &PYTHON_KINDS,
+            )),
+            _ => None,
+        }
+    }
+
+    // The scope for a highlight query capture such as `function.macro`
+    fn capture_scope(name: &str) -> Option<Scope> {
+        let scope = match name {
+            "function.macro" => Scope::Macro,
+            "variable.parameter" => Scope::Variable,
+            _ => match name.split('.').next()? {
+                "comment" => Scope::Comment,
+                "string" | "escape" => Scope::String,
+                "number" => Scope::Number,
+                "constant" => Scope::Constant,
+                "keyword" => Scope::Keyword,
+                "type" | "constructor" => Scope::Type,
+                "function" => Scope::Function,
+                "attribute" => Scope::Attribute,
+                "property" => Scope::Key,
+                _ => return None,
+            },
+        };
+        Some(scope)
+    }
+
+    fn position(point: Point) -> Position {
+        Position {
+            x: point.column,
+            y: point.row,
+        }
+    }
+
+    fn point(position: &Position) -> Point {
+        Point {
+            row: position.y,
+            column: position.x,
+        }
+    }
+
+    fn node_span(node: Node) -> Span {
+        Span {
+            start: position(node.start_position()),
+            end: position(node.end_position()),
+        }
+    }
+
+    // Where the row starting at byte begins, or with back where the row above it ends
+    fn row_point(text: &str, byte: usize, row: usize, back: bool) -> Point {
+        if !back || row == 0 {
+            return Point { row, column: 0 };
+        }
+        let line_start = text[..byte - 1].rfind('\n').map_or(0, |i| i + 1);
+        Point {
+            row: row - 1,
+            column: byte - 1 - line_start,
+        }
+    }
+
+    // Puts the rows the edit replaced into text, returning the edit for the tree
+    fn splice(text: &mut String, rows: &[Row], edit: RowEdit) -> InputEdit {
+        // rows above the edit are the same in both versions
+        let start: usize = rows[..edit.start].iter().map(|row| row.len() + 1).sum();
+        let mut old_end = start;
+        for _ in edit.start..edit.old_end {
+            old_end += text[old_end..]
+                .find('\n')
+                .map_or(text.len() - old_end, |i| i + 1);
+        }
+        let new_text: String = rows[edit.start..edit.new_end]
+            .iter()
+            .map(|row| format!("{}\n", row.as_str()))
+            .collect();
+        let new_end = start + new_text.len();
+
+        // the parser is not shown the last line break, so an edit running up to it ends a byte sooner
+        // and when it only adds or only takes away rows, it starts a byte sooner too
+        let back_end = old_end == text.len();
+        let back_start = back_end && (start == old_end || start == new_end);
+        let byte = |byte: usize, back: bool| byte.saturating_sub(back as usize);
+        let start_position = row_point(text, start, edit.start, back_start);
+        let old_end_position = row_point(text, old_end, edit.old_end, back_end);
+        text.replace_range(start..old_end, &new_text);
+        InputEdit {
+            start_byte: byte(start, back_start),
+            old_end_byte: byte(old_end, back_end),
+            new_end_byte: byte(new_end, back_end),
+            start_position,
+            old_end_position,
+            new_end_position: row_point(text, new_end, edit.new_end, back_end),
+        }
+    }
+
+    // The document's text kept parsed, reparsing only around what changed since last time
+    pub struct Syntax {
+        parser: Parser,
+        grammar: Option<Grammar>,
+        // every row followed by a line break
+        text: String,
+        tree: Option<Tree>,
+    }
+
+    impl Default for Syntax {
+        fn default() -> Self {
+            Self {
+                parser: Parser::new(),
+                grammar: None,
+                text: String::new(),
+                tree: None,
+            }
+        }
+    }
+
+    impl Syntax {
+        // Brings the tree in line with the rows after edit, false when there is no grammar for the filetype
+        pub fn update(&mut self, filetype: Filetype, rows: &[Row], edit: Option<RowEdit>) -> bool {
+            if self.grammar.as_ref().map(|grammar| grammar.filetype) != Some(filetype) {
+                self.tree = None;
+                self.text.clear();
+                self.grammar = language(filetype).and_then(|(language, highlights, kinds)| {
+                    self.parser.set_language(&language).ok()?;
+                    Some(Grammar {
+                        filetype,
+                        query: Query::new(&language, highlights).ok()?,
+                        kinds,
+                    })
+                });
+            }
+            if self.grammar.is_none() {
+                return false;
+            }
+
+            let edited = match (&mut self.tree, edit) {
+                // nothing has changed since the last parse
+                (Some(_), None) => return true,
+                (Some(tree), Some(edit)) if edit.new_end <= rows.len() => {
+                    tree.edit(&splice(&mut self.text, rows, edit));
+                    true
+                }
+                _ => false,
+            };
+            if !edited {
+                self.tree = None;
+                self.text = rows
+                    .iter()
+                    .map(|row| format!("{}\n", row.as_str()))
+                    .collect();
+            }
+            let shown = self.text.len().saturating_sub(1);
+            self.tree = self.parser.parse(&self.text[..shown], self.tree.as_ref());
+            self.tree.is_some()
+        }
+
+        // The tokens of each visible row, earlier captures winning where they overlap
+        pub fn highlights(&self, visible: Range<usize>) -> Vec<Vec<Token>> {
+            let mut rows: Vec<Vec<Token>> = visible.clone().map(|_| vec![]).collect();
+            let (Some(tree), Some(grammar)) = (&self.tree, &self.grammar) else {
+                return rows;
+            };
+            let mut cursor = QueryCursor::new();
+            cursor.set_point_range(
+                Point {
+                    row: visible.start,
+                    column: 0,
+                }..Point {
+                    row: visible.end,
+                    column: 0,
+                },
+            );
+            let names = grammar.query.capture_names();
+            let mut captures =
+                cursor.captures(&grammar.query, tree.root_node(), self.text.as_bytes());
+            while let Some((found, i)) = captures.next() {
+                let capture = found.captures[*i];
+                let Some(scope) = capture_scope(names[capture.index as usize]) else {
+                    continue;
+                };
+                let span = node_span(capture.node);
+                for y in span.start.y.max(visible.start)..=span.end.y.min(visible.end - 1) {
+                    let Some(columns) = span.columns_on(y) else {
+                        continue;
+                    };
+                    let tokens = &mut rows[y - visible.start];
+                    if columns.is_empty()
+                        || tokens
+                            .last()
+                            .is_some_and(|last| last.columns.end > columns.start)
+                    {
+                        continue;
+                    }
+                    tokens.push(Token { columns, scope });
+                }
+            }
+            rows
+        }
+
+        // The smallest node covering more than the span
+        pub fn enclosing_node(&self, span: &Span) -> Option<Span> {
+            let tree = self.tree.as_ref()?;
+            let mut node = tree
+                .root_node()
+                .named_descendant_for_point_range(point(&span.start), point(&span.end))?;
+            loop {
+                let found = node_span(node);
+                if found.start < span.start || found.end > span.end {
+                    return Some(found);
+                }
+                node = node.parent()?;
+            }
+        }
+
+        // A function, class or argument around the position, count of them out
+        pub fn object_span(
+            &self,
+            at: &Position,
+            object: TextObject,
+            scope: ObjectScope,
+            count: usize,
+        ) -> Option<Span> {
+            let tree = self.tree.as_ref()?;
+            let kinds = self.grammar.as_ref()?.kinds;
+            let is_object = |node: &Node| match object {
+                TextObject::Function => kinds.functions.contains(&node.kind()),
+                TextObject::Class => kinds.classes.contains(&node.kind()),
+                TextObject::Argument => {
+                    node.is_named()
+                        && node
+                            .parent()
+                            .is_some_and(|parent| kinds.argument_lists.contains(&parent.kind()))
+                }
+                _ => false,
+            };
+
+            let mut node = tree
+                .root_node()
+                .descendant_for_point_range(point(at), point(at))?;
+            for i in 0..count.max(1) {
+                if i > 0 {
+                    node = node.parent()?;
+                }
+                while !is_object(&node) {
+                    node = node.parent()?;
+                }
+            }
+
+            match (object, scope) {
+                (TextObject::Argument, ObjectScope::Inner) => Some(node_span(node)),
+                (TextObject::Argument, ObjectScope::Around) => Some(argument_around(node)),
+                (_, ObjectScope::Around) => Some(node_span(node)),
+                (_, ObjectScope::Inner) => {
+                    let body = node.child_by_field_name("body")?;
+                    Some(inside_brackets(body))
+                }
+            }
+        }
+
+        // Where the count'th function after or before the position starts
+        pub fn function_start(
+            &self,
+            at: &Position,
+            forward: bool,
+            count: usize,
+        ) -> Option<Position> {
+            let tree = self.tree.as_ref()?;
+            let kinds = self.grammar.as_ref()?.kinds;
+            let mut starts = vec![];
+            let mut cursor = tree.walk();
+            // visits every node once, going down before along
+            'walk: loop {
+                let node = cursor.node();
+                if kinds.functions.contains(&node.kind()) && node.kind() != "closure_expression" {
+                    starts.push(position(node.start_position()));
+                }
+                if cursor.goto_first_child() {
+                    continue;
+                }
+                while !cursor.goto_next_sibling() {
+                    if !cursor.goto_parent() {
+                        break 'walk;
+                    }
+                }
+            }
+
+            match forward {
+                true => starts.into_iter().filter(|start| start > at).nth(count - 1),
+                false => starts
+                    .into_iter()
+                    .rev()
+                    .filter(|start| start < at)
+                    .nth(count - 1),
+            }
+        }
+    }
+
+    // The node's text inside its brackets, or all of it when it has none
+    // Brackets on rows of their own leave out the line breaks next to them, like vim's i{
+    fn inside_brackets(node: Node) -> Span {
+        let (Some(first), Some(last)) = (node.child(0), node.child(node.child_count() - 1)) else {
+            return node_span(node);
+        };
+        if first.is_named() || last.is_named() || node.child_count() < 2 {
+            return node_span(node);
+        }
+        let mut span = Span {
+            start: position(first.end_position()),
+            end: position(last.start_position()),
+        };
+        let (Some(inner_first), Some(inner_last)) = (first.next_sibling(), last.prev_sibling())
+        else {
+            return span;
+        };
+        if inner_first.start_position().row > span.start.y {
+            span.start = Position {
+                x: 0,
+                y: span.start.y + 1,
+            };
+        }
+        if inner_last.end_position().row < span.end.y {
+            span.end.x = 0;
+        }
+        span
+    }
+
+    // An argument with the comma and space after it, or the comma before when it is the last
+    fn argument_around(node: Node) -> Span {
+        let mut span = node_span(node);
+        match node.next_sibling() {
+            Some(comma) if comma.kind() == "," => {
+                span.end = match comma.next_named_sibling() {
+                    Some(next) => position(next.start_position()),
+                    None => position(comma.end_position()),
+                };
+            }
+            _ => {
+                if let Some(comma) = node.prev_sibling().filter(|prev| prev.kind() == ",") {
+                    span.start = match comma.prev_named_sibling() {
+                        Some(prev) => position(prev.end_position()),
+                        None => position(comma.start_position()),
+                    };
+                }
+            }
+        }
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::Row,
+        editor::Position,
+        filetype::Filetype,
+        text_target::{ObjectScope, TextObject},
+    };
+
+    fn syntax(lines: &[&str]) -> Syntax {
+        let rows: Vec<Row> = lines.iter().map(|&line| Row::from(line)).collect();
+        let mut syntax: Syntax = Default::default();
+        syntax.update(Filetype::Rust, &rows, None);
+        syntax
+    }
+
+    const SOURCE: [&str; 5] = ["use std::io;", "", "fn a() {", "    b(1, 2);", "}"];
+
+    #[test]
+    fn objects_outside_any_node() {
+        let syntax = syntax(&SOURCE);
+        let at = Position { x: 0, y: 0 };
+        for object in [
+            TextObject::Function,
+            TextObject::Class,
+            TextObject::Argument,
+        ] {
+            assert!(syntax
+                .object_span(&at, object, ObjectScope::Inner, 1)
+                .is_none());
+        }
+        // there is no second function around the first
+        let at = Position { x: 4, y: 3 };
+        assert!(syntax
+            .object_span(&at, TextObject::Function, ObjectScope::Around, 2)
+            .is_none());
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn objects_inside_nodes() {
+        let syntax = syntax(&SOURCE);
+        let at = Position { x: 4, y: 3 };
+        let span = syntax
+            .object_span(&at, TextObject::Function, ObjectScope::Around, 1)
+            .unwrap();
+        assert!(span.start == Position { x: 0, y: 2 } && span.end == Position { x: 1, y: 4 });
+        let at = Position { x: 9, y: 3 };
+        let span = syntax
+            .object_span(&at, TextObject::Argument, ObjectScope::Around, 1)
+            .unwrap();
+        // the last argument takes the comma before it
+        assert!(span.start == Position { x: 7, y: 3 } && span.end == Position { x: 10, y: 3 });
+    }
+}
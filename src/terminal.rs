@@ -2,7 +2,7 @@ use std::io::{self, Write};
 
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
-    event::{KeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    event::{EnableBracketedPaste, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
     execute,
     terminal::{enable_raw_mode, size, Clear, ClearType},
 };
@@ -28,14 +28,24 @@ impl Terminal {
             )?;
         }
 
+        // pastes arrive as one event rather than a key press per character
+        execute!(io::stdout(), EnableBracketedPaste)?;
+
         enable_raw_mode()?;
-        let term_size = size()?;
-        Ok(Terminal {
-            size: Size {
-                width: term_size.0,
-                height: term_size.1,
-            },
-        })
+        let (width, height) = size()?;
+        let mut terminal = Terminal {
+            size: Size { width, height },
+        };
+        terminal.resize(width, height);
+        Ok(terminal)
+    }
+
+    // Keeps at least a row of text above the status bar and message, however small it gets
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.size = Size {
+            width: width.max(1),
+            height: height.max(3),
+        };
     }
 
     // Clears the entire terminal display
@@ -53,14 +63,8 @@ impl Terminal {
     }
 
     pub fn move_cursor(position: &Position) {
-        let x = match position.x.try_into() {
-            Ok(x) => x,
-            Err(_) => u16::MAX,
-        };
-        let y = match position.y.try_into() {
-            Ok(y) => y,
-            Err(_) => u16::MAX,
-        };
+        let x = position.x.try_into().unwrap_or(u16::MAX);
+        let y = position.y.try_into().unwrap_or(u16::MAX);
 
         if let Err(e) = execute!(io::stdout(), MoveTo(x, y)) {
             panic!("Panic during cursor movement. {}", e);
@@ -83,3 +87,23 @@ impl Terminal {
         let _ = io::stdout().flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_keeps_room_for_text() {
+        let mut terminal = Terminal {
+            size: Size {
+                width: 80,
+                height: 24,
+            },
+        };
+        terminal.resize(100, 40);
+        assert_eq!((terminal.size.width, terminal.size.height), (100, 40));
+        // a row of text above the status bar and message, and a column of it
+        terminal.resize(0, 1);
+        assert_eq!((terminal.size.width, terminal.size.height), (1, 3));
+    }
+}
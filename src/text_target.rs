@@ -1,9 +1,477 @@
+use std::cmp;
+
+use crate::{document::Span, modal::VerticalDirection};
+
 #[derive(Clone, Copy)]
 pub enum TextTarget {
     Nothing,
     All,
     WholeRow,
+    Rows(VerticalDirection), // the cursor's row and count more below or above, j and k after an operator
+    ToLine(Option<usize>),   // the rows from the cursor's to the line, the last one when None
     RowAfterCursor,
     UnderCursor,
-    Char(char),
+    BeforeCursor, // count characters back along the row, h after an operator
+    Char(FindKind, char, bool), // f F t T, the bool set when repeated by ; or ,
+    Object(TextObject, ObjectScope),
+    Selection,  // the visual selection, the editor swaps this for a Span
+    Span(Span), // an exact region of the document
+}
+
+impl TextTarget {
+    // targets taking whole rows, their text is pasted as rows of its own
+    pub fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            TextTarget::All | TextTarget::WholeRow | TextTarget::Rows(_) | TextTarget::ToLine(_)
+        )
+    }
+}
+
+// The in-line character searches, f F t T respectively
+#[derive(Clone, Copy)]
+pub enum FindKind {
+    Forward,
+    Backward,
+    TillForward,
+    TillBackward,
+}
+
+impl FindKind {
+    // the same search going the other way, used by `,`
+    pub fn reversed(self) -> Self {
+        match self {
+            FindKind::Forward => FindKind::Backward,
+            FindKind::Backward => FindKind::Forward,
+            FindKind::TillForward => FindKind::TillBackward,
+            FindKind::TillBackward => FindKind::TillForward,
+        }
+    }
+
+    pub fn is_forward(self) -> bool {
+        matches!(self, FindKind::Forward | FindKind::TillForward)
+    }
+}
+
+// Vim style text objects, chosen after `i` or `a` following an operator or in visual mode
+#[derive(Clone, Copy)]
+pub enum TextObject {
+    Word,
+    BigWord,
+    Sentence,
+    Paragraph,
+    Quote(char),
+    Bracket(char, char),
+    Tag,
+    // from the syntax tree, found by the editor rather than the document
+    Function,
+    Class,
+    Argument,
+}
+
+impl TextObject {
+    pub fn from_key(c: char) -> Option<Self> {
+        let object = match c {
+            'w' => TextObject::Word,
+            'W' => TextObject::BigWord,
+            's' => TextObject::Sentence,
+            'p' => TextObject::Paragraph,
+            '"' | '\'' | '`' => TextObject::Quote(c),
+            '(' | ')' | 'b' => TextObject::Bracket('(', ')'),
+            '[' | ']' => TextObject::Bracket('[', ']'),
+            '{' | '}' | 'B' => TextObject::Bracket('{', '}'),
+            '<' | '>' => TextObject::Bracket('<', '>'),
+            't' => TextObject::Tag,
+            'f' => TextObject::Function,
+            'c' => TextObject::Class,
+            'a' => TextObject::Argument,
+            _ => return None,
+        };
+        Some(object)
+    }
+}
+
+// Whether a text object covers only its contents or also its surroundings
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ObjectScope {
+    Inner,
+    Around,
+}
+
+// Each resolver below works on the document flattened into chars with '\n' between rows
+// and returns the [start, end) char range of the object around the cursor index `at`
+
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    LineBreak,
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn char_class(c: char, big: bool) -> CharClass {
+    if c == '\n' {
+        CharClass::LineBreak
+    } else if c.is_whitespace() {
+        CharClass::Blank
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// index after the run of characters sharing a class with text[from], line breaks are never a run
+fn run_end(text: &[char], from: usize, big: bool) -> usize {
+    let Some(&first) = text.get(from) else {
+        return from;
+    };
+    let class = char_class(first, big);
+    if class == CharClass::LineBreak {
+        return from;
+    }
+    let mut end = from;
+    while end < text.len() && char_class(text[end], big) == class {
+        end += 1;
+    }
+    end
+}
+
+fn run_start(text: &[char], from: usize, big: bool) -> usize {
+    let class = char_class(text[from], big);
+    let mut start = from;
+    while start > 0 && char_class(text[start - 1], big) == class {
+        start -= 1;
+    }
+    start
+}
+
+pub fn word_range(
+    text: &[char],
+    at: usize,
+    big: bool,
+    scope: ObjectScope,
+    count: usize,
+) -> Option<(usize, usize)> {
+    if at >= text.len() || text[at] == '\n' {
+        return None;
+    }
+    let is_blank = |i: usize| char_class(text[i], big) == CharClass::Blank;
+    let mut start = run_start(text, at, big);
+    let mut end = start;
+
+    if scope == ObjectScope::Inner {
+        for _ in 0..count {
+            let next = run_end(text, end, big);
+            if next == end {
+                break;
+            }
+            end = next;
+        }
+        return Some((start, end));
+    }
+
+    let started_blank = is_blank(at);
+    let mut took_trailing = false;
+    for _ in 0..count {
+        if started_blank {
+            // leading whitespace then the word after it
+            end = run_end(text, end, big);
+            end = run_end(text, end, big);
+        } else {
+            end = run_end(text, end, big);
+            took_trailing = end < text.len() && is_blank(end);
+            if took_trailing {
+                end = run_end(text, end, big);
+            }
+        }
+    }
+    if !started_blank && !took_trailing {
+        // nothing trailing so the whitespace before the word goes instead
+        while start > 0 && is_blank(start - 1) {
+            start -= 1;
+        }
+    }
+    Some((start, end))
+}
+
+pub fn sentence_range(
+    text: &[char],
+    at: usize,
+    scope: ObjectScope,
+    count: usize,
+) -> Option<(usize, usize)> {
+    // (start, end of sentence, end including trailing whitespace)
+    let mut sentences = vec![];
+    let mut pos = 0;
+    while pos < text.len() {
+        let start = pos;
+        let mut end = pos;
+        while end < text.len() {
+            let c = text[end];
+            if matches!(c, '.' | '!' | '?') && text.get(end + 1).is_none_or(|n| n.is_whitespace()) {
+                end += 1;
+                break;
+            }
+            if c == '\n' && text.get(end + 1) == Some(&'\n') {
+                break;
+            }
+            end += 1;
+        }
+        let mut around = end;
+        while around < text.len() && text[around].is_whitespace() {
+            around += 1;
+        }
+        sentences.push((start, end, around));
+        pos = around.max(start + 1);
+    }
+
+    let first = sentences.iter().position(|s| at < s.2)?;
+    let last = cmp::min(first + count.saturating_sub(1), sentences.len() - 1);
+    let start = sentences[first].0;
+    let end = match scope {
+        ObjectScope::Inner => sentences[last].1,
+        ObjectScope::Around => sentences[last].2,
+    };
+    Some((start, end))
+}
+
+pub fn quote_range(
+    line: &[char],
+    at: usize,
+    quote: char,
+    scope: ObjectScope,
+) -> Option<(usize, usize)> {
+    let quotes: Vec<usize> = (0..line.len())
+        .filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\'))
+        .collect();
+    // the pair around the cursor, otherwise the first pair after it
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| at <= close)?;
+
+    match scope {
+        ObjectScope::Inner => Some((open + 1, close)),
+        ObjectScope::Around => {
+            let mut start = open;
+            let mut end = close + 1;
+            if end < line.len() && line[end].is_whitespace() {
+                while end < line.len() && line[end].is_whitespace() {
+                    end += 1;
+                }
+            } else {
+                while start > 0 && line[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+            }
+            Some((start, end))
+        }
+    }
+}
+
+// count picks how many levels of enclosing brackets to go out
+pub fn bracket_range(
+    text: &[char],
+    at: usize,
+    open: char,
+    close: char,
+    scope: ObjectScope,
+    count: usize,
+) -> Option<(usize, usize)> {
+    let at = cmp::min(at, text.len().checked_sub(1)?);
+    let mut depth = 0;
+    let mut level = count.max(1);
+    let mut open_idx = None;
+    for i in (0..=at).rev() {
+        if text[i] == close && i != at {
+            depth += 1;
+        } else if text[i] == open {
+            if depth == 0 {
+                level -= 1;
+                if level == 0 {
+                    open_idx = Some(i);
+                    break;
+                }
+            } else {
+                depth -= 1;
+            }
+        }
+    }
+    let open_idx = open_idx?;
+
+    depth = 0;
+    let mut close_idx = None;
+    for (i, &c) in text.iter().enumerate().skip(open_idx + 1) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                close_idx = Some(i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let close_idx = close_idx?;
+
+    match scope {
+        ObjectScope::Around => Some((open_idx, close_idx + 1)),
+        ObjectScope::Inner => {
+            // a block spread over lines keeps its brackets on their own lines
+            let mut start = open_idx + 1;
+            let mut end = close_idx;
+            if text.get(start) == Some(&'\n') {
+                start += 1;
+                let mut line_start = end;
+                while line_start > start && matches!(text[line_start - 1], ' ' | '\t') {
+                    line_start -= 1;
+                }
+                if line_start > start && text[line_start - 1] == '\n' {
+                    end = line_start;
+                }
+            }
+            Some((start, cmp::min(cmp::max(start, end), close_idx)))
+        }
+    }
+}
+
+pub fn tag_range(
+    text: &[char],
+    at: usize,
+    scope: ObjectScope,
+    count: usize,
+) -> Option<(usize, usize)> {
+    // (open tag start, open tag end, close tag start, close tag end)
+    let mut pairs = vec![];
+    let mut open_tags: Vec<(String, usize, usize)> = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] != '<' {
+            i += 1;
+            continue;
+        }
+        let Some(len) = text[i..].iter().position(|&c| c == '>') else {
+            break;
+        };
+        let tag: String = text[i + 1..i + len].iter().collect();
+        let end = i + len + 1;
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            if let Some(idx) = open_tags.iter().rposition(|(open, _, _)| open == name) {
+                let (_, open_start, open_end) = open_tags[idx];
+                open_tags.truncate(idx);
+                pairs.push((open_start, open_end, i, end));
+            }
+        } else if !(tag.ends_with('/') || tag.starts_with('!') || tag.starts_with('?')) {
+            let name: String = tag.chars().take_while(|c| !c.is_whitespace()).collect();
+            open_tags.push((name, i, end));
+        }
+        i = end;
+    }
+
+    let mut enclosing: Vec<_> = pairs
+        .into_iter()
+        .filter(|&(open_start, _, _, close_end)| open_start <= at && at < close_end)
+        .collect();
+    // innermost first
+    enclosing.sort_by_key(|pair| cmp::Reverse(pair.0));
+    let (open_start, open_end, close_start, close_end) = *enclosing.get(count.saturating_sub(1))?;
+    match scope {
+        ObjectScope::Inner => Some((open_end, close_start)),
+        ObjectScope::Around => Some((open_start, close_end)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ObjectScope::{Around, Inner};
+
+    // The text an object covers, found around the first char of `at` in text
+    fn object(
+        text: &str,
+        at: &str,
+        find: impl Fn(&[char], usize) -> Option<(usize, usize)>,
+    ) -> Option<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let at = text[..text.find(at).unwrap()].chars().count();
+        let (start, end) = find(&chars, at)?;
+        Some(chars[start..end].iter().collect())
+    }
+
+    #[test]
+    fn words() {
+        let word = |at, big, scope, count| {
+            object("foo bar.baz  qux", at, |text, at| {
+                word_range(text, at, big, scope, count)
+            })
+        };
+        assert_eq!(word("ar", false, Inner, 1).unwrap(), "bar");
+        assert_eq!(word("ar", false, Inner, 3).unwrap(), "bar.baz");
+        assert_eq!(word("ar", true, Inner, 1).unwrap(), "bar.baz");
+        // trailing whitespace goes with the word
+        assert_eq!(word("oo", false, Around, 1).unwrap(), "foo ");
+        assert_eq!(word("ar", true, Around, 1).unwrap(), "bar.baz  ");
+        // unless there is none, then it is the whitespace before
+        assert_eq!(word("ar", false, Around, 1).unwrap(), " bar");
+        assert_eq!(word("qux", false, Around, 1).unwrap(), "  qux");
+        // starting on whitespace takes the word after it
+        assert_eq!(word("  ", false, Around, 1).unwrap(), "  qux");
+        assert_eq!(word("  ", false, Inner, 1).unwrap(), "  ");
+    }
+
+    #[test]
+    fn words_stop_at_line_breaks() {
+        let text = "ab\ncd";
+        let inner = |at| object(text, at, |text, at| word_range(text, at, false, Inner, 2));
+        assert_eq!(inner("ab").unwrap(), "ab");
+        assert!(inner("\n").is_none());
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "f(a, (b), c)";
+        let paren = |at, scope, count| {
+            object(text, at, |text, at| {
+                bracket_range(text, at, '(', ')', scope, count)
+            })
+        };
+        assert_eq!(paren("b", Inner, 1).unwrap(), "b");
+        assert_eq!(paren("b", Around, 1).unwrap(), "(b)");
+        assert_eq!(paren("b", Inner, 2).unwrap(), "a, (b), c");
+        // on a bracket is inside it
+        assert_eq!(paren("(b", Inner, 1).unwrap(), "b");
+        assert_eq!(paren("), c", Inner, 1).unwrap(), "b");
+        assert_eq!(paren("a", Around, 1).unwrap(), "(a, (b), c)");
+        assert!(paren("f", Inner, 1).is_none());
+        assert!(paren("b", Inner, 3).is_none());
+    }
+
+    #[test]
+    fn brackets_over_lines() {
+        let text = "{\n    x;\n}";
+        let inner = object(text, "x", |text, at| {
+            bracket_range(text, at, '{', '}', Inner, 1)
+        });
+        assert_eq!(inner.unwrap(), "    x;\n");
+        let empty = object("()", ")", |text, at| {
+            bracket_range(text, at, '(', ')', Inner, 1)
+        });
+        assert_eq!(empty.unwrap(), "");
+    }
+
+    #[test]
+    fn tags() {
+        let text = "<div class=\"a\"><b>hi</b> <br/><!-- c --></div>";
+        let tag = |at, scope, count| object(text, at, |text, at| tag_range(text, at, scope, count));
+        assert_eq!(tag("hi", Inner, 1).unwrap(), "hi");
+        assert_eq!(tag("hi", Around, 1).unwrap(), "<b>hi</b>");
+        // self closing tags and comments are not pairs
+        assert_eq!(tag("hi", Inner, 2).unwrap(), "<b>hi</b> <br/><!-- c -->");
+        assert_eq!(tag("br", Inner, 1).unwrap(), "<b>hi</b> <br/><!-- c -->");
+        assert_eq!(tag("</div", Around, 1).unwrap(), text);
+        assert!(tag("hi", Inner, 3).is_none());
+        assert!(object("<p>x", "x", |text, at| tag_range(text, at, Inner, 1)).is_none());
+    }
 }
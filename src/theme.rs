@@ -0,0 +1,425 @@
+// Colour schemes for the syntax scopes and the editor's own parts, read from TOML
+//
+// [ui]
+// status_bar = { fg = "white", bg = "#1d4f91" }
+// whitespace = "dark_grey"          # a bare colour is the foreground
+// [syntax]
+// comment = ["#928374", 245, "dark_grey"]   # fallbacks, the first the terminal can show is used
+//
+// A colour is a name like "dark_blue", "#rrggbb" or a 256-colour index
+
+use std::{collections::HashMap, env, fs};
+
+use crossterm::style::{Color, Stylize};
+use toml::{Table, Value};
+
+use crate::{config::config_dir, highlight::Scope, signs::SignKind, status_line::Segment};
+
+const BUILT_IN: [(&str, &str); 4] = [
+    ("default", include_str!("../themes/default.toml")),
+    ("gruvbox", include_str!("../themes/gruvbox.toml")),
+    ("solarized", include_str!("../themes/solarized.toml")),
+    ("mono", include_str!("../themes/mono.toml")),
+];
+
+// The parts of the editor drawn outside of the text's own colours
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ui {
+    StatusBar,
+    Message,
+    Whitespace,
+    NonText,
+    LineNumber,
+    // a segment of the status bar, drawn like the rest of it unless the theme says otherwise
+    Status(Segment),
+    // a sign left of the line numbers
+    Sign(SignKind),
+}
+
+impl Ui {
+    fn from_name(name: &str) -> Option<Self> {
+        let ui = match name {
+            "status_bar" => Ui::StatusBar,
+            "message" => Ui::Message,
+            "whitespace" => Ui::Whitespace,
+            "nontext" => Ui::NonText,
+            "line_number" => Ui::LineNumber,
+            _ if name.starts_with("sign_") => Ui::Sign(SignKind::from_name(&name[5..])?),
+            _ => Ui::Status(Segment::from_name(name.strip_prefix("status_")?)?),
+        };
+        Some(ui)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+impl Style {
+    fn paint(&self, text: &str) -> String {
+        let mut styled = text.stylize();
+        if let Some(fg) = self.fg {
+            styled = styled.with(fg);
+        }
+        if let Some(bg) = self.bg {
+            styled = styled.on(bg);
+        }
+        styled.to_string()
+    }
+}
+
+// How many colours the terminal can show, going by $COLORTERM and $TERM
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+        if env::var("TERM").is_ok_and(|term| term.contains("256color")) {
+            return ColorDepth::Ansi256;
+        }
+        ColorDepth::Ansi16
+    }
+}
+
+pub struct Theme {
+    name: String,
+    syntax: HashMap<Scope, Color>,
+    ui: HashMap<Ui, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::load("default").expect("the default theme is valid")
+    }
+}
+
+impl Theme {
+    // Looks in the config directory's themes/ first, so built in themes can be replaced
+    pub fn load(name: &str) -> Result<Self, String> {
+        let user_file = config_dir().map(|dir| dir.join("themes").join(format!("{}.toml", name)));
+        let source = match user_file.and_then(|path| fs::read_to_string(path).ok()) {
+            Some(source) => source,
+            None => BUILT_IN
+                .iter()
+                .find(|(built_in, _)| *built_in == name)
+                .map(|(_, source)| source.to_string())
+                .ok_or(format!("Cannot find color scheme: {}", name))?,
+        };
+        Self::parse(name, &source).map_err(|err| format!("Color scheme {}: {}", name, err))
+    }
+
+    fn parse(name: &str, source: &str) -> Result<Self, String> {
+        // the message's first line, the rest points into the file
+        let table: Table = source.parse().map_err(|err: toml::de::Error| {
+            err.message().lines().next().unwrap_or_default().to_string()
+        })?;
+        let depth = ColorDepth::detect();
+        let mut theme = Self {
+            name: name.to_string(),
+            syntax: HashMap::new(),
+            ui: HashMap::new(),
+        };
+        for (key, value) in table_at(&table, "syntax")?.into_iter().flatten() {
+            let scope = Scope::from_name(key).ok_or(format!("unknown syntax scope {}", key))?;
+            theme.syntax.insert(scope, parse_color(value, depth)?);
+        }
+        for (key, value) in table_at(&table, "ui")?.into_iter().flatten() {
+            let ui = Ui::from_name(key).ok_or(format!("unknown ui part {}", key))?;
+            let style = match value {
+                Value::Table(style) => Style {
+                    fg: style
+                        .get("fg")
+                        .map(|fg| parse_color(fg, depth))
+                        .transpose()?,
+                    bg: style
+                        .get("bg")
+                        .map(|bg| parse_color(bg, depth))
+                        .transpose()?,
+                },
+                _ => Style {
+                    fg: Some(parse_color(value, depth)?),
+                    bg: None,
+                },
+            };
+            theme.ui.insert(ui, style);
+        }
+        Ok(theme)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self, scope: Scope) -> Option<Color> {
+        self.syntax.get(&scope).copied()
+    }
+
+    // Draws text as the given part, which keeps its usual look when the theme leaves it out
+    pub fn paint(&self, ui: Ui, text: &str) -> String {
+        if let Some(style) = self.ui.get(&ui) {
+            return style.paint(text);
+        }
+        match ui {
+            Ui::StatusBar => text.white().on_dark_blue().to_string(),
+            Ui::Message => text.black().on_grey().to_string(),
+            Ui::Whitespace => text.dim().to_string(),
+            Ui::NonText => text.to_string(),
+            Ui::LineNumber => text.dark_grey().to_string(),
+            Ui::Status(_) => self.paint(Ui::StatusBar, text),
+            Ui::Sign(SignKind::Added) => text.green().to_string(),
+            Ui::Sign(SignKind::Changed) => text.yellow().to_string(),
+            Ui::Sign(SignKind::Removed) => text.red().to_string(),
+            Ui::Sign(SignKind::Mark) => text.cyan().to_string(),
+        }
+    }
+
+    // The built in themes and any in the config directory, for listing
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|(name, _)| name.to_string()).collect();
+        let user_themes = config_dir().and_then(|dir| fs::read_dir(dir.join("themes")).ok());
+        for entry in user_themes.into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(stem) = path.file_stem() {
+                    names.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+fn table_at<'a>(table: &'a Table, key: &str) -> Result<Option<&'a Table>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Table(inner)) => Ok(Some(inner)),
+        Some(_) => Err(format!("{} should be a table", key)),
+    }
+}
+
+// A colour or a list of fallbacks, the first the terminal can show is used
+// When none of them fits the first is brought down to the nearest colour that does
+fn parse_color(value: &Value, depth: ColorDepth) -> Result<Color, String> {
+    let choices = match value {
+        Value::Array(choices) => choices.iter().collect(),
+        _ => vec![value],
+    };
+    let colors = choices
+        .into_iter()
+        .map(color_from_value)
+        .collect::<Result<Vec<Color>, String>>()?;
+    let first = *colors.first().ok_or("empty list of colors")?;
+    Ok(colors
+        .into_iter()
+        .find(|color| depth_of(*color) <= depth)
+        .unwrap_or_else(|| reduce(first, depth)))
+}
+
+fn color_from_value(value: &Value) -> Result<Color, String> {
+    match value {
+        Value::Integer(index) => u8::try_from(*index)
+            .map(Color::AnsiValue)
+            .map_err(|_| format!("no color number {}", index)),
+        Value::String(text) => color_from_str(text).ok_or(format!("unknown color {}", text)),
+        _ => Err("a color should be a name, #rrggbb or a number".to_string()),
+    }
+}
+
+fn color_from_str(text: &str) -> Option<Color> {
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+    ANSI_16
+        .iter()
+        .find(|(name, _, _)| *name == text)
+        .map(|(_, color, _)| *color)
+}
+
+fn depth_of(color: Color) -> ColorDepth {
+    match color {
+        Color::Rgb { .. } => ColorDepth::TrueColor,
+        Color::AnsiValue(index) if index >= 16 => ColorDepth::Ansi256,
+        _ => ColorDepth::Ansi16,
+    }
+}
+
+// The nearest colour the terminal can show
+fn reduce(color: Color, depth: ColorDepth) -> Color {
+    let Some((r, g, b)) = rgb_of(color) else {
+        return color;
+    };
+    if depth == ColorDepth::Ansi256 {
+        return Color::AnsiValue(ansi_256(r, g, b));
+    }
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)]
+            .iter()
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+            .sum::<i32>()
+    };
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, _, rgb)| distance(*rgb))
+        .map_or(color, |(_, color, _)| *color)
+}
+
+fn rgb_of(color: Color) -> Option<(u8, u8, u8)> {
+    match color {
+        Color::Rgb { r, g, b } => Some((r, g, b)),
+        Color::AnsiValue(index @ 0..=15) => Some(ANSI_16[index as usize].2),
+        // the 6x6x6 cube then 24 greys
+        Color::AnsiValue(index @ 16..=231) => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let i = index - 16;
+            Some((level(i / 36), level(i / 6 % 6), level(i % 6)))
+        }
+        Color::AnsiValue(index) => {
+            let grey = 8 + (index - 232) * 10;
+            Some((grey, grey, grey))
+        }
+        _ => None,
+    }
+}
+
+fn ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            _ => 232 + ((r - 8) / 10).min(23),
+        };
+    }
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+// The 16 colours by name, in the order of their ANSI index, with their usual look
+const ANSI_16: [(&str, Color, (u8, u8, u8)); 16] = [
+    ("black", Color::Black, (0, 0, 0)),
+    ("dark_red", Color::DarkRed, (128, 0, 0)),
+    ("dark_green", Color::DarkGreen, (0, 128, 0)),
+    ("dark_yellow", Color::DarkYellow, (128, 128, 0)),
+    ("dark_blue", Color::DarkBlue, (0, 0, 128)),
+    ("dark_magenta", Color::DarkMagenta, (128, 0, 128)),
+    ("dark_cyan", Color::DarkCyan, (0, 128, 128)),
+    ("grey", Color::Grey, (192, 192, 192)),
+    ("dark_grey", Color::DarkGrey, (128, 128, 128)),
+    ("red", Color::Red, (255, 0, 0)),
+    ("green", Color::Green, (0, 255, 0)),
+    ("yellow", Color::Yellow, (255, 255, 0)),
+    ("blue", Color::Blue, (0, 0, 255)),
+    ("magenta", Color::Magenta, (255, 0, 255)),
+    ("cyan", Color::Cyan, (0, 255, 255)),
+    ("white", Color::White, (255, 255, 255)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, depth: ColorDepth) -> Result<Color, String> {
+        let value: Value = toml::from_str(&format!("c = {}", text)).unwrap();
+        parse_color(&value["c"], depth)
+    }
+
+    #[test]
+    fn color_forms() {
+        let depth = ColorDepth::TrueColor;
+        assert_eq!(parse("\"dark_cyan\"", depth), Ok(Color::DarkCyan));
+        assert_eq!(
+            parse("\"#ff8000\"", depth),
+            Ok(Color::Rgb {
+                r: 255,
+                g: 128,
+                b: 0
+            })
+        );
+        assert_eq!(parse("208", depth), Ok(Color::AnsiValue(208)));
+    }
+
+    #[test]
+    fn bad_colors() {
+        let depth = ColorDepth::TrueColor;
+        assert!(parse("\"purple\"", depth).is_err());
+        assert!(parse("\"#ff80\"", depth).is_err());
+        assert!(parse("\"#gg8000\"", depth).is_err());
+        assert!(parse("256", depth).is_err());
+        assert!(parse("true", depth).is_err());
+        assert!(parse("[]", depth).is_err());
+        // one bad choice spoils the list
+        assert!(parse("[\"#ff8000\", \"purple\"]", depth).is_err());
+    }
+
+    #[test]
+    fn fallbacks() {
+        let choices = "[\"#ff8000\", 208, \"red\"]";
+        assert_eq!(
+            parse(choices, ColorDepth::TrueColor),
+            Ok(Color::Rgb {
+                r: 255,
+                g: 128,
+                b: 0
+            })
+        );
+        assert_eq!(
+            parse(choices, ColorDepth::Ansi256),
+            Ok(Color::AnsiValue(208))
+        );
+        assert_eq!(parse(choices, ColorDepth::Ansi16), Ok(Color::Red));
+
+        // with nothing shallow enough the first is reduced
+        assert_eq!(
+            parse("[\"#ff0000\", 208]", ColorDepth::Ansi16),
+            Ok(Color::Red)
+        );
+    }
+
+    #[test]
+    fn reduce_colors() {
+        let red = Color::Rgb { r: 255, g: 0, b: 0 };
+        assert_eq!(reduce(red, ColorDepth::Ansi256), Color::AnsiValue(196));
+        assert_eq!(reduce(red, ColorDepth::Ansi16), Color::Red);
+
+        let grey = Color::Rgb {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        assert_eq!(reduce(grey, ColorDepth::Ansi256), Color::AnsiValue(244));
+        assert_eq!(reduce(grey, ColorDepth::Ansi16), Color::DarkGrey);
+
+        let dim = Color::Rgb { r: 200, g: 0, b: 0 };
+        assert_eq!(reduce(dim, ColorDepth::Ansi16), Color::Red);
+        assert_eq!(
+            reduce(Color::AnsiValue(196), ColorDepth::Ansi16),
+            Color::Red
+        );
+
+        // named colours are already as shallow as they get
+        assert_eq!(reduce(Color::Blue, ColorDepth::Ansi16), Color::Blue);
+    }
+}